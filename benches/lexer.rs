@@ -0,0 +1,54 @@
+// Lexing time should grow linearly with the input size, so each benchmark should take about four
+// times as long as the one before it
+// Run with `cargo bench --bench lexer`
+
+#![feature(test)]
+
+extern crate blocks;
+extern crate test;
+
+use blocks::token::{Lexer, build_tokens};
+use test::Bencher;
+
+const LINES: &'static str = "?var_addr 100;
+symbol add {
+    set a = 0; set b = 0; set c = + a b; // sum
+    return;
+}
+set x = / * 3 4 ~ 9 2;
+cmp >= x 10;
+ifgoto add;
+raw `10 0 1`;
+";
+
+fn source(size: usize) -> String {
+    LINES.repeat(size / LINES.len())
+}
+
+fn bench_lexer(b: &mut Bencher, size: usize) {
+    let source = source(size);
+    b.bytes = source.len() as u64;
+    b.iter(|| Lexer::new(&source).count());
+}
+
+#[bench]
+fn lex_64k(b: &mut Bencher) {
+    bench_lexer(b, 1 << 16);
+}
+
+#[bench]
+fn lex_256k(b: &mut Bencher) {
+    bench_lexer(b, 1 << 18);
+}
+
+#[bench]
+fn lex_1m(b: &mut Bencher) {
+    bench_lexer(b, 1 << 20);
+}
+
+#[bench]
+fn build_tokens_1m(b: &mut Bencher) {
+    let source = source(1 << 20);
+    b.bytes = source.len() as u64;
+    b.iter(|| build_tokens(source.clone()).len());
+}
@@ -0,0 +1,16 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate blocks;
+extern crate blocks_fuzz;
+
+use blocks::utils::TokenWrapper;
+use blocks_fuzz::Program;
+
+fuzz_target!(|program: Program| {
+    let source = program.to_string();
+
+    if let Ok(tree) = blocks::tree::build_token_tree(source) {
+        let _ = blocks::ir::build_ir(TokenWrapper::Tree(tree), 0);
+    }
+});
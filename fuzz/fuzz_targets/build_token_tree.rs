@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate blocks;
+
+fuzz_target!(|source: &str| {
+    let _ = blocks::tree::build_token_tree(source.to_string());
+});
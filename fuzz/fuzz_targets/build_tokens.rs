@@ -0,0 +1,9 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate blocks;
+
+fuzz_target!(|source: &str| {
+    let _ = blocks::token::build_tokens(source.to_string());
+    let _ = blocks::token::build_tokens_with_trivia(source.to_string());
+});
@@ -0,0 +1,11 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate blocks;
+extern crate blocks_fuzz;
+
+use blocks_fuzz::Program;
+
+fuzz_target!(|program: Program| {
+    let _ = blocks::compile(&program.to_string());
+});
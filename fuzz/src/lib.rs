@@ -0,0 +1,110 @@
+// Structured program generation for the fuzz targets.
+// Arbitrary bytes rarely make it past the lexer and parser, so the IR and compile targets build
+// programs out of statements and expressions instead, which reach the later stages far more often.
+
+#[macro_use]
+extern crate arbitrary;
+
+use std::fmt;
+
+#[derive(Arbitrary, Debug)]
+pub enum Value {
+    Var(u8),
+    Number(i32),
+    Register(u8)
+}
+
+#[derive(Arbitrary, Debug)]
+pub enum Expr {
+    Value(Value),
+    Binary(u8, Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Deref(Box<Expr>),
+    Address(u8)
+}
+
+#[derive(Arbitrary, Debug)]
+pub enum Stmt {
+    Set(Expr, Expr),
+    Compare(u8, Expr, Expr),
+    Goto(u8),
+    IfGoto(u8),
+    Call(u8),
+    Return,
+    Raw(Vec<i32>),
+    Tag(u8, i32),
+    Symbol(u8, Vec<Stmt>)
+}
+
+#[derive(Arbitrary, Debug)]
+pub struct Program(pub Vec<Stmt>);
+
+const OPERATORS: &'static [&'static str] = &["+", "~", "*", "/", "^", "==", ">", "<", ">=", "<="];
+const REGISTERS: &'static [&'static str] = &["$int1", "$int2", "$int3", "$int4", "$flag", "$accum",
+                                              "$error", "$segf", "$segd", "$pcounter"];
+const TAGS: &'static [&'static str] = &["var_addr", "unknown"];
+
+fn name(id: u8) -> String {
+    format!("v{}", id % 8)
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Var(id) => write!(f, "{}", name(id)),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Register(r) => write!(f, "{}", REGISTERS[r as usize % REGISTERS.len()])
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::Value(ref v) => write!(f, "{}", v),
+            Expr::Binary(op, ref a, ref b) => write!(f, "{} {} {}", OPERATORS[op as usize % OPERATORS.len()], a, b),
+            Expr::Not(ref e) => write!(f, "! {}", e),
+            Expr::Deref(ref e) => write!(f, "#{}", e),
+            Expr::Address(id) => write!(f, "@{}", name(id))
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Stmt::Set(ref a, ref b) => writeln!(f, "set {} = {};", a, b),
+            Stmt::Compare(op, ref a, ref b) => {
+                writeln!(f, "cmp {} {} {};", OPERATORS[op as usize % OPERATORS.len()], a, b)
+            },
+            Stmt::Goto(id) => writeln!(f, "goto {};", name(id)),
+            Stmt::IfGoto(id) => writeln!(f, "ifgoto {};", name(id)),
+            Stmt::Call(id) => writeln!(f, "call {};", name(id)),
+            Stmt::Return => writeln!(f, "return;"),
+            Stmt::Raw(ref words) => {
+                let words = words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+                writeln!(f, "raw `{}`;", words.join(" "))
+            },
+            Stmt::Tag(tag, value) => writeln!(f, "?{} = {};", TAGS[tag as usize % TAGS.len()], value),
+            Stmt::Symbol(id, ref body) => {
+                writeln!(f, "symbol {} {{", name(id))?;
+
+                for stmt in body {
+                    write!(f, "{}", stmt)?;
+                }
+
+                writeln!(f, "}}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for stmt in &self.0 {
+            write!(f, "{}", stmt)?;
+        }
+
+        Ok(())
+    }
+}
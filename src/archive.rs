@@ -0,0 +1,134 @@
+// Static archives.
+// An archive bundles many objects into one file, with an index of the symbol blocks each one
+// exports. When linking, a member is only included if it defines a symbol block the program needs,
+// so unused routines of a library don't end up in the program.
+// Variables are not indexed, because every object exports the variables it assigns to (including
+// the compiler's temporaries), so a variable alone never pulls in a member.
+
+use error::*;
+use json::Json;
+use object::{Object, Section, link_objects};
+use token::Token;
+
+use std::collections::{BTreeMap, HashMap};
+
+const ARCHIVE_VERSION: i64 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    pub name: String,
+    pub object: Object
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Archive {
+    pub members: Vec<Member>,
+    // Maps each exported symbol block to the member that defines it
+    index: BTreeMap<String, usize>
+}
+
+impl Archive {
+    // Fails if two members define the same symbol block
+    pub fn new(members: Vec<Member>) -> Result<Archive, BlocksError> {
+        let mut index = BTreeMap::new();
+
+        for (i, member) in members.iter().enumerate() {
+            for export in member.object.exports.iter().filter(|e| e.section == Section::Text) {
+                if index.insert(export.name.clone(), i).is_some() {
+                    return Err(BlocksError::new(ErrorKind::DuplicateSymbol, Token::Other(export.name.clone())));
+                }
+            }
+        }
+
+        Ok(Archive {
+            members: members,
+            index: index
+        })
+    }
+
+    // Returns the member that defines a symbol block
+    pub fn find(&self, symbol: &str) -> Option<&Member> {
+        self.index.get(symbol).map(|&i| &self.members[i])
+    }
+
+    // The exported symbol blocks, sorted, with the names of the members that define them
+    pub fn index(&self) -> Vec<(&str, &str)> {
+        self.index.iter().map(|(s, &i)| (s as &str, &self.members[i].name as &str)).collect()
+    }
+
+    pub fn to_json(&self) -> Json {
+        let index = self.index.iter().map(|(name, &member)| Json::object(vec![
+            ("name", Json::string(name)),
+            ("member", Json::Number(member as i64))
+        ])).collect();
+
+        let members = self.members.iter().map(|m| Json::object(vec![
+            ("name", Json::string(&m.name)),
+            ("object", m.object.to_json())
+        ])).collect();
+
+        Json::object(vec![
+            ("archive_version", Json::Number(ARCHIVE_VERSION)),
+            ("index", Json::Array(index)),
+            ("members", Json::Array(members))
+        ])
+    }
+
+    // The index is rebuilt from the members, so a stored index that disagrees with them is an error
+    pub fn from_json(json: &Json) -> Result<Archive, String> {
+        if json.get("archive_version").as_i64() != Some(ARCHIVE_VERSION) {
+            return Err(format!("Not an archive, or one made by a different version (expected version {})",
+                               ARCHIVE_VERSION));
+        }
+
+        let members = json.get("members").as_array().ok_or("Invalid archive: bad `members`".to_string())?;
+        let members = members.iter().map(|m| Ok(Member {
+            name: m.get("name").as_str().ok_or("Invalid archive: bad member name".to_string())?.to_string(),
+            object: Object::from_json(m.get("object"))?
+        })).collect::<Result<Vec<_>, String>>()?;
+
+        let archive = Archive::new(members).map_err(|e| format!("Invalid archive: {}", e.message()))?;
+
+        let index = json.get("index").as_array().ok_or("Invalid archive: bad `index`".to_string())?;
+        let index = index.iter().map(|e| match (e.get("name").as_str(), e.get("member").as_i64()) {
+            (Some(name), Some(member)) => Ok((name.to_string(), member as usize)),
+            _ => Err("Invalid archive: bad `index`".to_string())
+        }).collect::<Result<BTreeMap<_, _>, String>>()?;
+
+        if index != archive.index {
+            return Err("Invalid archive: the index does not match the members".to_string());
+        }
+
+        Ok(archive)
+    }
+}
+
+// Links objects with the members of archives that define the symbol blocks they need
+pub fn link_with_archives(objects: &[Object], archives: &[Archive]) -> Result<(Vec<i32>, HashMap<String, i32>), BlocksError> {
+    link_objects(&select_members(objects, archives))
+}
+
+// Returns the objects followed by the archive members they need, in the order they were needed
+// Archives are searched in order, and a member may need symbols from an earlier archive
+pub fn select_members(objects: &[Object], archives: &[Archive]) -> Vec<Object> {
+    let mut linked = objects.to_vec();
+    let mut next = 0;
+
+    // Every object is checked once for imports, including the members added along the way
+    while next < linked.len() {
+        let imports = linked[next].imports.clone();
+        next += 1;
+
+        for import in imports {
+            if linked.iter().any(|o| o.export(&import).is_some()) {
+                continue;
+            }
+
+            if let Some(member) = archives.iter().filter_map(|a| a.find(&import)).next() {
+                linked.push(member.object.clone());
+            }
+        }
+    }
+
+    linked
+}
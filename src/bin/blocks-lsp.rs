@@ -0,0 +1,16 @@
+// Language server for blocks, speaking LSP over stdio
+
+extern crate blocks;
+
+use std::io::{self, Write};
+use std::process;
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+
+    if let Err(e) = blocks::lsp::run(&mut stdin.lock(), &mut stdout.lock()) {
+        writeln!(io::stderr(), "blocks-lsp: {}", e).unwrap();
+        process::exit(1);
+    }
+}
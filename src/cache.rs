@@ -0,0 +1,374 @@
+// Caches the IR of each file of a multi-file project, so that rebuilding the project only lexes,
+// parses and lowers the files that changed.
+// Entries are keyed by a hash of the file contents and stored as JSON in a cache directory. A
+// missing, stale or unreadable entry is rebuilt, so deleting the directory is always safe.
+
+use compile::{build_module_at, link_with_report};
+use compile_utils::locate_error;
+use error::BlocksError;
+use ir::{Ir, IrResult, RemovedBlock};
+use json::Json;
+use layout::Layout;
+use opt::OptLevel;
+use token::{REGISTERS, register_name};
+use utils::{Address, Register};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+// Bump this when the IR or the serialized format changes, so old entries are ignored
+const CACHE_VERSION: u32 = 5;
+
+// The output of every stage before compile_ir for one file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Module {
+    pub ir: Vec<Ir>,
+    pub blocks: BTreeMap<String, Vec<Ir>>
+}
+
+impl Module {
+    pub fn from_ir(ir: IrResult) -> Module {
+        Module {
+            ir: ir.ir,
+            blocks: ir.blocks
+        }
+    }
+
+    // Names of the symbol blocks defined by the module, sorted
+    pub fn symbols(&self) -> Vec<&str> {
+        self.blocks.keys().map(|s| s as &str).collect()
+    }
+
+    // Names of the variables and symbols the module refers to, sorted
+    pub fn references(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+
+        for item in self.ir.iter().chain(self.blocks.values().flat_map(|b| b.iter())) {
+            for addr in item.addresses() {
+                if let Address::Variable(ref name) = *addr {
+                    names.push(name as &str);
+                }
+            }
+        }
+
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+// Joins modules into one program, in order, as if their sources were concatenated
+pub fn link_modules(modules: Vec<Module>) -> IrResult {
+    let mut ir = Vec::new();
+    let mut blocks = BTreeMap::new();
+
+    for module in modules {
+        ir.extend(module.ir);
+        blocks.extend(module.blocks);
+    }
+
+    IrResult {
+        ir: ir,
+        blocks: blocks,
+        address: Address::Static(-1),
+        var_addr: Address::Static(-1),
+        register: None,
+        deref: false,
+        math: false
+    }
+}
+
+// A compiled program and the addresses of its variables
+pub type Compiled = (Vec<i32>, HashMap<String, i32>);
+
+// Compiles several files as one program, using the cache for files that did not change
+// On error, also returns the index of the file the error was found in
+pub fn compile_project(sources: &[&str], cache: Option<&mut Cache>, layout: &Layout)
+    -> Result<Compiled, (usize, BlocksError)> {
+
+    compile_project_at(sources, cache, layout, OptLevel::default())
+}
+
+// Like compile_project, but at an optimization level
+pub fn compile_project_at(sources: &[&str], cache: Option<&mut Cache>, layout: &Layout, level: OptLevel)
+    -> Result<Compiled, (usize, BlocksError)> {
+
+    compile_project_with_report(sources, cache, layout, level).map(|(compiled, _)| compiled)
+}
+
+// Like compile_project_at, but also returns the symbol blocks removed because nothing could reach
+// them
+pub fn compile_project_with_report(sources: &[&str], mut cache: Option<&mut Cache>, layout: &Layout, level: OptLevel)
+    -> Result<(Compiled, Vec<RemovedBlock>), (usize, BlocksError)> {
+
+    let mut modules = Vec::new();
+
+    for (i, source) in sources.iter().enumerate() {
+        let module = match cache {
+            Some(ref mut c) => c.module_at(source, level),
+            None => build_module_at(source, level).map(Module::from_ir)
+        };
+
+        modules.push(module.map_err(|e| (i, e))?);
+    }
+
+    let linked = link_with_report(link_modules(modules), layout, level).map(|(image, vars, removed)| ((image.code, vars), removed));
+
+    linked.map_err(|e| {
+        // Errors found while linking are reported in the first file that mentions the name
+        for (i, source) in sources.iter().enumerate() {
+            let located = locate_error(e.clone(), source);
+
+            if located.span().is_some() {
+                return (i, located);
+            }
+        }
+
+        (0, e)
+    })
+}
+
+pub struct Cache {
+    dir: PathBuf,
+    // Number of modules loaded from the cache and rebuilt since the cache was created
+    pub hits: usize,
+    pub misses: usize
+}
+
+impl Cache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Cache {
+        Cache {
+            dir: dir.into(),
+            hits: 0,
+            misses: 0
+        }
+    }
+
+    fn entry_path(&self, hash: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.json", hash))
+    }
+
+    // Returns the module for a file, building and storing it if it is not cached
+    pub fn module(&mut self, source: &str) -> Result<Module, BlocksError> {
+        self.module_at(source, OptLevel::default())
+    }
+
+    // Like module, but built at an optimization level
+    pub fn module_at(&mut self, source: &str, level: OptLevel) -> Result<Module, BlocksError> {
+        // Modules built at the default level are keyed by the file alone
+        let hash = if level == OptLevel::default() {
+            content_hash(source)
+        } else {
+            content_hash(&format!("{}\0{}", level.name(), source))
+        };
+
+        let path = self.entry_path(hash);
+
+        let cached = File::open(&path).ok().and_then(|mut f| {
+            let mut text = String::new();
+            f.read_to_string(&mut text).ok().map(|_| text)
+        });
+
+        if let Some(module) = cached.and_then(|text| Json::parse(&text).ok())
+                                    .and_then(|json| module_from_json(&json, hash)) {
+            self.hits += 1;
+            return Ok(module);
+        }
+
+        self.misses += 1;
+
+        let module = Module::from_ir(build_module_at(source, level)?);
+
+        // The cache only saves time, so failing to write an entry is not an error
+        let _ = self.store(&path, &module_to_json(&module, hash));
+
+        Ok(module)
+    }
+
+    fn store(&self, path: &PathBuf, json: &Json) -> ::std::io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        // Write to a temporary file first so that an interrupted build never leaves half an entry
+        let temp = path.with_extension("tmp");
+        File::create(&temp)?.write_all(json.to_string().as_bytes())?;
+        fs::rename(&temp, path)
+    }
+}
+
+// FNV-1a, which unlike the standard library's hasher is the same on every platform and release
+pub fn content_hash(source: &str) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    let key = format!("{}\0{}\0", CACHE_VERSION, env!("CARGO_PKG_VERSION"));
+
+    for byte in key.bytes().chain(source.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+fn names_to_json(names: Vec<&str>) -> Json {
+    Json::Array(names.into_iter().map(Json::string).collect())
+}
+
+pub fn module_to_json(module: &Module, hash: u64) -> Json {
+    let blocks = module.symbols().into_iter()
+                                 .map(|name| (name.to_string(), ir_list_to_json(&module.blocks[name])))
+                                 .collect::<Vec<_>>();
+
+    Json::object(vec![
+        ("version", Json::Number(CACHE_VERSION as i64)),
+        ("hash", Json::String(format!("{:016x}", hash))),
+        ("symbols", names_to_json(module.symbols())),
+        ("references", names_to_json(module.references())),
+        ("main", ir_list_to_json(&module.ir)),
+        ("blocks", Json::Object(blocks))
+    ])
+}
+
+// Returns None if the entry is malformed or was written for different contents or by a different
+// version of the cache
+pub fn module_from_json(json: &Json, hash: u64) -> Option<Module> {
+    if json.get("version").as_i64() != Some(CACHE_VERSION as i64) ||
+       json.get("hash").as_str() != Some(&format!("{:016x}", hash) as &str) {
+        return None;
+    }
+
+    let mut blocks = BTreeMap::new();
+
+    match *json.get("blocks") {
+        Json::Object(ref fields) => for (name, ir) in fields {
+            blocks.insert(name.clone(), ir_list_from_json(ir)?);
+        },
+        _ => return None
+    }
+
+    let module = Module {
+        ir: ir_list_from_json(json.get("main"))?,
+        blocks: blocks
+    };
+
+    // The symbol table is redundant with the IR, so a mismatch means the entry is corrupt
+    let symbols = json.get("symbols").as_array()?.iter().map(|s| s.as_str()).collect::<Option<Vec<_>>>()?;
+
+    if symbols != module.symbols() {
+        return None;
+    }
+
+    Some(module)
+}
+
+fn ir_list_to_json(ir: &[Ir]) -> Json {
+    Json::Array(ir.iter().map(ir_to_json).collect())
+}
+
+fn ir_list_from_json(json: &Json) -> Option<Vec<Ir>> {
+    json.as_array()?.iter().map(ir_from_json).collect()
+}
+
+fn address_to_json(addr: &Address) -> Json {
+    match *addr {
+        Address::Static(n) => Json::Number(n as i64),
+        Address::Variable(ref name) => Json::string(name)
+    }
+}
+
+fn address_from_json(json: &Json) -> Option<Address> {
+    match *json {
+        Json::Number(n) => Some(Address::Static(n as i32)),
+        Json::String(ref name) => Some(Address::Variable(name.clone())),
+        _ => None
+    }
+}
+
+fn register_from_json(json: &Json) -> Option<Register> {
+    let name = json.as_str()?;
+    REGISTERS.iter().find(|r| r.0 == name).map(|r| r.1.clone())
+}
+
+// Each instruction is an array of its name followed by its operands
+pub fn ir_to_json(item: &Ir) -> Json {
+    let (name, args) = match *item {
+        Ir::Write(ref a, ref b) => ("Write", vec![address_to_json(a), address_to_json(b)]),
+        Ir::Copy(ref a, ref b) => ("Copy", vec![address_to_json(a), address_to_json(b)]),
+        Ir::IndirWrite(ref a, ref b) => ("IndirWrite", vec![address_to_json(a), address_to_json(b)]),
+        Ir::IndirCopy(ref a, ref b) => ("IndirCopy", vec![address_to_json(a), address_to_json(b)]),
+        Ir::IndirCopy3(ref a, ref b) => ("IndirCopy3", vec![address_to_json(a), address_to_json(b)]),
+        Ir::RegWrite(ref r, ref a) => ("RegWrite", vec![Json::string(register_name(r)), address_to_json(a)]),
+        Ir::RegCopy(ref r, ref a) => ("RegCopy", vec![Json::string(register_name(r)), address_to_json(a)]),
+        Ir::RegMem(ref r, ref a) => ("RegMem", vec![Json::string(register_name(r)), address_to_json(a)]),
+        Ir::RegReg(ref a, ref b) => ("RegReg", vec![Json::string(register_name(a)), Json::string(register_name(b))]),
+        Ir::Add => ("Add", vec![]),
+        Ir::Sub => ("Sub", vec![]),
+        Ir::Mul => ("Mul", vec![]),
+        Ir::Div => ("Div", vec![]),
+        Ir::Equals => ("Equals", vec![]),
+        Ir::Less => ("Less", vec![]),
+        Ir::Greater => ("Greater", vec![]),
+        Ir::LessEqual => ("LessEqual", vec![]),
+        Ir::GreaterEqual => ("GreaterEqual", vec![]),
+        Ir::Or => ("Or", vec![]),
+        Ir::And => ("And", vec![]),
+        Ir::Not => ("Not", vec![]),
+        Ir::Xor => ("Xor", vec![]),
+        Ir::Branch(ref a) => ("Branch", vec![address_to_json(a)]),
+        Ir::CondBranch(ref a) => ("CondBranch", vec![address_to_json(a)]),
+        Ir::IndirBranch(ref a) => ("IndirBranch", vec![address_to_json(a)]),
+        Ir::Call(ref a) => ("Call", vec![address_to_json(a)]),
+        Ir::Tag(ref name, ref value) => ("Tag", vec![Json::string(name), Json::string(value)]),
+        Ir::Return => ("Return", vec![]),
+        Ir::Raw(ref code) => ("Raw", vec![Json::Array(code.iter().map(|&x| Json::Number(x as i64)).collect())])
+    };
+
+    let mut items = vec![Json::string(name)];
+    items.extend(args);
+
+    Json::Array(items)
+}
+
+pub fn ir_from_json(json: &Json) -> Option<Ir> {
+    let items = json.as_array()?;
+    let name = items.first()?.as_str()?;
+    let args = &items[1..];
+
+    let arg = |i: usize| args.get(i).and_then(address_from_json);
+    let reg = || args.first().and_then(register_from_json);
+
+    Some(match name {
+        "Write" => Ir::Write(arg(0)?, arg(1)?),
+        "Copy" => Ir::Copy(arg(0)?, arg(1)?),
+        "IndirWrite" => Ir::IndirWrite(arg(0)?, arg(1)?),
+        "IndirCopy" => Ir::IndirCopy(arg(0)?, arg(1)?),
+        "IndirCopy3" => Ir::IndirCopy3(arg(0)?, arg(1)?),
+        "RegWrite" => Ir::RegWrite(reg()?, arg(1)?),
+        "RegCopy" => Ir::RegCopy(reg()?, arg(1)?),
+        "RegMem" => Ir::RegMem(reg()?, arg(1)?),
+        "RegReg" => Ir::RegReg(reg()?, args.get(1).and_then(register_from_json)?),
+        "Add" => Ir::Add,
+        "Sub" => Ir::Sub,
+        "Mul" => Ir::Mul,
+        "Div" => Ir::Div,
+        "Equals" => Ir::Equals,
+        "Less" => Ir::Less,
+        "Greater" => Ir::Greater,
+        "LessEqual" => Ir::LessEqual,
+        "GreaterEqual" => Ir::GreaterEqual,
+        "Or" => Ir::Or,
+        "And" => Ir::And,
+        "Not" => Ir::Not,
+        "Xor" => Ir::Xor,
+        "Branch" => Ir::Branch(arg(0)?),
+        "CondBranch" => Ir::CondBranch(arg(0)?),
+        "IndirBranch" => Ir::IndirBranch(arg(0)?),
+        "Call" => Ir::Call(arg(0)?),
+        "Tag" => Ir::Tag(args.first()?.as_str()?.to_string(), args.get(1)?.as_str()?.to_string()),
+        "Return" => Ir::Return,
+        "Raw" => Ir::Raw(args.first()?.as_array()?.iter()
+                                              .map(|x| x.as_i64().map(|x| x as i32))
+                                              .collect::<Option<Vec<_>>>()?),
+        _ => return None
+    })
+}
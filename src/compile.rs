@@ -13,8 +13,11 @@ use error::*;
 use utils::*;
 use compile_utils::*;
 use ir::*;
+use ssa::{build_ssa, lower};
+use layout::{Image, Layout};
+use opt::{OptLevel, file_levels, parse_level_tag};
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 const SEGMENT_SETUP: &'static str = "
 12 8 9
@@ -35,48 +38,165 @@ const CLEANUP: &'static [i32] = &[
     35
 ];
 
+// Tags understood by compile_ir
+pub const TAGS: &'static [&'static str] = &["var_addr", "opt", "volatile", "inline"];
+
 pub fn compile(prog: &str) -> Result<Vec<i32>, BlocksError> {
+    let ir = build_module(prog)?;
+
+    finish(ir, prog).map(|r| r.0)
+}
+
+// Like compile, but also returns the addresses of variables, relative to the data segment
+pub fn compile_with_vars(prog: &str) -> Result<(Vec<i32>, HashMap<String, i32>), BlocksError> {
+    compile_at(prog, OptLevel::default())
+}
+
+// Like compile_with_vars, but at an optimization level
+pub fn compile_at(prog: &str, level: OptLevel) -> Result<(Vec<i32>, HashMap<String, i32>), BlocksError> {
+    link_at(build_module_at(prog, level)?, &Layout::default(), level).map(|(image, vars)| (image.code, vars))
+                                                                     .map_err(|e| locate_error(e, prog))
+}
+
+// Like compile_with_vars, but places the sections of the program according to a layout
+pub fn compile_with_layout(prog: &str, layout: &Layout) -> Result<(Image, HashMap<String, i32>), BlocksError> {
+    link_with_layout(build_module(prog)?, layout).map_err(|e| locate_error(e, prog))
+}
+
+// Runs every stage before compile_ir on one file
+// The result only depends on the file, so it can be cached and later linked with other files
+pub fn build_module(prog: &str) -> Result<IrResult, BlocksError> {
+    build_module_at(prog, OptLevel::default())
+}
+
+// Like build_module, but at an optimization level, which `?opt` tags in the file override
+// Code at a level other than `level` is surrounded by `opt` tags, so link_at can tell its level
+pub fn build_module_at(prog: &str, level: OptLevel) -> Result<IrResult, BlocksError> {
     let tree = build_token_tree(prog.to_string())?;
-    let mut ir = build_ir(TokenWrapper::Tree(tree), 0)?;
+    let (main, levels) = file_levels(&tree, level).map_err(|e| locate_error(e, prog))?;
+    let level_of = |name: Option<&str>| name.and_then(|n| levels.get(n)).cloned().unwrap_or(main);
+
+    let generate = |ssa: bool| if ssa {
+        build_ssa(tree.clone()).and_then(|program| lower(&program))
+    } else {
+        build_ir(TokenWrapper::Tree(tree.clone()), 0)
+    };
+
+    let mut ir = generate(main.passes().ssa).map_err(|e| locate_error(e, prog))?;
+
+    // Symbol blocks at levels that generate the IR the other way are taken from the other generator
+    if levels.values().any(|l| l.passes().ssa != main.passes().ssa) {
+        let other = generate(!main.passes().ssa).map_err(|e| locate_error(e, prog))?;
+
+        for (name, block) in other.blocks {
+            if level_of(Some(&name)).passes().ssa != main.passes().ssa {
+                ir.blocks.insert(name, block);
+            }
+        }
+    }
 
-    remove_dead_code(&mut ir.ir);
+    // Only the tags that mark code at other levels are kept
+    let is_level = |item: &Ir| matches!(*item, Ir::Tag(ref key, _) if key == "opt");
+    let tag = |level: OptLevel| Ir::Tag("opt".to_string(), level.name().to_string());
 
-    println!("IR:\nmain:");
+    ir.ir.retain(|item| !is_level(item));
 
-    for i in &ir.ir {
-        println!("{:?}", i);
+    if main != level {
+        ir.ir.insert(0, tag(main));
+        ir.ir.push(tag(level));
     }
 
-    for block in ir.blocks.keys() {
-        println!("\n{:?}:", block);
+    for (name, block) in ir.blocks.iter_mut() {
+        block.retain(|item| !is_level(item));
 
-        for i in ir.blocks.get(block).unwrap() {
-            println!("{:?}", i);
+        if level_of(Some(name)) != level {
+            block.insert(0, tag(level_of(Some(name))));
         }
     }
 
-    println!("\n");
+    Ok(ir)
+}
+
+fn finish(ir: IrResult, prog: &str) -> Result<(Vec<i32>, HashMap<String, i32>), BlocksError> {
+    link(ir).map_err(|e| locate_error(e, prog))
+}
+
+// Compiles the IR of a whole program, without locating errors in the source
+pub fn link(ir: IrResult) -> Result<(Vec<i32>, HashMap<String, i32>), BlocksError> {
+    link_with_layout(ir, &Layout::default()).map(|(image, vars)| (image.code, vars))
+}
+
+pub fn link_with_layout(ir: IrResult, layout: &Layout) -> Result<(Image, HashMap<String, i32>), BlocksError> {
+    link_at(ir, layout, OptLevel::default())
+}
+
+// Like link_with_layout, but for IR built at an optimization level
+pub fn link_at(ir: IrResult, layout: &Layout, level: OptLevel) -> Result<(Image, HashMap<String, i32>), BlocksError> {
+    link_with_report(ir, layout, level).map(|(image, vars, _)| (image, vars))
+}
+
+// A linked program, the addresses of its variables and the symbol blocks removed because nothing
+// could reach them
+pub type Linked = (Image, HashMap<String, i32>, Vec<RemovedBlock>);
+
+// Like link_at, but also returns the removed symbol blocks
+// Registers are allocated here rather than for each file, so that linking several files gives the
+// same program as compiling them as one
+pub fn link_with_report(mut ir: IrResult, layout: &Layout, level: OptLevel) -> Result<Linked, BlocksError> {
+    allocate_registers_at(&mut ir, level);
+    inline(&mut ir, level);
+    let removed = remove_dead_blocks(&mut ir, level);
+    peephole(&mut ir, level);
+
+    let blocks = ir.blocks.keys().cloned().collect::<Vec<_>>();
+    let mut vars = HashMap::new();
+
+    let (compiled, data_section_size, symbol_section_size) = compile_ir(ir, &mut vars, &mut 0, &mut 0)?;
+
+    // Symbol blocks share the table with variables
+    for name in blocks {
+        vars.remove(&name);
+    }
+
+    let (text, main) = compiled.split_at(symbol_section_size);
+
+    Ok((layout.build(text, main, data_section_size)?, vars, removed))
+}
+
+pub fn setup_size() -> usize {
+    SEGMENT_SETUP.split_whitespace().count()
+}
+
+// The code that sets up the segment registers and jumps to the main code
+// It must be placed at address 0, and the offsets are from address 0 to the data segment, from the
+// data segment to the flow segment, and from the flow segment to the main code
+pub fn segment_setup(data_offset: i32, flow_offset: i32, main_offset: i32) -> Vec<i32> {
+    SEGMENT_SETUP.to_string()
+                 .replace("$0", &format!("{}", data_offset))
+                 .replace("$1", &format!("{}", flow_offset))
+                 .replace("$2", &format!("{}", main_offset))
+                 .split_whitespace().map(|x| x.parse().unwrap()).collect()
+}
+
+pub fn cleanup() -> &'static [i32] {
+    CLEANUP
+}
 
-    let (mut compiled, data_section_size, symbol_section_size) = compile_ir(ir, &mut HashMap::new(), &mut 0, &mut 0)?;
-    
+// Surrounds the output of compile_ir with the data section, the segment setup and the cleanup code
+pub fn add_segments(mut compiled: Vec<i32>, data_section_size: usize, symbol_section_size: usize) -> Vec<i32> {
     for _ in 0..data_section_size {
         compiled.insert(0, 0);
     }
 
-    let setup_size = SEGMENT_SETUP.split_whitespace().collect::<Vec<_>>().len(); 
-    let setup = SEGMENT_SETUP.to_string()
-                             .replace("$0", &format!("{}", setup_size))
-                             .replace("$1", &format!("{}", data_section_size))
-                             .replace("$2", &format!("{}", symbol_section_size))
-                             .split_whitespace().map(|x| x.parse().unwrap()).collect::<Vec<_>>();
-    
+    let setup = segment_setup(setup_size() as i32, data_section_size as i32, symbol_section_size as i32);
+
     for x in setup.iter().rev() {
         compiled.insert(0, *x);
     }
 
     compiled.extend_from_slice(CLEANUP);
 
-    Ok(compiled)
+    compiled
 }
 
 pub fn compile_ir(ir: IrResult, vars: &mut HashMap<String, i32>,
@@ -84,12 +204,19 @@ pub fn compile_ir(ir: IrResult, vars: &mut HashMap<String, i32>,
 
     let mut result = Vec::new();
 
+    // Symbol blocks are placed in the order of their names, so the same program always compiles to
+    // the same code, and before any is compiled, so they can refer to each other
+    let mut addr = *symbol_addr;
+
     for (key, value) in ir.blocks.iter() {
-        vars.insert(key.clone(), *symbol_addr);
+        vars.insert(key.clone(), addr);
+        addr += get_code_size(value) as i32;
+    }
 
+    for value in ir.blocks.values() {
         let ir = IrResult {
             ir: value.clone(),
-            blocks: HashMap::new(),
+            blocks: BTreeMap::new(),
             address: Address::Static(-1),
             var_addr: Address::Static(-1),
             register: None,
@@ -105,107 +232,6 @@ pub fn compile_ir(ir: IrResult, vars: &mut HashMap<String, i32>,
 
     for item in ir.ir {
         match item {
-            Ir::Write(addr_a, data) => {
-                let addr_a = get_var_or_new(addr_a, vars, var_addr);
-                let data = get_static_addr(data);
-                result.extend_from_slice(&[0, addr_a, data]);
-            },
-            Ir::Copy(addr_a, addr_b) => {
-                let addr_a = get_var_or_new(addr_a, vars, var_addr);
-                let addr_b = get_addr(addr_b, vars)?;
-                result.extend_from_slice(&[1, addr_a, addr_b]);
-            },
-            Ir::IndirWrite(addr_a, data) => {
-                let addr_a = get_var_or_new(addr_a, vars, var_addr);
-                let data = get_static_addr(data);
-                result.extend_from_slice(&[2, addr_a, data]);
-            },
-            Ir::IndirCopy(addr_a, addr_b) => {
-                let addr_a = get_var_or_new(addr_a, vars, var_addr);
-                let addr_b = get_addr(addr_b, vars)?;
-                result.extend_from_slice(&[3, addr_a, addr_b]);
-            },
-            Ir::IndirCopy3(addr_a, addr_b) => {
-                let addr_a = get_var_or_new(addr_a, vars, var_addr);
-                let addr_b = get_addr(addr_b, vars)?;
-                result.extend_from_slice(&[5, addr_a, addr_b]);
-            },
-            Ir::RegWrite(reg, data) => {
-                let reg = reg as i32;
-                let data = get_static_addr(data);
-                result.extend_from_slice(&[10, reg, data]);
-            },
-            Ir::RegCopy(reg, addr) => {
-                let reg = reg as i32;
-                let addr = get_var_or_new(addr, vars, var_addr);
-                result.extend_from_slice(&[11, reg, addr]);
-            },
-            Ir::RegMem(reg, addr) => {
-                let reg = reg as i32;
-                let addr = get_var_or_new(addr, vars, var_addr);
-                result.extend_from_slice(&[13, addr, reg]);
-            },
-            Ir::Add => {
-                result.extend_from_slice(&[16, 0, 1]);
-            },
-            Ir::Sub => {
-                result.extend_from_slice(&[17, 0, 1]);
-            },
-            Ir::Mul => {
-                result.extend_from_slice(&[18, 0, 1]);
-            },
-            Ir::Div => {
-                result.extend_from_slice(&[19, 0, 1]);
-            },
-            Ir::Equals => {
-                result.extend_from_slice(&[20, 0, 1]);
-            },
-            Ir::Less => {
-                result.extend_from_slice(&[21, 0, 1]);
-            },
-            Ir::Greater => {
-                result.extend_from_slice(&[22, 0, 1]);
-            },
-            Ir::LessEqual => {
-                result.extend_from_slice(&[23, 0, 1]);
-            },
-            Ir::GreaterEqual => {
-                result.extend_from_slice(&[24, 0, 1]);
-            },
-            Ir::Or => {
-                result.extend_from_slice(&[25, 0, 1]);
-            },
-            Ir::And => {
-                result.extend_from_slice(&[26, 0, 1]);
-            },
-            Ir::Not => {
-                result.extend_from_slice(&[27, 0]);
-            },
-            Ir::Xor => {
-                result.extend_from_slice(&[28, 0, 1]);
-            },
-            Ir::Branch(addr) => {
-                let addr = get_addr(addr, vars)?;
-                result.extend_from_slice(&[29, addr]);
-            },
-            Ir::CondBranch(addr) => {
-                let addr = get_addr(addr, vars)?;
-                result.extend_from_slice(&[30, addr]);
-            },
-            Ir::IndirBranch(addr) => {
-                let addr = get_addr(addr, vars)?;
-                result.extend_from_slice(&[32, addr]);
-            },
-            Ir::Call(addr) => {
-                let addr = get_addr(addr, vars)?;
-                result.extend_from_slice(&[33, addr]);
-            },
-            Ir::Return => {
-                result.push(35);
-            },
-            Ir::Raw(raw) => {
-                result.extend_from_slice(&raw);
-            },
             Ir::Tag(name, value) => {
                 match &name as &_ {
                     "var_addr" => *var_addr = if let Ok(v) = value.parse() {
@@ -214,8 +240,25 @@ pub fn compile_ir(ir: IrResult, vars: &mut HashMap<String, i32>,
                         return Err(BlocksError::new(ErrorKind::TagError,
                                                     Token::Other(format!("var_addr must be a number (found `{}`)", value))));
                     },
+                    "opt" => {
+                        parse_level_tag(&value)?;
+                    },
+                    "inline" => {
+                        parse_inline_tag(&value)?;
+                    },
+                    // Only used by optimizations
+                    "volatile" => {},
                     _ => return Err(BlocksError::new(ErrorKind::UnknownTag, Token::Other(name)))
                 }
+            },
+            item => for operand in encode_ir(item) {
+                result.push(match operand {
+                    Operand::Word(word) => word,
+                    Operand::Dest(addr) => get_var_or_new(addr, vars, var_addr),
+                    // A temp may be read in a symbol block compiled before the code that writes it
+                    Operand::Source(ref addr) if addr.is_temp() => get_var_or_new(addr.clone(), vars, var_addr),
+                    Operand::Source(addr) => get_addr(addr, vars)?
+                });
             }
         }
     }
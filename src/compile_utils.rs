@@ -1,4 +1,4 @@
-use token::Token;
+use token::{Token, build_tokens_spanned};
 use error::*;
 use ir::Ir;
 use utils::Address;
@@ -31,20 +31,89 @@ pub fn get_addr(addr: Address, vars: &HashMap<String, i32>) -> Result<i32, Block
     }
 }
 
-pub fn get_static_addr(addr: Address) -> i32 {
-    if let Address::Static(addr) = addr {
-        addr
-    } else {
-        panic!("Address was not Static");
+// A word of machine code, before the addresses of variables and symbols are known
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Word(i32),
+    // Declares the variable if it does not exist yet
+    Dest(Address),
+    // Must refer to a declared variable or a symbol
+    Source(Address)
+}
+
+// Encodes an instruction as machine code
+// Tags produce no code, so they must be handled by the caller
+pub fn encode_ir(ir: Ir) -> Vec<Operand> {
+    use self::Operand::*;
+
+    let arithmetic = |opcode| vec![Word(opcode), Word(0), Word(1)];
+
+    match ir {
+        Ir::Write(a, b) => vec![Word(0), Dest(a), Source(b)],
+        Ir::Copy(a, b) => vec![Word(1), Dest(a), Source(b)],
+        Ir::IndirWrite(a, b) => vec![Word(2), Dest(a), Source(b)],
+        Ir::IndirCopy(a, b) => vec![Word(3), Dest(a), Source(b)],
+        Ir::IndirCopy3(a, b) => vec![Word(5), Dest(a), Source(b)],
+        Ir::RegWrite(reg, data) => vec![Word(10), Word(reg as i32), Source(data)],
+        Ir::RegCopy(reg, addr) => vec![Word(11), Word(reg as i32), Dest(addr)],
+        Ir::RegMem(reg, addr) => vec![Word(13), Dest(addr), Word(reg as i32)],
+        Ir::RegReg(a, b) => vec![Word(12), Word(a as i32), Word(b as i32)],
+        Ir::Add => arithmetic(16),
+        Ir::Sub => arithmetic(17),
+        Ir::Mul => arithmetic(18),
+        Ir::Div => arithmetic(19),
+        Ir::Equals => arithmetic(20),
+        Ir::Less => arithmetic(21),
+        Ir::Greater => arithmetic(22),
+        Ir::LessEqual => arithmetic(23),
+        Ir::GreaterEqual => arithmetic(24),
+        Ir::Or => arithmetic(25),
+        Ir::And => arithmetic(26),
+        Ir::Not => vec![Word(27), Word(0)],
+        Ir::Xor => arithmetic(28),
+        Ir::Branch(addr) => vec![Word(29), Source(addr)],
+        Ir::CondBranch(addr) => vec![Word(30), Source(addr)],
+        Ir::IndirBranch(addr) => vec![Word(32), Source(addr)],
+        Ir::Call(addr) => vec![Word(33), Source(addr)],
+        Ir::Return => vec![Word(35)],
+        Ir::Raw(raw) => raw.into_iter().map(Word).collect(),
+        Ir::Tag(..) => Vec::new()
+    }
+}
+
+// Number of words of machine code compile_ir emits for an instruction
+pub fn get_ir_size(ir: &Ir) -> usize {
+    match *ir {
+        Ir::Branch(_) | Ir::CondBranch(_) | Ir::IndirBranch(_) | Ir::Call(_) | Ir::Not => 2,
+        Ir::Return => 1,
+        Ir::Raw(ref raw) => raw.len(),
+        Ir::Tag(..) => 0,
+        _ => 3
     }
 }
 
 pub fn get_code_size(ir: &[Ir]) -> usize {
-    ir.iter().fold(0, |accum, x| {
-        accum + match *x {
-            Ir::Branch(_) | Ir::CondBranch(_) | Ir::IndirBranch(_) | Ir::Call(_) | Ir::Not => 2,
-            Ir::Return => 1,
-            _ => 3
-        }
-    })
+    ir.iter().fold(0, |accum, x| accum + get_ir_size(x))
+}
+
+// Errors found after parsing don't know where in the source they came from, so this guesses the
+// location from the first use of the identifier the error refers to
+pub fn locate_error(err: BlocksError, prog: &str) -> BlocksError {
+    if err.span().is_some() {
+        return err;
+    }
+
+    let ident = match *err.extra() {
+        Token::Other(ref s) | Token::Identifier(ref s) => Token::Identifier(s.clone()),
+        _ => return err
+    };
+
+    let span = build_tokens_spanned(prog.to_string()).into_iter()
+                                                     .find(|&(ref t, _)| *t == ident)
+                                                     .map(|(_, s)| s);
+
+    match span {
+        Some(s) => err.with_span(s),
+        None => err
+    }
 }
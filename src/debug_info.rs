@@ -0,0 +1,197 @@
+// Debug information for compiled programs.
+// Maps machine code addresses back to IR instructions and source lines, and records where
+// variables and symbol blocks were placed.
+// The IR has no positions, so lines are found by building the IR of each statement separately,
+// which produces the same instructions as building the whole block. Because of this, programs are
+// compiled without optimizations when debug information is requested.
+
+use token::build_tokens_spanned;
+use tree::{Tree, build_token_tree, count_tokens, IGNORED_TOKENS};
+use ir::{Ir, build_ir};
+use compile::{compile_ir, add_segments, setup_size};
+use compile_utils::{get_ir_size, locate_error};
+use error::{BlocksError, Span};
+use utils::{TokenWrapper, is_element_token};
+
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DebugEntry {
+    pub address: i32,
+    pub ir: Ir,
+    pub line: Option<usize>,
+    // The symbol block containing the instruction, or None for the main program
+    pub symbol: Option<String>,
+    // Whether this is the first instruction of a source statement
+    pub statement_start: bool
+}
+
+#[derive(Clone, Debug)]
+pub struct DebugInfo {
+    pub data_segment: i32,
+    pub flow_segment: i32,
+    // Addresses of variables, relative to the data segment
+    pub vars: HashMap<String, i32>,
+    // Addresses of symbol blocks, relative to the flow segment
+    pub symbols: HashMap<String, i32>,
+    // Sorted by address
+    pub entries: Vec<DebugEntry>
+}
+
+impl DebugInfo {
+    // Finds the instruction starting at an address
+    pub fn entry_at(&self, addr: i32) -> Option<&DebugEntry> {
+        self.entries.binary_search_by_key(&addr, |e| e.address).ok().map(|i| &self.entries[i])
+    }
+
+    // Finds the instruction containing an address
+    pub fn entry_containing(&self, addr: i32) -> Option<&DebugEntry> {
+        let index = match self.entries.binary_search_by_key(&addr, |e| e.address) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1
+        };
+
+        let entry = &self.entries[index];
+
+        if addr < entry.address + get_ir_size(&entry.ir) as i32 {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    // Address of the first instruction generated for a source line
+    pub fn line_address(&self, line: usize) -> Option<i32> {
+        self.entries.iter().find(|e| e.line == Some(line) && e.statement_start).map(|e| e.address)
+    }
+
+    pub fn symbol_address(&self, name: &str) -> Option<i32> {
+        self.symbols.get(name).map(|addr| self.flow_segment + addr)
+    }
+
+    pub fn var_address(&self, name: &str) -> Option<i32> {
+        self.vars.get(name).map(|addr| self.data_segment + addr)
+    }
+}
+
+// First line and IR length of each statement, for the main program and each symbol block
+struct StatementLines {
+    main: Vec<(usize, usize)>,
+    blocks: HashMap<String, Vec<(usize, usize)>>
+}
+
+fn statement_lines(stmts: &[TokenWrapper], spans: &[Span], pos: &mut usize,
+                   lines: &mut Vec<(usize, usize)>, result: &mut StatementLines) -> Result<(), BlocksError> {
+    for stmt in stmts {
+        let line = spans[*pos].line_start;
+
+        if let TokenWrapper::Tree(Tree::Symbol(ref name, ref body)) = *stmt {
+            let mut block_lines = Vec::new();
+
+            // Skip `symbol`, the name and the opening brace
+            *pos += 3;
+
+            if let TokenWrapper::Tree(Tree::Block(ref body)) = **body {
+                // Symbol blocks nested in other symbol blocks are not compiled, so they are skipped
+                for s in body {
+                    if let TokenWrapper::Tree(Tree::Symbol(..)) = *s {
+                        *pos += count_tokens(s);
+                    } else {
+                        statement_lines(&[s.clone()], spans, pos, &mut block_lines, result)?;
+                    }
+                }
+            }
+
+            *pos += 1;
+            result.blocks.insert(name.clone(), block_lines);
+        } else {
+            lines.push((line, build_ir(stmt.clone(), 0)?.ir.len()));
+            *pos += count_tokens(stmt);
+        }
+    }
+
+    Ok(())
+}
+
+// Creates debug entries for a list of instructions starting at `address`
+fn add_entries(ir: &[Ir], lines: &[(usize, usize)], mut address: i32,
+               symbol: Option<String>, entries: &mut Vec<DebugEntry>) {
+    // Lines are only usable if the statements account for every instruction
+    let known = lines.iter().map(|l| l.1).sum::<usize>() == ir.len();
+    let mut statements = lines.iter().flat_map(|&(line, len)| (0..len).map(move |i| (line, i == 0)));
+
+    for i in ir {
+        let (line, statement_start) = match statements.next() {
+            Some((line, start)) if known => (Some(line), start),
+            _ => (None, false)
+        };
+
+        entries.push(DebugEntry {
+            address: address,
+            ir: i.clone(),
+            line: line,
+            symbol: symbol.clone(),
+            statement_start: statement_start
+        });
+
+        address += get_ir_size(i) as i32;
+    }
+}
+
+pub fn compile_with_debug_info(prog: &str) -> Result<(Vec<i32>, DebugInfo), BlocksError> {
+    let tree = build_token_tree(prog.to_string())?;
+    let ir = build_ir(TokenWrapper::Tree(tree.clone()), 0).map_err(|e| locate_error(e, prog))?;
+
+    let spans = build_tokens_spanned(prog.to_string()).into_iter()
+                                                      .filter(|t| !is_element_token(&t.0, &IGNORED_TOKENS))
+                                                      .map(|t| t.1)
+                                                      .collect::<Vec<_>>();
+
+    let mut lines = StatementLines {
+        main: Vec::new(),
+        blocks: HashMap::new()
+    };
+
+    if let Tree::Block(ref stmts) = tree {
+        let mut main = Vec::new();
+        statement_lines(stmts, &spans, &mut 0, &mut main, &mut lines)?;
+        lines.main = main;
+    }
+
+    let main_ir = ir.ir.clone();
+    let blocks = ir.blocks.clone();
+    let mut vars = HashMap::new();
+
+    let (compiled, data_section_size, symbol_section_size) = compile_ir(ir, &mut vars, &mut 0, &mut 0)
+        .map_err(|e| locate_error(e, prog))?;
+
+    let data_segment = setup_size() as i32;
+    let flow_segment = data_segment + data_section_size as i32;
+    let mut symbols = HashMap::new();
+    let mut entries = Vec::new();
+
+    for (name, ir) in &blocks {
+        let addr = vars.remove(name).unwrap_or(0);
+        let block_lines = lines.blocks.get(name).cloned().unwrap_or(Vec::new());
+
+        add_entries(ir, &block_lines, flow_segment + addr, Some(name.clone()), &mut entries);
+        symbols.insert(name.clone(), addr);
+    }
+
+    add_entries(&main_ir, &lines.main, flow_segment + symbol_section_size as i32, None, &mut entries);
+
+    // Tags emit no code, so they share an address with the next instruction and are left out
+    entries.retain(|e| if let Ir::Tag(..) = e.ir { false } else { true });
+    entries.sort_by_key(|e| e.address);
+
+    let info = DebugInfo {
+        data_segment: data_segment,
+        flow_segment: flow_segment,
+        vars: vars,
+        symbols: symbols,
+        entries: entries
+    };
+
+    Ok((add_segments(compiled, data_section_size, symbol_section_size), info))
+}
@@ -0,0 +1,188 @@
+// Interactive debugger for compiled programs.
+// Runs the program on the emulator, using debug information to map between machine code and
+// source lines, symbol blocks, variables and IR instructions.
+
+use emulator::{Machine, Fault, disassemble};
+use debug_info::{DebugInfo, compile_with_debug_info};
+use error::BlocksError;
+use token::REGISTERS;
+
+// Stops `continue` and `step` from hanging on programs that never halt or reach a breakpoint
+const MAX_CONTINUE_STEPS: u64 = 10000000;
+
+const HELP: &'static str = "Commands:
+    break <line|symbol>    Set a breakpoint (b)
+    delete <line|symbol>   Remove a breakpoint
+    breakpoints            List breakpoints
+    continue               Run until a breakpoint or the end of the program (c, run)
+    step                   Run until the next source statement (s)
+    stepi                  Run a single machine instruction (si)
+    registers              Print all registers (regs)
+    print <var|$register>  Print a variable or register (p)
+    where                  Show the current instruction (w)
+    quit                   Exit the debugger (q)";
+
+pub struct Debugger {
+    pub machine: Machine,
+    pub info: DebugInfo,
+    // Pairs of the breakpoint as written by the user and its address
+    breakpoints: Vec<(String, i32)>
+}
+
+impl Debugger {
+    pub fn new(program: &[i32], info: DebugInfo) -> Debugger {
+        Debugger {
+            machine: Machine::new(program),
+            info: info,
+            breakpoints: Vec::new()
+        }
+    }
+
+    pub fn from_source(prog: &str) -> Result<Debugger, BlocksError> {
+        let (program, info) = compile_with_debug_info(prog)?;
+
+        Ok(Debugger::new(&program, info))
+    }
+
+    // Runs a command, returning the text to show the user
+    pub fn command(&mut self, input: &str) -> String {
+        let words = input.split_whitespace().collect::<Vec<_>>();
+        let arg = words.get(1).cloned();
+
+        match (words.get(0).cloned().unwrap_or(""), arg) {
+            ("break", Some(arg)) | ("b", Some(arg)) => self.add_breakpoint(arg),
+            ("delete", Some(arg)) => {
+                let count = self.breakpoints.len();
+                self.breakpoints.retain(|b| b.0 != arg);
+
+                if self.breakpoints.len() < count {
+                    format!("Deleted breakpoint {}", arg)
+                } else {
+                    format!("No breakpoint at {}", arg)
+                }
+            },
+            ("breakpoints", None) => {
+                let list = self.breakpoints.iter()
+                                           .map(|b| format!("{} (address {})", b.0, b.1))
+                                           .collect::<Vec<_>>();
+
+                if list.is_empty() { "No breakpoints".to_string() } else { list.join("\n") }
+            },
+            ("continue", None) | ("c", None) | ("run", None) | ("r", None) => self.resume(),
+            ("step", None) | ("s", None) => self.step_statement(),
+            ("stepi", None) | ("si", None) => {
+                let result = self.machine.step();
+                self.report(result)
+            },
+            ("registers", None) | ("regs", None) => self.registers(),
+            ("print", Some(name)) | ("p", Some(name)) => self.print(name),
+            ("where", None) | ("w", None) => self.location(),
+            ("help", None) | ("h", None) => HELP.to_string(),
+            ("", None) => String::new(),
+            _ => format!("Unknown command: {}\nType `help` for a list of commands", input.trim())
+        }
+    }
+
+    fn add_breakpoint(&mut self, arg: &str) -> String {
+        let addr = if let Ok(line) = arg.parse::<usize>() {
+            match self.info.line_address(line) {
+                Some(addr) => addr,
+                None => return format!("No code on line {}", line)
+            }
+        } else {
+            match self.info.symbol_address(arg) {
+                Some(addr) => addr,
+                None => return format!("Unknown symbol: {}", arg)
+            }
+        };
+
+        if !self.breakpoints.iter().any(|b| b.0 == arg) {
+            self.breakpoints.push((arg.to_string(), addr));
+        }
+
+        format!("Breakpoint at {} (address {})", arg, addr)
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        let pc = self.machine.pc();
+        self.breakpoints.iter().any(|b| b.1 == pc)
+    }
+
+    fn resume(&mut self) -> String {
+        // Step off the current breakpoint first so continuing makes progress
+        let mut result = self.machine.step();
+
+        for _ in 0..MAX_CONTINUE_STEPS {
+            if result.is_err() || self.machine.halted || self.at_breakpoint() {
+                return self.report(result);
+            }
+
+            result = self.machine.step();
+        }
+
+        format!("Stopped after {} steps without reaching a breakpoint\n{}",
+                MAX_CONTINUE_STEPS, self.location())
+    }
+
+    fn step_statement(&mut self) -> String {
+        let mut result = self.machine.step();
+
+        for _ in 0..MAX_CONTINUE_STEPS {
+            let at_statement = self.info.entry_at(self.machine.pc()).map(|e| e.statement_start).unwrap_or(false);
+
+            if result.is_err() || self.machine.halted || at_statement || self.at_breakpoint() {
+                return self.report(result);
+            }
+
+            result = self.machine.step();
+        }
+
+        format!("Stopped after {} steps without reaching the next statement\n{}",
+                MAX_CONTINUE_STEPS, self.location())
+    }
+
+    fn report(&self, result: Result<(), Fault>) -> String {
+        match result {
+            Err(fault) => format!("Fault: {}\n{}", fault, self.location()),
+            Ok(()) if self.machine.halted => "Program halted".to_string(),
+            Ok(()) => self.location()
+        }
+    }
+
+    // Describes the instruction at the program counter
+    pub fn location(&self) -> String {
+        let pc = self.machine.pc();
+        let machine = disassemble(&self.machine.memory, pc);
+
+        match self.info.entry_at(pc) {
+            Some(entry) => {
+                let block = entry.symbol.as_ref().map(|s| format!("symbol {}", s)).unwrap_or("main".to_string());
+                let line = entry.line.map(|l| format!(", line {}", l)).unwrap_or(String::new());
+
+                format!("{}: {:<24} {:?} ({}{})", pc, machine, entry.ir, block, line)
+            },
+            None => format!("{}: {:<24} (segment setup or cleanup)", pc, machine)
+        }
+    }
+
+    fn registers(&self) -> String {
+        REGISTERS.iter()
+                 .map(|r| format!("{:<10} {}", r.0, self.machine.register(r.1.clone())))
+                 .collect::<Vec<_>>()
+                 .join("\n")
+    }
+
+    fn print(&self, name: &str) -> String {
+        if let Some(reg) = REGISTERS.iter().find(|r| r.0 == name) {
+            return format!("{} = {}", name, self.machine.register(reg.1.clone()));
+        }
+
+        match self.info.var_address(name) {
+            Some(addr) => match self.machine.read(addr as i64) {
+                Ok(value) => format!("{} = {}", name, value),
+                Err(fault) => format!("{}: {}", name, fault)
+            },
+            None => format!("Unknown variable: {}", name)
+        }
+    }
+}
@@ -0,0 +1,241 @@
+// Documentation generator.
+// Collects `///` comments directly above symbol blocks and variable assignments and renders them
+// as Markdown. Symbols and variables whose names start with an underscore are private and skipped.
+// Register usage of each symbol block is inferred from its IR, following calls within the file:
+//
+//   - inputs are registers read before the block writes them, which is how values are passed
+//   - clobbers are registers the block (or anything it calls) writes
+
+use token::*;
+use tree::build_token_tree;
+use ir::{Ir, build_ir};
+use error::BlocksError;
+use utils::{Address, Register, TokenWrapper};
+
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DocKind {
+    Symbol,
+    Variable
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DocItem {
+    pub kind: DocKind,
+    pub name: String,
+    pub doc: String,
+    pub inputs: Vec<Register>,
+    pub clobbers: Vec<Register>,
+    pub calls: Vec<String>,
+    // Inline machine code may do anything, so its register usage is unknown
+    pub raw: bool
+}
+
+#[derive(Default)]
+struct Usage {
+    inputs: Vec<Register>,
+    clobbers: Vec<Register>,
+    calls: Vec<String>,
+    raw: bool
+}
+
+fn add(list: &mut Vec<Register>, reg: Register) {
+    if !list.contains(&reg) {
+        list.push(reg);
+    }
+}
+
+fn read(usage: &mut Usage, reg: Register) {
+    if !usage.clobbers.contains(&reg) {
+        add(&mut usage.inputs, reg);
+    }
+}
+
+// Finds the register usage of a symbol block, `visiting` guards against recursive calls
+fn register_usage(name: &str, blocks: &BTreeMap<String, Vec<Ir>>, visiting: &mut Vec<String>) -> Usage {
+    let mut usage = Usage::default();
+
+    let ir = match blocks.get(name) {
+        Some(ir) if !visiting.iter().any(|v| v == name) => ir,
+        _ => return usage
+    };
+
+    visiting.push(name.to_string());
+
+    for i in ir {
+        match *i {
+            Ir::RegWrite(ref reg, _) | Ir::RegCopy(ref reg, _) => add(&mut usage.clobbers, reg.clone()),
+            Ir::RegMem(ref reg, _) => read(&mut usage, reg.clone()),
+            Ir::Add | Ir::Sub | Ir::Mul | Ir::Div | Ir::Or | Ir::And | Ir::Xor => {
+                read(&mut usage, Register::Int1);
+                read(&mut usage, Register::Int2);
+                add(&mut usage.clobbers, Register::Accum);
+            },
+            Ir::Not => {
+                read(&mut usage, Register::Int1);
+                add(&mut usage.clobbers, Register::Accum);
+            },
+            Ir::Equals | Ir::Less | Ir::Greater | Ir::LessEqual | Ir::GreaterEqual => {
+                read(&mut usage, Register::Int1);
+                read(&mut usage, Register::Int2);
+                add(&mut usage.clobbers, Register::Flag);
+            },
+            Ir::CondBranch(_) => read(&mut usage, Register::Flag),
+            Ir::Call(Address::Variable(ref callee)) => {
+                if !usage.calls.contains(callee) {
+                    usage.calls.push(callee.clone());
+                }
+
+                let callee = register_usage(callee, blocks, visiting);
+
+                for reg in callee.inputs {
+                    read(&mut usage, reg);
+                }
+
+                for reg in callee.clobbers {
+                    add(&mut usage.clobbers, reg);
+                }
+
+                usage.raw |= callee.raw;
+            },
+            Ir::Raw(_) => usage.raw = true,
+            _ => {}
+        }
+    }
+
+    visiting.pop();
+    usage
+}
+
+// Collects the `///` lines directly above a token, with the slashes and one space removed
+fn doc_comment(token: &TriviaToken) -> String {
+    let mut lines = Vec::new();
+    let mut newlines = 0;
+
+    for trivia in token.leading.iter().rev() {
+        match *trivia {
+            Trivia::Newline => {
+                newlines += 1;
+
+                // A blank line ends the doc comment
+                if newlines > 1 {
+                    break;
+                }
+            },
+            Trivia::Whitespace(_) => {},
+            Trivia::Comment(ref text) if text.starts_with("///") => {
+                let text = text[3..].trim_end();
+                lines.push(if text.starts_with(' ') { &text[1..] } else { text });
+                newlines = 0;
+            },
+            _ => break
+        }
+    }
+
+    lines.reverse();
+    lines.join("\n")
+}
+
+pub fn build_docs(prog: &str) -> Result<Vec<DocItem>, BlocksError> {
+    let tree = build_token_tree(prog.to_string())?;
+    let blocks = build_ir(TokenWrapper::Tree(tree), 0)?.blocks;
+    let tokens = build_tokens_with_trivia(prog.to_string());
+
+    let mut items: Vec<DocItem> = Vec::new();
+
+    for pair in tokens.windows(2) {
+        let kind = match pair[0].token {
+            Token::Symbol => DocKind::Symbol,
+            Token::Assign => DocKind::Variable,
+            _ => continue
+        };
+
+        let name = match pair[1].token {
+            Token::Identifier(ref ident) if !ident.starts_with('_') => ident.clone(),
+            _ => continue
+        };
+
+        if items.iter().any(|i| i.kind == kind && i.name == name) {
+            continue;
+        }
+
+        let doc = doc_comment(&pair[0]);
+
+        // Undocumented variables are usually just locals
+        if kind == DocKind::Variable && doc.is_empty() {
+            continue;
+        }
+
+        let usage = if kind == DocKind::Symbol {
+            register_usage(&name, &blocks, &mut Vec::new())
+        } else {
+            Usage::default()
+        };
+
+        items.push(DocItem {
+            kind: kind,
+            name: name,
+            doc: doc,
+            inputs: usage.inputs,
+            clobbers: usage.clobbers,
+            calls: usage.calls,
+            raw: usage.raw
+        });
+    }
+
+    Ok(items)
+}
+
+fn register_list(regs: &[Register]) -> String {
+    regs.iter().map(|r| format!("`{}`", register_name(r))).collect::<Vec<_>>().join(", ")
+}
+
+pub fn render_markdown(title: &str, items: &[DocItem]) -> String {
+    let mut result = format!("# {}\n", title);
+
+    let sections = [(DocKind::Symbol, "Symbols"), (DocKind::Variable, "Variables")];
+
+    for &(ref kind, heading) in &sections {
+        let items = items.iter().filter(|i| i.kind == *kind).collect::<Vec<_>>();
+
+        if items.is_empty() {
+            continue;
+        }
+
+        result.push_str(&format!("\n## {}\n", heading));
+
+        for item in items {
+            result.push_str(&format!("\n### `{}`\n\n", item.name));
+
+            if !item.doc.is_empty() {
+                result.push_str(&item.doc);
+                result.push_str("\n\n");
+            }
+
+            if item.kind == DocKind::Variable {
+                continue;
+            }
+
+            let none = |s: String| if s.is_empty() { "none".to_string() } else { s };
+
+            result.push_str(&format!("- Inputs: {}\n", none(register_list(&item.inputs))));
+            result.push_str(&format!("- Clobbers: {}\n", none(register_list(&item.clobbers))));
+
+            let calls = item.calls.iter()
+                                  .filter(|c| !c.starts_with('_'))
+                                  .map(|c| format!("`{}`", c))
+                                  .collect::<Vec<_>>();
+
+            if !calls.is_empty() {
+                result.push_str(&format!("- Calls: {}\n", calls.join(", ")));
+            }
+
+            if item.raw {
+                result.push_str("- Contains inline machine code, so other registers may also be used\n");
+            }
+        }
+    }
+
+    result
+}
@@ -0,0 +1,156 @@
+// Memory-mapped devices for the emulator.
+// A device handles loads and stores to a range of addresses, and is notified after every
+// instruction. The standard devices are a console and a timer, mapped near the top of the default
+// memory size. Addresses are absolute, so programs compiled by `compile` reach them through the
+// data segment register, for example `set console = ~ 65520 $segd; set + console 0 = 72;` prints `H`.
+
+use emulator::machine::{Machine, DEFAULT_MEMORY_SIZE};
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+// Console registers: writing to the output register prints a byte, reading the input register
+// reads a byte or -1 at the end of the input, and reading the status register returns 1 if a
+// byte of input is available
+pub const CONSOLE_ADDRESS: i64 = DEFAULT_MEMORY_SIZE as i64 - 16;
+pub const CONSOLE_OUTPUT: usize = 0;
+pub const CONSOLE_INPUT: usize = 1;
+pub const CONSOLE_STATUS: usize = 2;
+
+// The timer holds the number of instructions run since it was last written to
+pub const TIMER_ADDRESS: i64 = DEFAULT_MEMORY_SIZE as i64 - 8;
+
+pub trait Device {
+    // Number of addresses the device occupies
+    fn size(&self) -> usize;
+
+    fn read(&mut self, offset: usize) -> i32;
+
+    fn write(&mut self, offset: usize, value: i32);
+
+    // Called after every instruction
+    fn tick(&mut self) {}
+}
+
+pub struct Console {
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
+    // A byte read to check the status register, which the next read of the input returns
+    peeked: Option<i32>
+}
+
+impl Console {
+    pub fn new(input: Box<dyn Read>, output: Box<dyn Write>) -> Console {
+        Console {
+            input: input,
+            output: output,
+            peeked: None
+        }
+    }
+
+    pub fn stdio() -> Console {
+        Console::new(Box::new(io::stdin()), Box::new(io::stdout()))
+    }
+
+    // Creates a console that reads from `input` and writes to the returned buffer
+    pub fn buffered(input: &[u8]) -> (Console, OutputBuffer) {
+        let output = OutputBuffer::default();
+        let console = Console::new(Box::new(io::Cursor::new(input.to_vec())), Box::new(output.clone()));
+
+        (console, output)
+    }
+
+    fn next_byte(&mut self) -> i32 {
+        if let Some(byte) = self.peeked.take() {
+            return byte;
+        }
+
+        let mut byte = [0];
+
+        match self.input.read(&mut byte) {
+            Ok(1) => byte[0] as i32,
+            _ => -1
+        }
+    }
+}
+
+impl Device for Console {
+    fn size(&self) -> usize {
+        3
+    }
+
+    fn read(&mut self, offset: usize) -> i32 {
+        match offset {
+            CONSOLE_INPUT => self.next_byte(),
+            CONSOLE_STATUS => {
+                let byte = self.next_byte();
+                self.peeked = Some(byte);
+
+                (byte != -1) as i32
+            },
+            _ => 0
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: i32) {
+        if offset == CONSOLE_OUTPUT {
+            // Output is best effort, as programs have no way to handle errors
+            let _ = self.output.write_all(&[value as u8]).and_then(|_| self.output.flush());
+        }
+    }
+}
+
+// Output captured from a console, which can be read while the console is attached to a machine
+#[derive(Clone, Default)]
+pub struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl OutputBuffer {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct Timer {
+    ticks: i32
+}
+
+impl Device for Timer {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _: usize) -> i32 {
+        self.ticks
+    }
+
+    fn write(&mut self, _: usize, value: i32) {
+        self.ticks = value;
+    }
+
+    fn tick(&mut self) {
+        self.ticks = self.ticks.wrapping_add(1);
+    }
+}
+
+// Attaches a console and a timer at their standard addresses
+pub fn attach_standard_devices(machine: &mut Machine, console: Console) {
+    machine.attach(CONSOLE_ADDRESS, Box::new(console));
+    machine.attach(TIMER_ADDRESS, Box::new(Timer::default()));
+}
@@ -0,0 +1,378 @@
+// GDB remote serial protocol stub for the emulator.
+// Memory words are exposed as 4 little-endian bytes each, so word `n` is at byte address `4 * n`.
+// The program counter is reported as a byte address to match; all other registers are reported
+// as they are, so segment registers hold word addresses.
+
+use emulator::machine::{Machine, Fault, REGISTER_COUNT};
+use token::REGISTERS;
+use utils::Register;
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+// Instructions run between checks for an interrupt from the client while continuing
+const CONTINUE_BATCH: u64 = 10000;
+
+pub fn target_description() -> String {
+    let mut xml = "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target>\n  \
+                   <feature name=\"org.blocks.mybytes\">\n".to_string();
+
+    for (i, reg) in registers_by_id().iter().enumerate() {
+        let kind = if *reg == Register::PCounter { "code_ptr" } else { "int32" };
+        let name = &reg_name(reg)[1..];
+
+        xml.push_str(&format!("    <reg name=\"{}\" bitsize=\"32\" type=\"{}\" regnum=\"{}\"/>\n", name, kind, i));
+    }
+
+    xml.push_str("  </feature>\n</target>\n");
+    xml
+}
+
+fn registers_by_id() -> Vec<Register> {
+    (0..REGISTER_COUNT as i32).filter_map(::emulator::machine::register_from_id).collect()
+}
+
+fn reg_name(reg: &Register) -> &'static str {
+    REGISTERS.iter().find(|r| r.1 == *reg).map(|r| r.0).unwrap()
+}
+
+fn hex_word(value: i32) -> String {
+    (0..4).map(|i| format!("{:02x}", (value >> (i * 8)) as u8)).collect()
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s, 16).ok()
+}
+
+fn parse_bytes(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..s.len() / 2).map(|i| u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()).collect()
+}
+
+fn word_from_bytes(bytes: &[u8]) -> i32 {
+    bytes.iter().enumerate().fold(0, |acc, (i, b)| acc | (*b as i32) << (i * 8))
+}
+
+pub struct GdbStub {
+    pub machine: Machine,
+    // Word addresses
+    breakpoints: Vec<i32>
+}
+
+impl GdbStub {
+    pub fn new(machine: Machine) -> GdbStub {
+        GdbStub {
+            machine: machine,
+            breakpoints: Vec::new()
+        }
+    }
+
+    fn read_register(&self, id: usize) -> i32 {
+        if id == Register::PCounter as usize {
+            self.machine.pc() * 4
+        } else {
+            self.machine.registers[id]
+        }
+    }
+
+    fn write_register(&mut self, id: usize, value: i32) {
+        self.machine.registers[id] = if id == Register::PCounter as usize { value / 4 } else { value };
+    }
+
+    fn stop_reply(&self, result: Result<(), Fault>) -> String {
+        let signal = match result {
+            Ok(()) if self.machine.halted => return "W00".to_string(),
+            Ok(()) => SIGTRAP,
+            Err(Fault::InvalidOpcode(_)) | Err(Fault::InvalidRegister(_)) => SIGILL,
+            Err(Fault::AddressOutOfRange(_)) => SIGSEGV,
+            Err(Fault::DivideByZero) => SIGFPE
+        };
+
+        format!("S{:02x}", signal)
+    }
+
+    // Continues until a breakpoint, a fault, the machine halting, or `interrupted` returning true
+    fn resume(&mut self, interrupted: &mut dyn FnMut() -> bool) -> String {
+        // Step off a breakpoint at the current instruction first
+        let mut result = self.machine.step();
+
+        loop {
+            for _ in 0..CONTINUE_BATCH {
+                if result.is_err() || self.machine.halted || self.breakpoints.contains(&self.machine.pc()) {
+                    return self.stop_reply(result);
+                }
+
+                result = self.machine.step();
+            }
+
+            if interrupted() {
+                return format!("S{:02x}", SIGINT);
+            }
+        }
+    }
+
+    // Handles the payload of a packet, returning the payload of the reply
+    // Unsupported packets get an empty reply, as the protocol requires
+    pub fn handle_packet(&mut self, packet: &str, interrupted: &mut dyn FnMut() -> bool) -> String {
+        let (command, args) = packet.split_at(if packet.is_empty() { 0 } else { 1 });
+
+        match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => (0..REGISTER_COUNT).map(|i| hex_word(self.read_register(i))).collect(),
+            "G" => match parse_bytes(args) {
+                Some(ref bytes) if bytes.len() == REGISTER_COUNT * 4 => {
+                    for i in 0..REGISTER_COUNT {
+                        self.write_register(i, word_from_bytes(&bytes[i * 4..i * 4 + 4]));
+                    }
+
+                    "OK".to_string()
+                },
+                _ => "E01".to_string()
+            },
+            "p" => match parse_hex(args) {
+                Some(id) if (id as usize) < REGISTER_COUNT => hex_word(self.read_register(id as usize)),
+                _ => "E01".to_string()
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+
+                match (parts.next().and_then(parse_hex), parts.next().and_then(parse_bytes)) {
+                    (Some(id), Some(ref bytes)) if (id as usize) < REGISTER_COUNT && bytes.len() == 4 => {
+                        self.write_register(id as usize, word_from_bytes(bytes));
+                        "OK".to_string()
+                    },
+                    _ => "E01".to_string()
+                }
+            },
+            "m" => self.read_memory(args).unwrap_or("E01".to_string()),
+            "M" => self.write_memory(args).map(|_| "OK".to_string()).unwrap_or("E01".to_string()),
+            "s" => {
+                let result = self.machine.step();
+                self.stop_reply(result)
+            },
+            "c" => self.resume(interrupted),
+            "Z" | "z" => {
+                let parts = args.split(',').collect::<Vec<_>>();
+
+                // Only software breakpoints are supported
+                match (parts.first(), parts.get(1).and_then(|a| parse_hex(a))) {
+                    (Some(&"0"), Some(addr)) => {
+                        let addr = (addr / 4) as i32;
+
+                        if command == "Z" {
+                            if !self.breakpoints.contains(&addr) {
+                                self.breakpoints.push(addr);
+                            }
+                        } else {
+                            self.breakpoints.retain(|b| *b != addr);
+                        }
+
+                        "OK".to_string()
+                    },
+                    _ => String::new()
+                }
+            },
+            "H" => "OK".to_string(),
+            "D" => "OK".to_string(),
+            "q" => self.query(args),
+            _ => String::new()
+        }
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            "PacketSize=4000;qXfer:features:read+".to_string()
+        } else if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            let mut parts = range.split(',').map(parse_hex);
+
+            match (parts.next(), parts.next()) {
+                (Some(Some(offset)), Some(Some(length))) => {
+                    let xml = target_description();
+                    let start = ::std::cmp::min(offset as usize, xml.len());
+                    let end = ::std::cmp::min(start + length as usize, xml.len());
+
+                    format!("{}{}", if end == xml.len() { "l" } else { "m" }, &xml[start..end])
+                },
+                _ => "E01".to_string()
+            }
+        } else if args == "Attached" {
+            "1".to_string()
+        } else if args == "C" {
+            "QC1".to_string()
+        } else if args == "fThreadInfo" {
+            "m1".to_string()
+        } else if args == "sThreadInfo" {
+            "l".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    // Parses `addr,length` in bytes into a range of words
+    fn word_range(&self, args: &str) -> Option<(usize, usize)> {
+        let mut parts = args.split(',');
+        let addr = parse_hex(parts.next()?)? as usize;
+        let length = parse_hex(parts.next()?)? as usize;
+
+        let end = addr.checked_add(length)?;
+
+        if addr % 4 != 0 || length % 4 != 0 || end / 4 > self.machine.memory.len() {
+            return None;
+        }
+
+        Some((addr / 4, length / 4))
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (start, words) = self.word_range(args)?;
+
+        Some(self.machine.memory[start..start + words].iter().map(|w| hex_word(*w)).collect())
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<()> {
+        let mut parts = args.splitn(2, ':');
+        let (start, words) = self.word_range(parts.next()?)?;
+        let bytes = parse_bytes(parts.next()?)?;
+
+        if bytes.len() != words * 4 {
+            return None;
+        }
+
+        for i in 0..words {
+            self.machine.memory[start + i] = word_from_bytes(&bytes[i * 4..i * 4 + 4]);
+        }
+
+        Some(())
+    }
+}
+
+fn checksum(payload: &str) -> u8 {
+    payload.bytes().fold(0u8, |acc, b| acc.wrapping_add(b))
+}
+
+// Reads a packet, acknowledging it, and returns its payload
+// Returns None when the connection closes, and Some("\x03") for an interrupt
+pub fn read_packet<R: Read + Write>(stream: &mut R) -> io::Result<Option<String>> {
+    let mut byte = [0];
+
+    loop {
+        // Skip acknowledgements and anything else before the start of a packet
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+
+            match byte[0] {
+                b'$' => break,
+                0x03 => return Ok(Some("\x03".to_string())),
+                _ => {}
+            }
+        }
+
+        let mut payload = Vec::new();
+
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+
+            if byte[0] == b'#' {
+                break;
+            }
+
+            payload.push(byte[0]);
+        }
+
+        let mut sum = [0; 2];
+        stream.read_exact(&mut sum)?;
+
+        let payload = String::from_utf8_lossy(&payload).into_owned();
+        let expected = ::std::str::from_utf8(&sum).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+
+        if expected == Some(checksum(&payload)) {
+            stream.write_all(b"+")?;
+            return Ok(Some(payload));
+        }
+
+        stream.write_all(b"-")?;
+    }
+}
+
+pub fn write_packet<W: Write>(stream: &mut W, payload: &str) -> io::Result<()> {
+    let mut escaped = String::new();
+
+    for c in payload.chars() {
+        match c {
+            '#' | '$' | '}' | '*' => {
+                escaped.push('}');
+                escaped.push(((c as u8) ^ 0x20) as char);
+            },
+            c => escaped.push(c)
+        }
+    }
+
+    write!(stream, "${}#{:02x}", escaped, checksum(&escaped))?;
+    stream.flush()
+}
+
+// Serves a single client over a connection until it detaches, kills the target or disconnects
+pub fn serve_connection(stub: &mut GdbStub, stream: &mut TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+
+    while let Some(packet) = read_packet(stream)? {
+        if packet == "\x03" {
+            write_packet(stream, &format!("S{:02x}", SIGINT))?;
+            continue;
+        }
+
+        if packet == "k" {
+            break;
+        }
+
+        let reply = {
+            let peek = stream.try_clone()?;
+
+            // Continuing checks for an interrupt byte without blocking
+            let mut interrupted = || {
+                let mut byte = [0];
+
+                peek.set_nonblocking(true).is_ok() && {
+                    let result = (&peek).read(&mut byte);
+                    let _ = peek.set_nonblocking(false);
+
+                    match result {
+                        Ok(1) => byte[0] == 0x03,
+                        _ => false
+                    }
+                }
+            };
+
+            stub.handle_packet(&packet, &mut interrupted)
+        };
+
+        write_packet(stream, &reply)?;
+
+        if packet == "D" {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Listens on a local port and serves the first client that connects
+pub fn serve(stub: &mut GdbStub, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (mut stream, _) = listener.accept()?;
+
+    serve_connection(stub, &mut stream)
+}
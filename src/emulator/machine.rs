@@ -0,0 +1,398 @@
+// Emulator for the mybytes machine that blocks compiles to.
+// Memory is a flat array of words, and the program is loaded at address 0.
+// Memory operands are relative to the data segment register and branch targets are relative to
+// the flow segment register, which the prologue emitted by `compile` sets up.
+// Returning with an empty call stack halts the machine.
+// Devices can be attached to ranges of addresses, in which case loads and stores from those
+// addresses are handled by the device instead of memory.
+
+use emulator::devices::Device;
+use utils::Register;
+
+use std::fmt;
+
+pub const REGISTER_COUNT: usize = 10;
+pub const DEFAULT_MEMORY_SIZE: usize = 1 << 16;
+
+// Mnemonic and length in words of each opcode, indexed by opcode
+const OPCODES: &'static [(&'static str, usize)] = &[
+    ("write", 3),     // 0: mem[a] = value
+    ("copy", 3),      // 1: mem[a] = mem[b]
+    ("indwrite", 3),  // 2: mem[mem[a]] = value
+    ("indcopy", 3),   // 3: mem[mem[a]] = mem[b]
+    ("", 0),
+    ("indcopy3", 3),  // 5: mem[a] = mem[mem[b]]
+    ("", 0),
+    ("", 0),
+    ("", 0),
+    ("", 0),
+    ("regwrite", 3),  // 10: reg = value
+    ("regcopy", 3),   // 11: reg = mem[a]
+    ("regreg", 3),    // 12: reg_a = reg_b
+    ("regmem", 3),    // 13: mem[a] = reg
+    ("", 0),
+    ("", 0),
+    ("add", 3),       // 16-19: accum = reg_a op reg_b
+    ("sub", 3),
+    ("mul", 3),
+    ("div", 3),
+    ("eq", 3),        // 20-24: flag = reg_a op reg_b
+    ("lt", 3),
+    ("gt", 3),
+    ("le", 3),
+    ("ge", 3),
+    ("or", 3),        // 25, 26: accum = reg_a op reg_b
+    ("and", 3),
+    ("not", 2),       // 27: accum = !reg
+    ("xor", 3),       // 28: accum = reg_a ^ reg_b
+    ("br", 2),        // 29: pc = a
+    ("cbr", 2),       // 30: pc = a if flag is set
+    ("", 0),
+    ("ibr", 2),       // 32: pc = mem[a]
+    ("call", 2),      // 33: push return address, pc = a
+    ("", 0),
+    ("ret", 1)        // 35: pop return address, or halt
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    InvalidOpcode(i32),
+    InvalidRegister(i32),
+    AddressOutOfRange(i64),
+    DivideByZero
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fault::InvalidOpcode(op) => write!(f, "Invalid opcode: {}", op),
+            Fault::InvalidRegister(reg) => write!(f, "Invalid register: {}", reg),
+            Fault::AddressOutOfRange(addr) => write!(f, "Address out of range: {}", addr),
+            Fault::DivideByZero => write!(f, "Division by zero")
+        }
+    }
+}
+
+// Returns the mnemonic and length of an opcode
+pub fn opcode_info(opcode: i32) -> Option<(&'static str, usize)> {
+    if opcode < 0 {
+        return None;
+    }
+
+    match OPCODES.get(opcode as usize) {
+        Some(&(_, 0)) | None => None,
+        Some(&info) => Some(info)
+    }
+}
+
+pub fn register_from_id(id: i32) -> Option<Register> {
+    let registers = [Register::Int1, Register::Int2, Register::Int3, Register::Int4, Register::Flag,
+                     Register::Accum, Register::Error, Register::FlowSegment, Register::DataSegment,
+                     Register::PCounter];
+
+    if id < 0 {
+        None
+    } else {
+        registers.get(id as usize).cloned()
+    }
+}
+
+pub struct Machine {
+    pub memory: Vec<i32>,
+    pub registers: [i32; REGISTER_COUNT],
+    pub call_stack: Vec<i32>,
+    pub halted: bool,
+    pub steps: u64,
+    // Pairs of base address and device
+    devices: Vec<(i64, Box<dyn Device>)>
+}
+
+impl Machine {
+    pub fn new(program: &[i32]) -> Machine {
+        Machine::with_memory_size(program, DEFAULT_MEMORY_SIZE)
+    }
+
+    pub fn with_memory_size(program: &[i32], size: usize) -> Machine {
+        let mut memory = program.to_vec();
+
+        if memory.len() < size {
+            memory.resize(size, 0);
+        }
+
+        Machine {
+            memory: memory,
+            registers: [0; REGISTER_COUNT],
+            call_stack: Vec::new(),
+            halted: false,
+            steps: 0,
+            devices: Vec::new()
+        }
+    }
+
+    // Maps a device to the addresses starting at `base`
+    // Panics if the range overlaps another device
+    pub fn attach(&mut self, base: i64, device: Box<dyn Device>) {
+        let end = base + device.size() as i64;
+
+        for &(other, ref d) in &self.devices {
+            if base < other + d.size() as i64 && other < end {
+                panic!("Device at {} overlaps the device at {}", base, other);
+            }
+        }
+
+        self.devices.push((base, device));
+    }
+
+    fn device_at(&mut self, addr: i64) -> Option<(usize, &mut Box<dyn Device>)> {
+        self.devices.iter_mut()
+                    .find(|d| addr >= d.0 && addr < d.0 + d.1.size() as i64)
+                    .map(|d| ((addr - d.0) as usize, &mut d.1))
+    }
+
+    pub fn pc(&self) -> i32 {
+        self.registers[Register::PCounter as usize]
+    }
+
+    pub fn register(&self, reg: Register) -> i32 {
+        self.registers[reg as usize]
+    }
+
+    pub fn set_register(&mut self, reg: Register, value: i32) {
+        self.registers[reg as usize] = value;
+    }
+
+    fn check_address(&self, addr: i64) -> Result<usize, Fault> {
+        if addr < 0 || addr >= self.memory.len() as i64 {
+            Err(Fault::AddressOutOfRange(addr))
+        } else {
+            Ok(addr as usize)
+        }
+    }
+
+    // Reads and writes memory directly, without going through devices
+    pub fn read(&self, addr: i64) -> Result<i32, Fault> {
+        self.check_address(addr).map(|a| self.memory[a])
+    }
+
+    pub fn write(&mut self, addr: i64, value: i32) -> Result<(), Fault> {
+        let addr = self.check_address(addr)?;
+        self.memory[addr] = value;
+
+        Ok(())
+    }
+
+    // Loads and stores made by instructions, which are handled by a device if one is mapped there
+    fn load(&mut self, addr: i64) -> Result<i32, Fault> {
+        match self.device_at(addr) {
+            Some((offset, device)) => Ok(device.read(offset)),
+            None => self.read(addr)
+        }
+    }
+
+    fn store(&mut self, addr: i64, value: i32) -> Result<(), Fault> {
+        match self.device_at(addr) {
+            Some((offset, device)) => {
+                device.write(offset, value);
+                Ok(())
+            },
+            None => self.write(addr, value)
+        }
+    }
+
+    // Address of a memory operand relative to the data segment
+    fn data(&self, offset: i32) -> i64 {
+        self.register(Register::DataSegment) as i64 + offset as i64
+    }
+
+    // Address of a branch target relative to the flow segment
+    fn flow(&self, offset: i32) -> i32 {
+        self.register(Register::FlowSegment).wrapping_add(offset)
+    }
+
+    fn reg_index(&self, id: i32) -> Result<usize, Fault> {
+        if id < 0 || id as usize >= REGISTER_COUNT {
+            Err(Fault::InvalidRegister(id))
+        } else {
+            Ok(id as usize)
+        }
+    }
+
+    // Reads the opcode and operands of the instruction at an address
+    pub fn fetch(&self, addr: i32) -> Result<(i32, Vec<i32>), Fault> {
+        let opcode = self.read(addr as i64)?;
+        let (_, len) = opcode_info(opcode).ok_or(Fault::InvalidOpcode(opcode))?;
+
+        let mut operands = Vec::new();
+
+        for i in 1..len {
+            operands.push(self.read(addr as i64 + i as i64)?);
+        }
+
+        Ok((opcode, operands))
+    }
+
+    // Executes a single instruction
+    // On a fault the machine is left unchanged, with the program counter at the faulting instruction
+    pub fn step(&mut self) -> Result<(), Fault> {
+        if self.halted {
+            return Ok(());
+        }
+
+        let pc = self.pc();
+        let (opcode, args) = self.fetch(pc)?;
+        let mut next = pc + args.len() as i32 + 1;
+
+        match opcode {
+            0 => {
+                let addr = self.data(args[0]);
+                self.store(addr, args[1])?;
+            },
+            1 => {
+                let (a, b) = (self.data(args[0]), self.data(args[1]));
+                let value = self.load(b)?;
+                self.store(a, value)?;
+            },
+            2 => {
+                let pointer = self.load(self.data(args[0]))?;
+                let addr = self.data(pointer);
+                self.store(addr, args[1])?;
+            },
+            3 => {
+                let pointer = self.load(self.data(args[0]))?;
+                let value = self.load(self.data(args[1]))?;
+                let addr = self.data(pointer);
+                self.store(addr, value)?;
+            },
+            5 => {
+                let pointer = self.load(self.data(args[1]))?;
+                let value = self.load(self.data(pointer))?;
+                let addr = self.data(args[0]);
+                self.store(addr, value)?;
+            },
+            10 => {
+                let reg = self.reg_index(args[0])?;
+                self.registers[reg] = args[1];
+            },
+            11 => {
+                let reg = self.reg_index(args[0])?;
+                self.registers[reg] = self.load(self.data(args[1]))?;
+            },
+            12 => {
+                let (a, b) = (self.reg_index(args[0])?, self.reg_index(args[1])?);
+                self.registers[a] = self.registers[b];
+            },
+            13 => {
+                let reg = self.reg_index(args[1])?;
+                let (addr, value) = (self.data(args[0]), self.registers[reg]);
+                self.store(addr, value)?;
+            },
+            16..=26 | 28 => {
+                let (a, b) = (self.reg_index(args[0])?, self.reg_index(args[1])?);
+                let (a, b) = (self.registers[a], self.registers[b]);
+
+                let (target, value) = match opcode {
+                    16 => (Register::Accum, a.wrapping_add(b)),
+                    17 => (Register::Accum, a.wrapping_sub(b)),
+                    18 => (Register::Accum, a.wrapping_mul(b)),
+                    19 => (Register::Accum, if b == 0 {
+                        return Err(Fault::DivideByZero);
+                    } else {
+                        a.wrapping_div(b)
+                    }),
+                    20 => (Register::Flag, (a == b) as i32),
+                    21 => (Register::Flag, (a < b) as i32),
+                    22 => (Register::Flag, (a > b) as i32),
+                    23 => (Register::Flag, (a <= b) as i32),
+                    24 => (Register::Flag, (a >= b) as i32),
+                    25 => (Register::Accum, a | b),
+                    26 => (Register::Accum, a & b),
+                    _ => (Register::Accum, a ^ b)
+                };
+
+                self.set_register(target, value);
+            },
+            27 => {
+                let reg = self.reg_index(args[0])?;
+                let value = !self.registers[reg];
+                self.set_register(Register::Accum, value);
+            },
+            29 => next = self.flow(args[0]),
+            30 => if self.register(Register::Flag) != 0 {
+                next = self.flow(args[0]);
+            },
+            32 => {
+                let target = self.load(self.data(args[0]))?;
+                next = self.flow(target);
+            },
+            33 => {
+                self.call_stack.push(next);
+                next = self.flow(args[0]);
+            },
+            35 => match self.call_stack.pop() {
+                Some(addr) => next = addr,
+                None => {
+                    next = pc;
+                    self.halted = true;
+                }
+            },
+            _ => return Err(Fault::InvalidOpcode(opcode))
+        }
+
+        self.set_register(Register::PCounter, next);
+        self.steps += 1;
+
+        for device in &mut self.devices {
+            device.1.tick();
+        }
+
+        Ok(())
+    }
+
+    // Runs until the machine halts or `max_steps` instructions have run
+    // Returns whether the machine halted
+    pub fn run(&mut self, max_steps: u64) -> Result<bool, Fault> {
+        for _ in 0..max_steps {
+            if self.halted {
+                break;
+            }
+
+            self.step()?;
+        }
+
+        Ok(self.halted)
+    }
+}
+
+// Formats the instruction at an address, such as `regcopy $int1 4`
+pub fn disassemble(memory: &[i32], addr: i32) -> String {
+    let opcode = match memory.get(addr as usize) {
+        Some(&op) if addr >= 0 => op,
+        _ => return "<out of range>".to_string()
+    };
+
+    let (name, len) = match opcode_info(opcode) {
+        Some(info) => info,
+        None => return format!("<invalid opcode {}>", opcode)
+    };
+
+    let mut result = name.to_string();
+
+    for i in 1..len {
+        let arg = memory.get(addr as usize + i).cloned().unwrap_or(0);
+
+        // Register operands are shown by name
+        let is_register = match opcode {
+            10 | 11 | 27 => i == 1,
+            12 | 16..=26 | 28 => true,
+            13 => i == 2,
+            _ => false
+        };
+
+        match register_from_id(arg) {
+            Some(reg) if is_register => result.push_str(&format!(" {}", ::token::register_name(&reg))),
+            _ => result.push_str(&format!(" {}", arg))
+        }
+    }
+
+    result
+}
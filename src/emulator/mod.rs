@@ -0,0 +1,6 @@
+mod machine;
+pub mod devices;
+pub mod gdb;
+pub mod trace;
+
+pub use self::machine::*;
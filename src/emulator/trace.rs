@@ -0,0 +1,208 @@
+// Execution tracing for the emulator.
+// Each executed instruction is recorded with its address, opcode, operands and the registers it
+// changed. Traces are written in a compact binary format: a header, then for each instruction
+// the program counter, the opcode, its operands, the number of changed registers, and a register
+// id and new value for each one. Numbers are zigzag encoded variable length integers, and the
+// number of operands is implied by the opcode. The program counter is left out of the changed
+// registers, because the next record holds it.
+
+use emulator::machine::{Machine, Fault, REGISTER_COUNT, opcode_info};
+use utils::Register;
+
+use std::io::{self, Read, Write};
+
+const MAGIC: &'static [u8] = b"BTRC\x01";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: i32,
+    pub opcode: i32,
+    pub operands: Vec<i32>,
+    // Pairs of register id and new value
+    pub deltas: Vec<(usize, i32)>
+}
+
+// Executes a single instruction, recording it
+// Returns None if the machine has already halted
+pub fn step_traced(machine: &mut Machine) -> Result<Option<TraceRecord>, Fault> {
+    if machine.halted {
+        return Ok(None);
+    }
+
+    let pc = machine.pc();
+    let (opcode, operands) = machine.fetch(pc)?;
+    let before = machine.registers;
+
+    machine.step()?;
+
+    let deltas = (0..REGISTER_COUNT).filter(|&i| i != Register::PCounter as usize && machine.registers[i] != before[i])
+                                    .map(|i| (i, machine.registers[i]))
+                                    .collect();
+
+    Ok(Some(TraceRecord {
+        pc: pc,
+        opcode: opcode,
+        operands: operands,
+        deltas: deltas
+    }))
+}
+
+fn write_number<W: Write>(out: &mut W, value: i64) -> io::Result<()> {
+    let mut n = ((value << 1) ^ (value >> 63)) as u64;
+
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+
+        if n == 0 {
+            return out.write_all(&[byte]);
+        }
+
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+// Returns None at the end of the input
+fn read_number<R: Read>(input: &mut R) -> io::Result<Option<i64>> {
+    let mut n = 0u64;
+    let mut shift = 0;
+    let mut byte = [0];
+
+    loop {
+        if input.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated trace"));
+        }
+
+        if shift >= 64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid number in trace"));
+        }
+
+        n |= ((byte[0] & 0x7f) as u64) << shift;
+        shift += 7;
+
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(((n >> 1) as i64) ^ -((n & 1) as i64)));
+        }
+    }
+}
+
+fn expect_number<R: Read>(input: &mut R) -> io::Result<i64> {
+    read_number(input)?.ok_or(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated trace"))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub struct TraceWriter<W: Write> {
+    out: W
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut out: W) -> io::Result<TraceWriter<W>> {
+        out.write_all(MAGIC)?;
+
+        Ok(TraceWriter {
+            out: out
+        })
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        write_number(&mut self.out, record.pc as i64)?;
+        write_number(&mut self.out, record.opcode as i64)?;
+
+        for op in &record.operands {
+            write_number(&mut self.out, *op as i64)?;
+        }
+
+        write_number(&mut self.out, record.deltas.len() as i64)?;
+
+        for &(reg, value) in &record.deltas {
+            write_number(&mut self.out, reg as i64)?;
+            write_number(&mut self.out, value as i64)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+pub struct TraceReader<R: Read> {
+    input: R
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<TraceReader<R>> {
+        let mut magic = [0; 5];
+        input.read_exact(&mut magic)?;
+
+        if magic != MAGIC {
+            return Err(invalid("Not a blocks trace file"));
+        }
+
+        Ok(TraceReader {
+            input: input
+        })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<TraceRecord>> {
+        let pc = match read_number(&mut self.input)? {
+            Some(pc) => pc as i32,
+            None => return Ok(None)
+        };
+
+        let opcode = expect_number(&mut self.input)? as i32;
+        let (_, len) = opcode_info(opcode).ok_or(invalid("Invalid opcode in trace"))?;
+
+        let mut operands = Vec::new();
+
+        for _ in 1..len {
+            operands.push(expect_number(&mut self.input)? as i32);
+        }
+
+        let count = expect_number(&mut self.input)?;
+
+        if count < 0 || count > REGISTER_COUNT as i64 {
+            return Err(invalid("Invalid register count in trace"));
+        }
+
+        let mut deltas = Vec::new();
+
+        for _ in 0..count {
+            let reg = expect_number(&mut self.input)?;
+
+            if reg < 0 || reg >= REGISTER_COUNT as i64 {
+                return Err(invalid("Invalid register in trace"));
+            }
+
+            deltas.push((reg as usize, expect_number(&mut self.input)? as i32));
+        }
+
+        Ok(Some(TraceRecord {
+            pc: pc,
+            opcode: opcode,
+            operands: operands,
+            deltas: deltas
+        }))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<io::Result<TraceRecord>> {
+        match self.read_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e))
+        }
+    }
+}
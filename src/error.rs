@@ -1,4 +1,5 @@
 use token::Token;
+use json::Json;
 
 use std::error::Error;
 use std::fmt;
@@ -18,9 +19,37 @@ const ERROR_MESSAGES: &'static [&'static str] = &[
     "IfGoto address must be an identifier",
     "Unknown tag: $0",
     "Tag error: $0",
-    "Unknown error at token: $0"
+    "Unknown error at token: $0",
+    "Expression is nested too deeply at token: $0",
+    "Symbol is defined more than once: $0",
+    "Invalid memory layout: $0"
 ];
 
+// Suggested fixes, indexed the same way as ERROR_MESSAGES
+// An empty string means there is no useful suggestion for that error
+const SUGGESTIONS: &'static [&'static str] = &[
+    "check for a missing semicolon or operand before this token",
+    "add the missing operands after the keyword or operator",
+    "use an identifier as the symbol name, e.g. `symbol foo { ... }`",
+    "use an identifier as the tag name, e.g. `?var_addr = 10`",
+    "use an identifier or number as the tag value",
+    "assign to the variable with `set` before using it",
+    "inline machine code must be whitespace separated integers, e.g. raw `10 7 0`",
+    "add the matching brace",
+    "use an identifier after `@`, e.g. `@foo`",
+    "addresses must not be negative",
+    "use an identifier or number as the call address",
+    "use an identifier or number as the ifgoto address",
+    "the supported tags are `var_addr`, `opt`, `volatile` and `inline`",
+    "",
+    "",
+    "split the expression into several statements",
+    "rename one of the definitions, or link only one of the objects that define it",
+    "move or resize the sections and reserved regions in the layout so they don't overlap"
+];
+
+// The code of a kind is its position here, and codes are part of the JSON diagnostics, so new kinds
+// go at the end
 #[derive(Clone, Debug)]
 pub enum ErrorKind {
     UnexpectedToken,
@@ -37,36 +66,123 @@ pub enum ErrorKind {
     IfGotoAddressType,
     UnknownTag,
     TagError,
-    Other
+    Other,
+    TooDeep,
+    DuplicateSymbol,
+    LayoutError
+}
+
+// A region of the source, with 1-based lines and columns
+// The end column is exclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub line_start: usize,
+    pub column_start: usize,
+    pub line_end: usize,
+    pub column_end: usize
+}
+
+impl Span {
+    pub fn new(start: (usize, usize), end: (usize, usize)) -> Span {
+        Span {
+            line_start: start.0,
+            column_start: start.1,
+            line_end: end.0,
+            column_end: end.1
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("line_start", Json::Number(self.line_start as i64)),
+            ("column_start", Json::Number(self.column_start as i64)),
+            ("line_end", Json::Number(self.line_end as i64)),
+            ("column_end", Json::Number(self.column_end as i64))
+        ])
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct BlocksError {
     kind: ErrorKind,
-    extra: Token
+    extra: Token,
+    span: Option<Span>
 }
 
 impl BlocksError {
     pub fn new(kind: ErrorKind, extra: Token) -> BlocksError {
         BlocksError {
             kind: kind,
-            extra: extra
+            extra: extra,
+            span: None
+        }
+    }
+
+    // Sets the location of the error, unless one was already set closer to its cause
+    pub fn with_span(mut self, span: Span) -> BlocksError {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+
+        self
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub fn extra(&self) -> &Token {
+        &self.extra
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    pub fn code(&self) -> usize {
+        self.kind.clone() as usize
+    }
+
+    pub fn message(&self) -> String {
+        let message = ERROR_MESSAGES[self.code()];
+
+        if let Token::Other(ref s) = self.extra {
+            message.replace("$0", s)
+        } else {
+            message.replace("$0", &format!("{:?}", self.extra))
         }
     }
+
+    pub fn suggestion(&self) -> Option<&'static str> {
+        match SUGGESTIONS[self.code()] {
+            "" => None,
+            s => Some(s)
+        }
+    }
+
+    // Converts the error to a diagnostic for `--message-format=json`
+    // All errors are currently fatal, so the severity is always "error"
+    pub fn to_json(&self, file: &str) -> Json {
+        Json::object(vec![
+            ("code", Json::Number(self.code() as i64)),
+            ("severity", Json::string("error")),
+            ("message", Json::String(self.message())),
+            ("file", Json::string(file)),
+            ("span", self.span.map(|s| s.to_json()).unwrap_or(Json::Null)),
+            ("suggestion", self.suggestion().map(Json::string).unwrap_or(Json::Null))
+        ])
+    }
 }
 
 impl fmt::Display for BlocksError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let code = self.kind.clone() as usize;
-        let mut message = ERROR_MESSAGES[code].to_string();
-        write!(f, "Error (code {}):\n", code)?;
+        write!(f, "Error (code {})", self.code())?;
 
-        if let Token::Other(ref s) = self.extra {
-            message = message.replace("$0", &s);
-        } else {
-            message = message.replace("$0", &format!("{:?}", self.extra));
+        if let Some(span) = self.span {
+            write!(f, " at line {}, column {}", span.line_start, span.column_start)?;
         }
-        write!(f, "{}", message)
+
+        write!(f, ":\n{}", self.message())
     }
 }
 
@@ -0,0 +1,265 @@
+// Source formatter.
+// Pretty prints the token tree with consistent spacing and indentation, keeping `//` comments.
+// The tree has no positions, but it is built from prefix notation, so walking it in order visits
+// the significant tokens in source order. This is used to find where each statement came from,
+// which decides where comments and blank lines go.
+
+use token::*;
+use tree::{Tree, build_token_tree, count_tokens, IGNORED_TOKENS};
+use error::{BlocksError, Span};
+use utils::{TokenWrapper, is_element_token};
+
+const INDENT: &'static str = "    ";
+
+enum Line {
+    Blank,
+    Code(usize, String, Option<String>)
+}
+
+struct Formatter {
+    spans: Vec<Span>,
+    pos: usize,
+    comments: Vec<(String, Span)>,
+    next_comment: usize,
+    last_line: usize,
+    lines: Vec<Line>
+}
+
+pub fn format(prog: &str) -> Result<String, BlocksError> {
+    let tree = build_token_tree(prog.to_string())?;
+    let (tokens, comments) = build_tokens_with_comments(prog.to_string());
+
+    let mut formatter = Formatter {
+        spans: tokens.into_iter()
+                     .filter(|t| !is_element_token(&t.0, &IGNORED_TOKENS))
+                     .map(|t| t.1)
+                     .collect(),
+        pos: 0,
+        comments: comments,
+        next_comment: 0,
+        last_line: 0,
+        lines: Vec::new()
+    };
+
+    if let Tree::Block(stmts) = tree {
+        formatter.block(&stmts, 0);
+    }
+
+    formatter.comments_before(usize::max_value(), 0);
+
+    Ok(formatter.finish())
+}
+
+impl Formatter {
+    // Adds a blank line if the source had one before `line`, collapsing several into one
+    fn separate(&mut self, line: usize) {
+        let after_open = match self.lines.last() {
+            Some(&Line::Code(_, ref code, _)) => code.ends_with('{'),
+            _ => true
+        };
+
+        if !after_open && line > self.last_line + 1 {
+            self.lines.push(Line::Blank);
+        }
+    }
+
+    // Emits the comments that start before the given line as their own lines
+    fn comments_before(&mut self, line: usize, indent: usize) {
+        while self.next_comment < self.comments.len() && self.comments[self.next_comment].1.line_start < line {
+            let (text, span) = self.comments[self.next_comment].clone();
+
+            self.separate(span.line_start);
+            self.lines.push(Line::Code(indent, text.trim_end().to_string(), None));
+            self.last_line = span.line_end;
+            self.next_comment += 1;
+        }
+    }
+
+    // Takes the comment on the same line as the end of a statement, if there is one
+    // A comment after more code on that line, like the `}` of `symbol foo { return; } // x`, belongs
+    // to that code instead
+    fn trailing_comment(&mut self) -> Option<String> {
+        let line = self.last_line;
+        let before_next = |span: Span| self.spans.get(self.pos)
+                                                 .map(|next| (span.line_start, span.column_start) <
+                                                             (next.line_start, next.column_start))
+                                                 .unwrap_or(true);
+
+        match self.comments.get(self.next_comment) {
+            Some(&(ref text, span)) if span.line_start == line && before_next(span) => {
+                self.next_comment += 1;
+                Some(text.trim_end().to_string())
+            },
+            _ => None
+        }
+    }
+
+    fn next_span(&mut self) -> Span {
+        let span = self.spans[self.pos];
+        self.pos += 1;
+        self.last_line = span.line_end;
+
+        span
+    }
+
+    fn block(&mut self, stmts: &[TokenWrapper], indent: usize) {
+        for stmt in stmts {
+            let line = self.spans[self.pos].line_start;
+
+            self.comments_before(line, indent);
+            self.separate(line);
+
+            if let TokenWrapper::Tree(Tree::Symbol(ref name, ref body)) = *stmt {
+                self.symbol(name, body, indent);
+            } else {
+                let code = format!("{};", self.expr(stmt));
+                let comment = self.trailing_comment();
+
+                self.lines.push(Line::Code(indent, code, comment));
+            }
+        }
+    }
+
+    fn symbol(&mut self, name: &str, body: &TokenWrapper, indent: usize) {
+        // `symbol`, the name and the opening brace
+        for _ in 0..3 {
+            self.next_span();
+        }
+
+        let stmts = match *body {
+            TokenWrapper::Tree(Tree::Block(ref stmts)) => stmts.clone(),
+            _ => Vec::new()
+        };
+
+        let close_line = self.spans[self.pos + count_tokens(body) - 2].line_start;
+        let has_comments = self.comments.get(self.next_comment)
+                                        .map(|c| c.1.line_start < close_line)
+                                        .unwrap_or(false);
+
+        if stmts.is_empty() && !has_comments {
+            self.next_span();
+            let comment = self.trailing_comment();
+            self.lines.push(Line::Code(indent, format!("symbol {} {{}}", name), comment));
+
+            return;
+        }
+
+        let comment = self.trailing_comment();
+        self.lines.push(Line::Code(indent, format!("symbol {} {{", name), comment));
+
+        self.block(&stmts, indent + 1);
+        self.comments_before(close_line, indent + 1);
+
+        self.next_span();
+        let comment = self.trailing_comment();
+        self.lines.push(Line::Code(indent, "}".to_string(), comment));
+    }
+
+    fn expr(&mut self, tree: &TokenWrapper) -> String {
+        let tree = match *tree {
+            TokenWrapper::Token(ref token) => {
+                self.next_span();
+
+                return match *token {
+                    Token::Identifier(ref ident) => ident.clone(),
+                    Token::Number(num) => format!("{}", num),
+                    Token::Register(ref reg) => register_name(reg).to_string(),
+                    ref t => format!("{:?}", t)
+                };
+            },
+            TokenWrapper::Tree(ref tree) => tree
+        };
+
+        // Every other node starts with its keyword or operator
+        self.next_span();
+
+        match *tree {
+            Tree::Assign(ref lhs, ref rhs) => {
+                let lhs = self.expr(lhs);
+                format!("set {} = {}", lhs, self.expr(rhs))
+            },
+            Tree::Dereference(ref item) => format!("#{}", self.expr(item)),
+            Tree::Address(ref item) => format!("@{}", self.expr(item)),
+            Tree::Goto(ref item) => format!("goto {}", self.expr(item)),
+            Tree::IfGoto(ref item) => format!("ifgoto {}", self.expr(item)),
+            Tree::Call(ref item) => format!("call {}", self.expr(item)),
+            Tree::Compare(ref item) => format!("cmp {}", self.expr(item)),
+            Tree::Not(ref item) => format!("! {}", self.expr(item)),
+            Tree::Return => "return".to_string(),
+            Tree::Multiply(ref lhs, ref rhs) => self.binary("*", lhs, rhs),
+            Tree::Divide(ref lhs, ref rhs) => self.binary("/", lhs, rhs),
+            Tree::Add(ref lhs, ref rhs) => self.binary("+", lhs, rhs),
+            Tree::Subtract(ref lhs, ref rhs) => self.binary("~", lhs, rhs),
+            Tree::Greater(ref lhs, ref rhs) => self.binary(">", lhs, rhs),
+            Tree::Less(ref lhs, ref rhs) => self.binary("<", lhs, rhs),
+            Tree::GreaterEqual(ref lhs, ref rhs) => self.binary(">=", lhs, rhs),
+            Tree::LessEqual(ref lhs, ref rhs) => self.binary("<=", lhs, rhs),
+            Tree::Equals(ref lhs, ref rhs) => self.binary("==", lhs, rhs),
+            Tree::And(ref lhs, ref rhs) => self.binary("&", lhs, rhs),
+            Tree::Or(ref lhs, ref rhs) => self.binary("|", lhs, rhs),
+            Tree::Xor(ref lhs, ref rhs) => self.binary("^", lhs, rhs),
+            Tree::Tag(ref name, ref value) => {
+                self.next_span();
+                self.next_span();
+                format!("?{} = {}", name, value)
+            },
+            Tree::Raw(ref raw) => {
+                self.next_span();
+                let raw = raw.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+                format!("raw `{}`", raw.join(" "))
+            },
+            Tree::Block(..) | Tree::Symbol(..) => unreachable!()
+        }
+    }
+
+    fn binary(&mut self, operator: &str, lhs: &TokenWrapper, rhs: &TokenWrapper) -> String {
+        let lhs = self.expr(lhs);
+        format!("{} {} {}", operator, lhs, self.expr(rhs))
+    }
+
+    // Joins the lines, aligning trailing comments on consecutive lines to the same column
+    fn finish(self) -> String {
+        let width = |line: &Line| match *line {
+            Line::Code(indent, ref code, Some(_)) => Some(indent * INDENT.len() + code.chars().count()),
+            _ => None
+        };
+
+        let mut result = String::new();
+        let mut i = 0;
+
+        while i < self.lines.len() {
+            let mut end = i;
+            let mut column = 0;
+
+            while let Some(w) = self.lines.get(end).and_then(&width) {
+                column = ::std::cmp::max(column, w);
+                end += 1;
+            }
+
+            for line in &self.lines[i..::std::cmp::max(end, i + 1)] {
+                match *line {
+                    Line::Blank => {},
+                    Line::Code(indent, ref code, ref comment) => {
+                        for _ in 0..indent {
+                            result.push_str(INDENT);
+                        }
+
+                        result.push_str(code);
+
+                        if let Some(ref comment) = *comment {
+                            let padding = column - indent * INDENT.len() - code.chars().count();
+                            result.push_str(&" ".repeat(padding + 1));
+                            result.push_str(comment);
+                        }
+                    }
+                }
+
+                result.push('\n');
+            }
+
+            i = ::std::cmp::max(end, i + 1);
+        }
+
+        result
+    }
+}
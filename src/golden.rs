@@ -0,0 +1,305 @@
+// Golden tests for whole programs.
+// A program's expected results are written in `//` comments at the top of the file:
+//
+//   // input: abc\n      bytes given to the console
+//   // var: x = 42       the final value of a variable (may appear several times)
+//   // output: ABC\n     everything written to the console
+//   // error: 5          the code of the error the program fails to compile with
+//   // fault: <message>  the fault the emulator stops with
+//
+// Programs are compiled with `compile` and run on the emulator with the standard devices. Running
+// in bless mode rewrites the headers to match the actual results, recording every variable.
+
+use compile::{compile_with_vars, setup_size};
+use emulator::Machine;
+use emulator::devices::{Console, attach_standard_devices};
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+// Programs that run longer than this are reported as not halting
+const MAX_STEPS: u64 = 1000000;
+
+const KEYS: &'static [&'static str] = &["input", "var", "output", "error", "fault"];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Expectation {
+    pub input: Vec<u8>,
+    // Sorted by name
+    pub vars: Vec<(String, i32)>,
+    pub output: Vec<u8>,
+    pub error: Option<usize>,
+    pub fault: Option<String>
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let mut result = String::new();
+
+    for &b in bytes {
+        match b {
+            b'\n' => result.push_str("\\n"),
+            b'\t' => result.push_str("\\t"),
+            b'\\' => result.push_str("\\\\"),
+            0x20..=0x7e => result.push(b as char),
+            _ => result.push_str(&format!("\\x{:02x}", b))
+        }
+    }
+
+    result
+}
+
+fn unescape(s: &str) -> Result<Vec<u8>, String> {
+    let mut result = Vec::new();
+    let mut bytes = s.bytes();
+
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            result.push(b);
+            continue;
+        }
+
+        match bytes.next() {
+            Some(b'n') => result.push(b'\n'),
+            Some(b't') => result.push(b'\t'),
+            Some(b'\\') => result.push(b'\\'),
+            Some(b'x') => {
+                let hex = bytes.by_ref().take(2).collect::<Vec<_>>();
+                let byte = String::from_utf8(hex).ok().and_then(|h| u8::from_str_radix(&h, 16).ok());
+
+                result.push(byte.ok_or(format!("Invalid escape in `{}`", s))?);
+            },
+            _ => return Err(format!("Invalid escape in `{}`", s))
+        }
+    }
+
+    Ok(result)
+}
+
+// Splits a header line into its key and value
+fn header_entry(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+
+    if !line.starts_with("//") || line.starts_with("///") {
+        return None;
+    }
+
+    let mut parts = line[2..].splitn(2, ':');
+    let key = parts.next()?.trim();
+    let value = parts.next()?;
+
+    if KEYS.contains(&key) {
+        // A single space separates the colon from the value, so values can have leading spaces
+        Some((key, value.strip_prefix(' ').unwrap_or(value)))
+    } else {
+        None
+    }
+}
+
+// Lines of the leading comment block
+fn header_lines(source: &str) -> Vec<&str> {
+    source.lines().take_while(|l| l.trim_start().starts_with("//")).collect()
+}
+
+pub fn parse_header(source: &str) -> Result<Expectation, String> {
+    let mut expected = Expectation::default();
+
+    for line in header_lines(source) {
+        let (key, value) = match header_entry(line) {
+            Some(entry) => entry,
+            None => continue
+        };
+
+        match key {
+            "input" => expected.input = unescape(value)?,
+            "output" => expected.output = unescape(value)?,
+            "var" => {
+                let mut parts = value.splitn(2, '=');
+                let name = parts.next().unwrap_or("").trim();
+                let value = parts.next().and_then(|v| v.trim().parse().ok());
+
+                match value {
+                    Some(v) if !name.is_empty() => expected.vars.push((name.to_string(), v)),
+                    _ => return Err(format!("Invalid variable expectation `{}`", line))
+                }
+            },
+            "error" => expected.error = Some(value.trim().parse().map_err(|_| format!("Invalid error code `{}`", line))?),
+            _ => expected.fault = Some(value.to_string())
+        }
+    }
+
+    expected.vars.sort();
+
+    Ok(expected)
+}
+
+pub fn render_header(expected: &Expectation) -> String {
+    let mut result = String::new();
+
+    if !expected.input.is_empty() {
+        result.push_str(&format!("// input: {}\n", escape(&expected.input)));
+    }
+
+    if let Some(code) = expected.error {
+        result.push_str(&format!("// error: {}\n", code));
+    }
+
+    for &(ref name, value) in &expected.vars {
+        result.push_str(&format!("// var: {} = {}\n", name, value));
+    }
+
+    if !expected.output.is_empty() {
+        result.push_str(&format!("// output: {}\n", escape(&expected.output)));
+    }
+
+    if let Some(ref fault) = expected.fault {
+        result.push_str(&format!("// fault: {}\n", fault));
+    }
+
+    result
+}
+
+// Compiles and runs a program, recording every variable
+pub fn run_program(source: &str, input: &[u8]) -> Expectation {
+    let mut actual = Expectation {
+        input: input.to_vec(),
+        ..Expectation::default()
+    };
+
+    match compile_with_vars(source) {
+        Ok((program, vars)) => run_compiled(&program, vars, input),
+        Err(e) => {
+            actual.error = Some(e.code());
+            actual
+        }
+    }
+}
+
+// Runs a compiled program, recording the variables in `vars`
+pub fn run_compiled(program: &[i32], vars: HashMap<String, i32>, input: &[u8]) -> Expectation {
+    let mut actual = Expectation {
+        input: input.to_vec(),
+        ..Expectation::default()
+    };
+
+    let mut machine = Machine::new(program);
+    let (console, output) = Console::buffered(input);
+
+    attach_standard_devices(&mut machine, console);
+
+    match machine.run(MAX_STEPS) {
+        Ok(true) => {},
+        Ok(false) => actual.fault = Some(format!("Did not halt after {} instructions", MAX_STEPS)),
+        Err(fault) => actual.fault = Some(fault.to_string())
+    }
+
+    for (name, addr) in vars {
+        if !name.starts_with("__temp_") {
+            let value = machine.read(setup_size() as i64 + addr as i64).unwrap_or(0);
+            actual.vars.push((name, value));
+        }
+    }
+
+    actual.vars.sort();
+    actual.output = output.contents();
+
+    actual
+}
+
+// Checks a program against its header, returning a description of the differences
+pub fn check(source: &str) -> Result<(), String> {
+    let expected = parse_header(source)?;
+    let actual = run_program(source, &expected.input);
+    let mut diff = Vec::new();
+
+    if expected.error != actual.error {
+        diff.push(format!("error: expected {:?}, found {:?}", expected.error, actual.error));
+    }
+
+    // Only the variables named in the header are checked
+    for &(ref name, value) in &expected.vars {
+        match actual.vars.iter().find(|v| v.0 == *name) {
+            Some(&(_, v)) if v == value => {},
+            Some(&(_, v)) => diff.push(format!("var {}: expected {}, found {}", name, value, v)),
+            None => diff.push(format!("var {}: expected {}, but it does not exist", name, value))
+        }
+    }
+
+    if expected.output != actual.output {
+        diff.push(format!("output: expected `{}`, found `{}`", escape(&expected.output), escape(&actual.output)));
+    }
+
+    if expected.fault != actual.fault {
+        diff.push(format!("fault: expected {:?}, found {:?}", expected.fault, actual.fault));
+    }
+
+    if diff.is_empty() {
+        Ok(())
+    } else {
+        Err(diff.join("\n"))
+    }
+}
+
+// Rewrites the header of a program to match its actual results
+// Other comments in the header are kept above the expectations
+pub fn bless(source: &str) -> Result<String, String> {
+    let expected = parse_header(source)?;
+    let actual = run_program(source, &expected.input);
+    let header = header_lines(source);
+
+    let mut result = header.iter().filter(|l| header_entry(l).is_none())
+                                  .map(|l| format!("{}\n", l))
+                                  .collect::<String>();
+
+    result.push_str(&render_header(&actual));
+
+    let body = source.lines().skip(header.len()).collect::<Vec<_>>().join("\n");
+    let newline = if source.ends_with('\n') { "\n" } else { "" };
+
+    result.push_str(&body);
+    result.push_str(newline);
+
+    Ok(result)
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    let mut source = String::new();
+    File::open(path)?.read_to_string(&mut source)?;
+
+    Ok(source)
+}
+
+// Checks or blesses every `.blk` file in a directory, returning the failures
+pub fn run_dir(dir: &Path, bless_files: bool) -> io::Result<Vec<(PathBuf, String)>> {
+    let mut paths = fs::read_dir(dir)?.map(|e| e.map(|e| e.path()))
+                                      .collect::<io::Result<Vec<_>>>()?;
+
+    paths.retain(|p| p.extension().is_some_and(|e| e == "blk"));
+    paths.sort();
+
+    let mut failures = Vec::new();
+
+    for path in paths {
+        let source = read_file(&path)?;
+
+        let result = if bless_files {
+            bless(&source).and_then(|blessed| {
+                if blessed != source {
+                    File::create(&path).and_then(|mut f| f.write_all(blessed.as_bytes()))
+                                       .map_err(|e| e.to_string())?;
+                }
+
+                Ok(())
+            })
+        } else {
+            check(&source)
+        };
+
+        if let Err(e) = result {
+            failures.push((path, e));
+        }
+    }
+
+    Ok(failures)
+}
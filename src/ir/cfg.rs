@@ -0,0 +1,259 @@
+// Control-flow graphs of the IR.
+// The main code and each symbol block are split into basic blocks, which end at instructions that
+// transfer control. Edges follow branches, calls and the code after them, including into other
+// symbol blocks, so the graph covers the whole program. A symbol block that doesn't end in a
+// return or jump runs into the one placed after it, which is the next one by name, and the last
+// one runs into the main code.
+//
+// Jumps to fixed addresses and through pointers have no edges, since their targets aren't known.
+// A symbol block whose address is taken by reachable code is assumed reachable, as it may be
+// jumped to through a pointer.
+
+use ir::{Ir, IrResult, falls_through};
+use token::register_name;
+use utils::Address;
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    // To the next block, when a conditional branch is not taken, a call returns or a symbol block
+    // runs into the one after it
+    Next,
+    Branch,
+    CondBranch,
+    Call
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    // The symbol block this block is in, or None for the main code
+    pub symbol: Option<String>,
+    pub ir: Vec<Ir>,
+    // The kind of each edge and the index of the block it goes to
+    pub edges: Vec<(EdgeKind, usize)>,
+    pub reachable: bool
+}
+
+// The main code comes first, starting at block 0, followed by the symbol blocks sorted by name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>
+}
+
+fn ends_block(ir: &Ir) -> bool {
+    matches!(*ir, Ir::Branch(_) | Ir::CondBranch(_) | Ir::IndirBranch(_) | Ir::Call(_) | Ir::Return)
+}
+
+fn split(symbol: Option<&String>, ir: &[Ir]) -> Vec<BasicBlock> {
+    let mut blocks = Vec::new();
+    let mut current = Vec::new();
+
+    for item in ir {
+        current.push(item.clone());
+
+        if ends_block(item) {
+            blocks.push(current);
+            current = Vec::new();
+        }
+    }
+
+    // An empty symbol block still has an entry
+    if !current.is_empty() || blocks.is_empty() {
+        blocks.push(current);
+    }
+
+    blocks.into_iter().map(|ir| BasicBlock {
+        symbol: symbol.cloned(),
+        ir: ir,
+        edges: Vec::new(),
+        reachable: false
+    }).collect()
+}
+
+impl Cfg {
+    pub fn build(ir: &IrResult) -> Cfg {
+        let symbols = ir.blocks.keys().collect::<Vec<_>>();
+        let mut blocks = split(None, &ir.ir);
+        let mut entries = HashMap::new();
+
+        for symbol in symbols {
+            entries.insert(symbol.clone(), blocks.len());
+            blocks.extend(split(Some(symbol), &ir.blocks[symbol]));
+        }
+
+        for i in 0..blocks.len() {
+            let target = |addr: &Address| match *addr {
+                Address::Variable(ref name) => entries.get(name).cloned(),
+                Address::Static(_) => None
+            };
+
+            // Symbol blocks follow the main code in the order compile_ir places them, and each has at
+            // least one basic block, so the block after the last one of a symbol block is the entry of
+            // the next. compile_ir places the main code after the last symbol block
+            let same = |j: usize| blocks.get(j).is_some_and(|b| b.symbol == blocks[i].symbol);
+
+            let next = match blocks[i].symbol {
+                Some(ref symbol) if !same(i + 1) => if falls_through(&ir.blocks[symbol]) {
+                    Some(if i + 1 < blocks.len() { i + 1 } else { 0 })
+                } else {
+                    None
+                },
+                _ if same(i + 1) => Some(i + 1),
+                _ => None
+            };
+
+            let edges = match blocks[i].ir.last() {
+                Some(Ir::Branch(addr)) => target(addr).map(|t| (EdgeKind::Branch, t)).into_iter().collect(),
+                Some(Ir::CondBranch(addr)) => {
+                    let taken = target(addr).map(|t| (EdgeKind::CondBranch, t));
+                    taken.into_iter().chain(next.map(|n| (EdgeKind::Next, n))).collect()
+                },
+                Some(Ir::Call(addr)) => {
+                    let called = target(addr).map(|t| (EdgeKind::Call, t));
+                    called.into_iter().chain(next.map(|n| (EdgeKind::Next, n))).collect()
+                },
+                Some(Ir::Return) | Some(Ir::IndirBranch(_)) => Vec::new(),
+                _ => next.map(|n| (EdgeKind::Next, n)).into_iter().collect()
+            };
+
+            blocks[i].edges = edges;
+        }
+
+        let mut cfg = Cfg { blocks: blocks };
+        cfg.mark_reachable(&entries);
+        cfg
+    }
+
+    fn mark_reachable(&mut self, entries: &HashMap<String, usize>) {
+        let mut stack = vec![0];
+
+        while let Some(i) = stack.pop() {
+            if self.blocks[i].reachable {
+                continue;
+            }
+
+            self.blocks[i].reachable = true;
+            stack.extend(self.blocks[i].edges.iter().map(|&(_, to)| to));
+
+            for item in &self.blocks[i].ir {
+                let taken = match *item {
+                    Ir::Write(_, Address::Variable(ref name)) |
+                    Ir::IndirWrite(_, Address::Variable(ref name)) |
+                    Ir::RegWrite(_, Address::Variable(ref name)) => entries.get(name),
+                    _ => None
+                };
+
+                stack.extend(taken);
+            }
+        }
+    }
+
+    // The index of the first block of the main code or a symbol block
+    pub fn entry(&self, symbol: Option<&str>) -> Option<usize> {
+        self.blocks.iter().position(|b| b.symbol.as_ref().map(|s| s as &str) == symbol)
+    }
+
+    // Renders the graph in the Graphviz DOT language, with a cluster for the main code and each
+    // symbol block, and unreachable blocks filled in
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        let mut start = 0;
+
+        while start < self.blocks.len() {
+            let symbol = &self.blocks[start].symbol;
+            let end = self.blocks[start..].iter().position(|b| b.symbol != *symbol).map_or(self.blocks.len(), |n| start + n);
+
+            dot.push_str(&format!("    subgraph cluster_{} {{\n", start));
+            dot.push_str(&format!("        label=\"{}\";\n", escape(symbol.as_ref().map_or("main", |s| s as &str))));
+
+            for i in start..end {
+                let block = &self.blocks[i];
+                let mut label = block.ir.iter().map(|ir| format!("{}\\l", escape(&describe(ir)))).collect::<String>();
+
+                if label.is_empty() {
+                    label.push_str("(empty)\\l");
+                }
+
+                let style = if block.reachable {
+                    ""
+                } else {
+                    ", style=\"filled,dashed\", fillcolor=\"#f4cccc\""
+                };
+
+                dot.push_str(&format!("        b{} [label=\"b{}:\\l{}\"{}];\n", i, i, label, style));
+            }
+
+            dot.push_str("    }\n");
+            start = end;
+        }
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            for &(kind, to) in &block.edges {
+                let attrs = match kind {
+                    EdgeKind::Next => "",
+                    EdgeKind::Branch => " [label=\"goto\"]",
+                    EdgeKind::CondBranch => " [label=\"ifgoto\"]",
+                    EdgeKind::Call => " [label=\"call\", style=dashed]"
+                };
+
+                dot.push_str(&format!("    b{} -> b{}{};\n", i, to, attrs));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl fmt::Display for Cfg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, block) in self.blocks.iter().enumerate() {
+            let edges = block.edges.iter().map(|&(kind, to)| format!("{:?} b{}", kind, to)).collect::<Vec<_>>();
+
+            write!(f, "b{} ({})", i, block.symbol.as_ref().map_or("main", |s| s as &str))?;
+
+            if !block.reachable {
+                write!(f, " unreachable")?;
+            }
+
+            writeln!(f, ": {} instructions -> [{}]", block.ir.len(), edges.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn address(addr: &Address) -> String {
+    match *addr {
+        Address::Static(n) => n.to_string(),
+        Address::Variable(ref name) => name.clone()
+    }
+}
+
+// A short description of an instruction, for labels
+pub fn describe(ir: &Ir) -> String {
+    match *ir {
+        Ir::Write(ref a, ref b) => format!("write {} {}", address(a), address(b)),
+        Ir::Copy(ref a, ref b) => format!("copy {} {}", address(a), address(b)),
+        Ir::IndirWrite(ref a, ref b) => format!("indirwrite {} {}", address(a), address(b)),
+        Ir::IndirCopy(ref a, ref b) => format!("indircopy {} {}", address(a), address(b)),
+        Ir::IndirCopy3(ref a, ref b) => format!("indircopy3 {} {}", address(a), address(b)),
+        Ir::RegWrite(ref reg, ref a) => format!("regwrite {} {}", register_name(reg), address(a)),
+        Ir::RegCopy(ref reg, ref a) => format!("regcopy {} {}", register_name(reg), address(a)),
+        Ir::RegMem(ref reg, ref a) => format!("regmem {} {}", register_name(reg), address(a)),
+        Ir::RegReg(ref a, ref b) => format!("regreg {} {}", register_name(a), register_name(b)),
+        Ir::Branch(ref a) => format!("branch {}", address(a)),
+        Ir::CondBranch(ref a) => format!("condbranch {}", address(a)),
+        Ir::IndirBranch(ref a) => format!("indirbranch {}", address(a)),
+        Ir::Call(ref a) => format!("call {}", address(a)),
+        Ir::Tag(ref key, ref value) => format!("tag {} {}", key, value),
+        Ir::Raw(ref raw) => format!("raw {}", raw.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(" ")),
+        ref ir => format!("{:?}", ir).to_lowercase()
+    }
+}
@@ -0,0 +1,82 @@
+// Removal of unreachable symbol blocks.
+// compile_ir places every symbol block in the program, so a file that includes a large library
+// carries all of it. This pass keeps only the blocks the main code can reach: those it branches to,
+// calls or takes the address of with `@`, and in turn those that these blocks reach.
+//
+// A block that doesn't end by returning or branching runs into the block placed after it, which is
+// then reachable too. Blocks are only removed if their level has the pass. Inline machine code,
+// computed gotos and jumps or calls to fixed addresses can land anywhere, so programs that use them
+// keep every block.
+
+use compile_utils::get_code_size;
+use ir::{Ir, IrResult};
+use opt::{OptLevel, code_level};
+use utils::Address;
+
+use std::collections::HashSet;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemovedBlock {
+    pub name: String,
+    // Words of code
+    pub size: usize
+}
+
+// The symbol blocks some code refers to
+fn referenced<'a>(ir: &'a [Ir], blocks: &[String]) -> Vec<&'a String> {
+    ir.iter().flat_map(Ir::addresses).filter_map(|addr| match *addr {
+        Address::Variable(ref name) if blocks.contains(name) => Some(name),
+        _ => None
+    }).collect()
+}
+
+// Whether control can run off the end of some code, into the symbol block placed after it
+pub fn falls_through(ir: &[Ir]) -> bool {
+    !matches!(ir.iter().rfind(|item| !matches!(**item, Ir::Tag(..))), Some(&Ir::Return) | Some(&Ir::Branch(_)) | Some(&Ir::IndirBranch(_)))
+}
+
+// Removes the symbol blocks of a program compiled at `level` that can't be reached, returning them
+// sorted by name
+// compile_ir places blocks in the order of their names, which decides what a block runs into
+pub fn remove_dead_blocks(ir: &mut IrResult, level: OptLevel) -> Vec<RemovedBlock> {
+    let jumps_anywhere = |code: &Vec<Ir>| code.iter().any(|item| match *item {
+        Ir::Raw(_) | Ir::IndirBranch(_) => true,
+        Ir::Branch(ref a) | Ir::CondBranch(ref a) | Ir::Call(ref a) => matches!(*a, Address::Static(_)),
+        _ => false
+    });
+
+    if jumps_anywhere(&ir.ir) || ir.blocks.values().any(jumps_anywhere) {
+        return Vec::new();
+    }
+
+    let order = ir.blocks.keys().cloned().collect::<Vec<_>>();
+    let mut reachable = HashSet::new();
+    let mut stack = referenced(&ir.ir, &order);
+
+    while let Some(name) = stack.pop() {
+        if !reachable.insert(name) {
+            continue;
+        }
+
+        let block = &ir.blocks[name];
+        stack.extend(referenced(block, &order));
+
+        if falls_through(block) {
+            let i = order.iter().position(|n| n == name).unwrap();
+            stack.extend(order.get(i + 1));
+        }
+    }
+
+    let removed = order.iter().filter(|name| {
+        !reachable.contains(name) && code_level(&ir.blocks[*name], level).passes().dead_blocks
+    }).map(|name| RemovedBlock {
+        name: name.clone(),
+        size: get_code_size(&ir.blocks[name])
+    }).collect::<Vec<_>>();
+
+    for block in &removed {
+        ir.blocks.remove(&block.name);
+    }
+
+    removed
+}
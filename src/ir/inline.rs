@@ -0,0 +1,192 @@
+// Inlining of small symbol blocks.
+// A `call` costs the call and return instructions and a trip through the stack, which is most of
+// the cost of a small helper. This pass replaces calls to small symbol blocks with a copy of their
+// code, and removes the blocks that are no longer referenced afterwards.
+//
+// A block can only be inlined if it runs straight through to a single `return` at its end: its
+// code must not branch or run inline machine code, which could jump anywhere, and it must not call
+// itself, directly or through other blocks. Blocks with at most INLINE_SIZE words of code are
+// inlined, and `?inline always;` or `?inline never;` in a block overrides the size.
+//
+// Both the call and the block must be at a level with the inline pass. Like peephole, this reads
+// the levels from the `opt` tags build_module leaves in the IR.
+
+use compile_utils::get_code_size;
+use error::{BlocksError, ErrorKind};
+use ir::{Ir, IrResult, falls_through};
+use opt::{OptLevel, code_level};
+use token::Token;
+use utils::Address;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// The largest block, in words of code, that is inlined without `?inline always`
+pub const INLINE_SIZE: usize = 24;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InlineHint {
+    Always,
+    Never
+}
+
+pub fn parse_inline_tag(value: &str) -> Result<InlineHint, BlocksError> {
+    match value {
+        "always" => Ok(InlineHint::Always),
+        "never" => Ok(InlineHint::Never),
+        _ => Err(BlocksError::new(ErrorKind::TagError,
+                                  Token::Other(format!("inline must be `always` or `never` (found `{}`)", value))))
+    }
+}
+
+fn tag<'a>(ir: &'a Ir, name: &str) -> Option<&'a str> {
+    match *ir {
+        Ir::Tag(ref key, ref value) if key == name => Some(value),
+        _ => None
+    }
+}
+
+// The code that replaces a call to a block, if the block can be inlined
+// compile_ir reports invalid tags, so they are ignored here
+fn inline_body(block: &[Ir]) -> Option<Vec<Ir>> {
+    let end = block.iter().rposition(|item| !matches!(*item, Ir::Tag(..)))?;
+
+    if block[end] != Ir::Return {
+        return None;
+    }
+
+    let straight = block[..end].iter().all(|item| {
+        !matches!(*item, Ir::Branch(_) | Ir::CondBranch(_) | Ir::IndirBranch(_) | Ir::Return | Ir::Raw(_))
+    });
+
+    if !straight {
+        return None;
+    }
+
+    // Other tags, such as `volatile`, still apply to the copy
+    Some(block.iter().enumerate()
+              .filter(|&(i, item)| i != end && tag(item, "opt").is_none() && tag(item, "inline").is_none())
+              .map(|(_, item)| item.clone())
+              .collect())
+}
+
+fn called(ir: &[Ir]) -> impl Iterator<Item = &String> {
+    ir.iter().filter_map(|item| match *item {
+        Ir::Call(Address::Variable(ref name)) => Some(name),
+        _ => None
+    })
+}
+
+// Whether a block can reach itself through calls
+fn is_recursive(name: &str, blocks: &BTreeMap<String, Vec<Ir>>) -> bool {
+    let mut stack = called(&blocks[name]).collect::<Vec<_>>();
+    let mut visited = HashSet::new();
+
+    while let Some(next) = stack.pop() {
+        if next == name {
+            return true;
+        }
+
+        if visited.insert(next) {
+            if let Some(block) = blocks.get(next) {
+                stack.extend(called(block));
+            }
+        }
+    }
+
+    false
+}
+
+fn refers_to(ir: &[Ir], name: &str) -> bool {
+    ir.iter().flat_map(Ir::addresses).any(|addr| matches!(*addr, Address::Variable(ref n) if n == name))
+}
+
+// Whether the block placed before a block runs into it
+// compile_ir places blocks in the order of their names
+fn is_run_into(blocks: &BTreeMap<String, Vec<Ir>>, name: &str) -> bool {
+    blocks.iter().take_while(|&(other, _)| other.as_str() < name).last().is_some_and(|(_, block)| falls_through(block))
+}
+
+// Replaces the calls in some code whose level has the inline pass, returning how many it replaced
+fn inline_calls(ir: &mut Vec<Ir>, mut level: OptLevel, bodies: &HashMap<String, Vec<Ir>>,
+                inlined: &mut HashSet<String>) -> usize {
+    let mut result = Vec::with_capacity(ir.len());
+    let mut replaced = 0;
+
+    for item in ir.drain(..) {
+        if let Some(value) = tag(&item, "opt") {
+            level = OptLevel::parse(value).unwrap_or(level);
+        }
+
+        if let Ir::Call(Address::Variable(ref name)) = item {
+            if let Some(body) = bodies.get(name).filter(|_| level.passes().inline) {
+                result.extend(body.iter().cloned());
+                inlined.insert(name.clone());
+                replaced += 1;
+                continue;
+            }
+        }
+
+        result.push(item);
+    }
+
+    *ir = result;
+    replaced
+}
+
+// Inlines calls to small symbol blocks in a whole program compiled at `level`, returning how many
+// calls were replaced
+pub fn inline(ir: &mut IrResult, level: OptLevel) -> usize {
+    let mut bodies = HashMap::new();
+
+    for (name, block) in &ir.blocks {
+        let hint = block.iter().filter_map(|item| tag(item, "inline")).next_back().and_then(|v| parse_inline_tag(v).ok());
+
+        if hint == Some(InlineHint::Never) || !code_level(block, level).passes().inline || is_recursive(name, &ir.blocks) {
+            continue;
+        }
+
+        if let Some(body) = inline_body(block) {
+            if hint == Some(InlineHint::Always) || get_code_size(&body) <= INLINE_SIZE {
+                bodies.insert(name.clone(), body);
+            }
+        }
+    }
+
+    let mut inlined = HashSet::new();
+    let mut replaced = 0;
+
+    // The copies may call other blocks that can be inlined, which are replaced by the next round
+    // No block can reach itself through calls, so this ends
+    loop {
+        let mut changed = inline_calls(&mut ir.ir, level, &bodies, &mut inlined);
+
+        for block in ir.blocks.values_mut() {
+            let block_level = code_level(block, level);
+            changed += inline_calls(block, block_level, &bodies, &mut inlined);
+        }
+
+        if changed == 0 {
+            break;
+        }
+
+        replaced += changed;
+    }
+
+    // Inlined blocks may still be branched to, called from code at other levels, have their address
+    // taken or be run into by the block before them, possibly only by other inlined blocks, so this
+    // repeats until none is removed
+    loop {
+        let unused = inlined.iter().find(|name| {
+            !refers_to(&ir.ir, name) && !is_run_into(&ir.blocks, name)
+                && !ir.blocks.iter().any(|(other, block)| other != *name && refers_to(block, name))
+        }).cloned();
+
+        match unused {
+            Some(name) => {
+                ir.blocks.remove(&name);
+                inlined.remove(&name);
+            },
+            None => return replaced
+        }
+    }
+}
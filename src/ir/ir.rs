@@ -12,7 +12,7 @@ use tree::Tree;
 use error::*;
 use utils::*;
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Ir {
@@ -24,6 +24,8 @@ pub enum Ir {
     RegWrite(Register, Address),
     RegCopy(Register, Address),
     RegMem(Register, Address),
+    // Copies the second register to the first
+    RegReg(Register, Register),
     Add,
     Sub,
     Mul,
@@ -46,10 +48,24 @@ pub enum Ir {
     Raw(Vec<i32>)
 }
 
+impl Ir {
+    // The addresses the instruction refers to
+    pub fn addresses(&self) -> Vec<&Address> {
+        match *self {
+            Ir::Write(ref a, ref b) | Ir::Copy(ref a, ref b) | Ir::IndirWrite(ref a, ref b) |
+            Ir::IndirCopy(ref a, ref b) | Ir::IndirCopy3(ref a, ref b) => vec![a, b],
+            Ir::RegWrite(_, ref a) | Ir::RegCopy(_, ref a) | Ir::RegMem(_, ref a) |
+            Ir::Branch(ref a) | Ir::CondBranch(ref a) | Ir::IndirBranch(ref a) | Ir::Call(ref a) => vec![a],
+            _ => Vec::new()
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct IrResult {
     pub ir: Vec<Ir>,
-    pub blocks: HashMap<String, Vec<Ir>>,
+    // Sorted by name, which is the order compile_ir places them in
+    pub blocks: BTreeMap<String, Vec<Ir>>,
     pub address: Address,
     pub var_addr: Address,
     pub register: Option<Register>,
@@ -59,7 +75,7 @@ pub struct IrResult {
 
 pub fn build_ir(tree: TokenWrapper, temp_id: i32) -> Result<IrResult, BlocksError> {
     let mut result = Vec::new();
-    let mut blocks = HashMap::new();
+    let mut blocks = BTreeMap::new();
     let mut address = Address::Static(-1);
     let mut var_addr = Address::Static(-1);
     let mut register = None;
@@ -109,7 +125,6 @@ pub fn build_ir(tree: TokenWrapper, temp_id: i32) -> Result<IrResult, BlocksErro
 
                 if let Address::Static(i) = rhs_addr {
                     if i < 0 {
-                        println!("foo");
                         return Err(BlocksError::new(ErrorKind::InvalidAddress, Token::Other(format!("{}", i))));
                     }
                 } else if let Address::Static(i) = lhs_addr {
@@ -188,7 +203,9 @@ pub fn build_ir(tree: TokenWrapper, temp_id: i32) -> Result<IrResult, BlocksErro
             blocks.insert(name, ir);
         },
         TokenWrapper::Tree(Tree::Compare(operator)) => {
-            result.append(&mut build_ir(*operator, 0)?.ir);
+            // Operands are loaded through __temp_1__, so the results of operators are kept in
+            // later temps, like in assignments
+            result.append(&mut build_ir(*operator, 1)?.ir);
         },
         TokenWrapper::Tree(Tree::Less(lhs, rhs)) => {
             insert_operator(*lhs, *rhs, &mut result, Ir::Less, get_temp_id(temp_id))?;
@@ -278,7 +295,7 @@ pub fn build_ir(tree: TokenWrapper, temp_id: i32) -> Result<IrResult, BlocksErro
             register = Some(reg);
         },
         TokenWrapper::Tree(Tree::Goto(item)) => {
-            let mut ir = build_ir(*item.clone(), 0)?;
+            let mut ir = build_ir(*item.clone(), 1)?;
             result.append(&mut ir.ir);
 
             let addr = if let TokenWrapper::Token(Token::Identifier(ident)) = *item {
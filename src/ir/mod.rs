@@ -1,5 +1,13 @@
 mod ir;
-mod optimizer;
+mod cfg;
+mod regalloc;
+mod peephole;
+mod inline;
+mod dead_blocks;
 
 pub use self::ir::*;
-pub use self::optimizer::*;
+pub use self::cfg::*;
+pub use self::regalloc::*;
+pub use self::peephole::*;
+pub use self::inline::*;
+pub use self::dead_blocks::*;
@@ -0,0 +1,241 @@
+// Peephole optimization of the code compile_ir emits.
+// Runs on the IR of the main code and each symbol block in the order compile_ir places them, so
+// the instructions are exactly those that become machine code. Branch targets are still names at
+// this point, and symbol blocks are only given addresses afterwards, so removing an instruction
+// moves everything placed after it without breaking any branch.
+//
+// Each rule looks at a short window of instructions and gives what to replace it with. Rules are
+// applied until none matches. Only variables are assumed to behave like memory: reading or writing
+// a fixed address may talk to a device, so it is never removed, and neither is an access to a
+// variable marked with `?volatile`.
+//
+// Code is skipped if its level, set by the `opt` tags build_module leaves in the IR, has no peephole
+// pass.
+
+use compile_utils::get_code_size;
+use ir::{Ir, IrResult};
+use opt::{OptLevel, parse_level_tag};
+use utils::{Address, Register};
+
+use std::collections::HashSet;
+
+pub struct Rule {
+    pub name: &'static str,
+    // Number of instructions the rule looks at
+    pub window: usize,
+    // Returns the instructions that replace the window, if the rule applies
+    pub apply: fn(&[Ir], &Context) -> Option<Vec<Ir>>
+}
+
+// What is known about the code around a window
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Context {
+    // The symbol blocks placed at the address right after the window
+    pub next: Vec<String>,
+    // Variables marked with `?volatile`
+    pub volatile: HashSet<String>
+}
+
+pub const RULES: &'static [Rule] = &[
+    Rule { name: "store-after-load", window: 2, apply: store_after_load },
+    Rule { name: "load-after-store", window: 2, apply: load_after_store },
+    Rule { name: "overwritten-register", window: 2, apply: overwritten_register },
+    Rule { name: "self-move", window: 1, apply: self_move },
+    Rule { name: "branch-to-next", window: 1, apply: branch_to_next }
+];
+
+// Registers that hold values and nothing else, unlike the program counter and segment registers
+fn is_plain(reg: &Register) -> bool {
+    matches!(*reg, Register::Int1 | Register::Int2 | Register::Int3 | Register::Int4 | Register::Flag | Register::Accum)
+}
+
+// Whether accesses to an address can be removed
+fn is_memory(addr: &Address, context: &Context) -> bool {
+    match *addr {
+        Address::Variable(ref name) => !context.volatile.contains(name),
+        Address::Static(_) => false
+    }
+}
+
+// `RegCopy r x` then `RegMem r x` stores the value x already has
+fn store_after_load(ir: &[Ir], context: &Context) -> Option<Vec<Ir>> {
+    match (&ir[0], &ir[1]) {
+        (Ir::RegCopy(r, a), Ir::RegMem(s, b)) if r == s && a == b && is_plain(r) && is_memory(a, context) => {
+            Some(vec![ir[0].clone()])
+        },
+        _ => None
+    }
+}
+
+// `RegMem r x` then `RegCopy r x` loads the value r already has
+fn load_after_store(ir: &[Ir], context: &Context) -> Option<Vec<Ir>> {
+    match (&ir[0], &ir[1]) {
+        (Ir::RegMem(r, a), Ir::RegCopy(s, b)) if r == s && a == b && is_plain(r) && is_memory(a, context) => {
+            Some(vec![ir[0].clone()])
+        },
+        _ => None
+    }
+}
+
+// A register written twice in a row only keeps the second value, unless the second write reads it
+// Loads are kept, since they declare the variable they read
+fn overwritten_register(ir: &[Ir], _: &Context) -> Option<Vec<Ir>> {
+    let first = match ir[0] {
+        Ir::RegWrite(ref r, _) => r,
+        Ir::RegReg(ref r, _) => r,
+        _ => return None
+    };
+
+    let overwritten = match ir[1] {
+        Ir::RegWrite(ref r, _) | Ir::RegCopy(ref r, _) => r == first,
+        Ir::RegReg(ref r, ref s) => r == first && s != first,
+        _ => false
+    };
+
+    if overwritten && is_plain(first) {
+        Some(vec![ir[1].clone()])
+    } else {
+        None
+    }
+}
+
+fn self_move(ir: &[Ir], _: &Context) -> Option<Vec<Ir>> {
+    match ir[0] {
+        Ir::RegReg(ref r, ref s) if r == s && is_plain(r) => Some(Vec::new()),
+        _ => None
+    }
+}
+
+// A branch to the address right after it goes where the code would have gone anyway
+fn branch_to_next(ir: &[Ir], context: &Context) -> Option<Vec<Ir>> {
+    match ir[0] {
+        Ir::Branch(Address::Variable(ref name)) |
+        Ir::CondBranch(Address::Variable(ref name)) if context.next.contains(name) => Some(Vec::new()),
+        _ => None
+    }
+}
+
+// Applies the rules to one piece of code until none matches, returning how many times they applied
+// `context.next` is the symbol blocks placed right after the code
+pub fn optimize_code(ir: &mut Vec<Ir>, context: &Context, rules: &[Rule]) -> usize {
+    let mut applied = 0;
+    let mut i = 0;
+    let inside = Context {
+        next: Vec::new(),
+        volatile: context.volatile.clone()
+    };
+
+    while i < ir.len() {
+        let replaced = rules.iter().filter(|r| i + r.window <= ir.len()).filter_map(|rule| {
+            let context = if i + rule.window == ir.len() { context } else { &inside };
+
+            (rule.apply)(&ir[i..i + rule.window], context).map(|new| (rule.window, new))
+        }).next();
+
+        match replaced {
+            Some((window, new)) => {
+                ir.splice(i..i + window, new);
+                applied += 1;
+
+                // The replacement may complete a pattern that starts a little earlier
+                i = i.saturating_sub(1);
+            },
+            None => i += 1
+        }
+    }
+
+    applied
+}
+
+// Optimizes the parts of some code whose level has the peephole pass
+// Each `opt` tag sets the level of the code after it, and code before any uses `level`
+fn optimize_levels(ir: &mut Vec<Ir>, level: OptLevel, context: &Context) -> usize {
+    let mut parts = vec![(level, Vec::new())];
+
+    for item in ir.drain(..) {
+        if let Ir::Tag(ref key, ref value) = item {
+            if key == "opt" {
+                // compile_ir reports invalid levels
+                let level = parse_level_tag(value).unwrap_or(level);
+                parts.push((level, Vec::new()));
+            }
+        }
+
+        parts.last_mut().unwrap().1.push(item);
+    }
+
+    let last = parts.len() - 1;
+    let mut applied = 0;
+
+    for (i, (level, mut part)) in parts.into_iter().enumerate() {
+        if level.passes().peephole {
+            // Only the last part is followed by the code after this
+            applied += if i == last {
+                optimize_code(&mut part, context, RULES)
+            } else {
+                optimize_code(&mut part, &Context { next: Vec::new(), ..context.clone() }, RULES)
+            };
+        }
+
+        ir.append(&mut part);
+    }
+
+    applied
+}
+
+// Runs the peephole rules over a whole program compiled at `level`, returning how many times they
+// applied
+// Symbol blocks are visited in the order compile_ir places them, which is the order of their names
+pub fn peephole(ir: &mut IrResult, level: OptLevel) -> usize {
+    let order = ir.blocks.keys().cloned().collect::<Vec<_>>();
+    let mut volatile = HashSet::new();
+    let mut applied = 0;
+
+    for item in ir.ir.iter().chain(ir.blocks.values().flat_map(|b| b.iter())) {
+        if let Ir::Tag(ref key, ref value) = *item {
+            if key == "volatile" {
+                volatile.insert(value.clone());
+            }
+        }
+    }
+
+    // Removing code can leave a symbol block empty, which puts the blocks after it at the end of the
+    // one before, so this repeats until nothing changes
+    loop {
+        let mut changed = 0;
+
+        for (i, name) in order.iter().enumerate() {
+            // Every block from the next one up to the first one with code starts at the same address
+            let mut next = Vec::new();
+
+            for following in &order[i + 1..] {
+                next.push(following.clone());
+
+                if get_code_size(&ir.blocks[following]) > 0 {
+                    break;
+                }
+            }
+
+            let context = Context {
+                next: next,
+                volatile: volatile.clone()
+            };
+
+            changed += optimize_levels(ir.blocks.get_mut(name).unwrap(), level, &context);
+        }
+
+        // The main code is followed by the cleanup code, which no branch can name
+        let context = Context {
+            next: Vec::new(),
+            volatile: volatile.clone()
+        };
+
+        changed += optimize_levels(&mut ir.ir, level, &context);
+
+        if changed == 0 {
+            return applied;
+        }
+
+        applied += changed;
+    }
+}
@@ -0,0 +1,289 @@
+// Register allocation.
+// The IR generator keeps every intermediate value in a `__temp_N__` variable in memory, so each one
+// costs a store, a load and a word of the data section. This pass moves temps that are written and
+// read within a few instructions into the general registers, and leaves (spills) the rest in memory.
+//
+// Only straight-line code is considered: a temp that is live across an instruction that transfers
+// control or runs inline machine code stays in memory. Int1 and Int2 are used by arithmetic, so they
+// are only chosen where their values are not needed. Int3 and Int4 are never used by the compiler,
+// so they are tried first, unless the program refers to them itself.
+
+use ir::{Ir, IrResult};
+use opt::{OptLevel, code_level};
+use utils::{Address, Register};
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+// Registers temps may be assigned to, in order of preference
+const CANDIDATES: &'static [Register] = &[Register::Int3, Register::Int4, Register::Int1, Register::Int2];
+
+fn bit(reg: &Register) -> u32 {
+    1 << (reg.clone() as u32)
+}
+
+fn temp_name(addr: &Address) -> Option<&str> {
+    match *addr {
+        Address::Variable(ref name) if name.starts_with("__temp_") => Some(name),
+        _ => None
+    }
+}
+
+fn is_barrier(ir: &Ir) -> bool {
+    matches!(*ir, Ir::Branch(_) | Ir::CondBranch(_) | Ir::IndirBranch(_) | Ir::Call(_) | Ir::Return | Ir::Raw(_) | Ir::Tag(..))
+}
+
+// Registers an instruction reads, including the operands of arithmetic
+// Control may leave at a barrier with arguments in Int1 and Int2, so they count as read there
+fn reads(ir: &Ir) -> u32 {
+    match *ir {
+        Ir::Add | Ir::Sub | Ir::Mul | Ir::Div | Ir::Equals | Ir::Less | Ir::Greater | Ir::LessEqual |
+        Ir::GreaterEqual | Ir::Or | Ir::And | Ir::Xor => bit(&Register::Int1) | bit(&Register::Int2),
+        Ir::Not => bit(&Register::Int1),
+        Ir::RegMem(ref reg, _) => bit(reg),
+        Ir::RegReg(_, ref reg) => bit(reg),
+        ref ir if is_barrier(ir) => bit(&Register::Int1) | bit(&Register::Int2),
+        _ => 0
+    }
+}
+
+fn writes(ir: &Ir) -> u32 {
+    match *ir {
+        Ir::RegWrite(ref reg, _) | Ir::RegCopy(ref reg, _) | Ir::RegReg(ref reg, _) => bit(reg),
+        // Inline code may write any register
+        Ir::Raw(_) => !0,
+        _ => 0
+    }
+}
+
+// How an instruction uses a temp
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Role {
+    // Writes or reads the temp in a way that has a register form
+    Def,
+    Use,
+    // Writes or reads the temp in a way that needs memory, such as through a pointer
+    MemDef,
+    MemUse,
+    // Takes the address of the temp, so it must always stay in memory
+    Address
+}
+
+type Operands<'a> = Vec<(&'a Address, Role)>;
+
+// The temps an instruction refers to, with reads before writes
+fn temps(ir: &Ir) -> Vec<(&str, Role)> {
+    let (reads, writes): (Operands, Operands) = match *ir {
+        Ir::Write(ref a, ref b) => (vec![(b, Role::Address)], vec![(a, Role::Def)]),
+        Ir::Copy(ref a, ref b) => (vec![(b, Role::Use)], vec![(a, Role::Def)]),
+        Ir::IndirWrite(ref a, ref b) => (vec![(a, Role::MemUse), (b, Role::Address)], vec![]),
+        Ir::IndirCopy(ref a, ref b) => (vec![(a, Role::MemUse), (b, Role::MemUse)], vec![]),
+        Ir::IndirCopy3(ref a, ref b) => (vec![(b, Role::MemUse)], vec![(a, Role::MemDef)]),
+        Ir::RegWrite(_, ref a) => (vec![(a, Role::Address)], vec![]),
+        Ir::RegCopy(_, ref a) => (vec![(a, Role::Use)], vec![]),
+        Ir::RegMem(_, ref a) => (vec![], vec![(a, Role::Def)]),
+        Ir::Branch(ref a) | Ir::CondBranch(ref a) | Ir::IndirBranch(ref a) | Ir::Call(ref a) => (vec![(a, Role::Address)], vec![]),
+        _ => (vec![], vec![])
+    };
+
+    reads.into_iter().chain(writes).filter_map(|(addr, role)| temp_name(addr).map(|n| (n, role))).collect()
+}
+
+// A value of a temp, from the instruction that writes it to the last one that reads it
+struct Range {
+    def: usize,
+    last_use: Option<usize>,
+    in_register: bool
+}
+
+// Finds temps that must stay in memory everywhere: those whose address is taken, and those read
+// before being written in some stretch of straight-line code, which means their value comes from
+// across a branch, a call, or another symbol block
+fn pinned_temps(functions: &[&Vec<Ir>]) -> HashSet<String> {
+    let mut pinned = HashSet::new();
+
+    for ir in functions {
+        let mut written = HashSet::new();
+
+        for item in ir.iter() {
+            if is_barrier(item) {
+                written.clear();
+            }
+
+            for (name, role) in temps(item) {
+                match role {
+                    Role::Address => { pinned.insert(name.to_string()); },
+                    Role::Use | Role::MemUse if !written.contains(name) => { pinned.insert(name.to_string()); },
+                    Role::Def | Role::MemDef => { written.insert(name); },
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pinned
+}
+
+fn live_ranges<'a>(ir: &'a [Ir], pinned: &HashSet<String>) -> Vec<(&'a str, Range)> {
+    let mut ranges: Vec<(&str, Range)> = Vec::new();
+    let mut open: HashMap<&str, usize> = HashMap::new();
+
+    for (i, item) in ir.iter().enumerate() {
+        if is_barrier(item) {
+            open.clear();
+        }
+
+        for (name, role) in temps(item) {
+            match role {
+                Role::Use | Role::MemUse => if let Some(&r) = open.get(name) {
+                    ranges[r].1.last_use = Some(i);
+                    ranges[r].1.in_register &= role == Role::Use;
+                },
+                Role::Def | Role::MemDef => {
+                    open.insert(name, ranges.len());
+                    ranges.push((name, Range {
+                        def: i,
+                        last_use: None,
+                        in_register: role == Role::Def && !pinned.contains(name)
+                    }));
+                },
+                Role::Address => {}
+            }
+        }
+    }
+
+    ranges
+}
+
+// Rewrites an instruction with the registers chosen for the temp it reads and the temp it writes
+fn rewrite(item: Ir, used: Option<&Register>, defined: Option<&Register>) -> Ir {
+    match (item, used, defined) {
+        (Ir::Write(_, data), _, Some(d)) => Ir::RegWrite(d.clone(), data),
+        (Ir::Copy(_, _), Some(u), Some(d)) => Ir::RegReg(d.clone(), u.clone()),
+        (Ir::Copy(_, b), None, Some(d)) => Ir::RegCopy(d.clone(), b),
+        (Ir::Copy(a, _), Some(u), None) => Ir::RegMem(u.clone(), a),
+        (Ir::RegMem(reg, _), _, Some(d)) => Ir::RegReg(d.clone(), reg),
+        (Ir::RegCopy(reg, _), Some(u), _) => Ir::RegReg(reg, u.clone()),
+        (item, _, _) => item
+    }
+}
+
+// Moves the temps of straight-line code into registers, returning how many ranges were moved
+pub fn allocate_function(ir: &mut Vec<Ir>, pinned: &HashSet<String>, available: &[Register]) -> usize {
+    let n = ir.len();
+
+    // Registers whose current value is still needed before each instruction
+    let mut live = vec![0; n + 1];
+
+    for i in (0..n).rev() {
+        live[i] = reads(&ir[i]) | (live[i + 1] & !writes(&ir[i]));
+    }
+
+    let mut used = vec![None; n];
+    let mut defined = vec![None; n];
+    let mut free_from = vec![0; available.len()];
+    let mut moved = 0;
+
+    for (name, range) in live_ranges(ir, pinned) {
+        let last_use = match range.last_use {
+            Some(u) if range.in_register => u,
+            _ => continue
+        };
+
+        // A temp that is only loaded into a register can share that register
+        let preferred = match ir[last_use] {
+            Ir::RegCopy(ref reg, _) => available.iter().position(|r| r == reg),
+            _ => None
+        };
+
+        let fits = |r: usize| {
+            let b = bit(&available[r]);
+
+            free_from[r] <= range.def &&
+            (range.def + 1..last_use + 1).all(|i| live[i] & b == 0) &&
+            (range.def + 1..last_use).all(|i| writes(&ir[i]) & b == 0)
+        };
+
+        let choice = preferred.into_iter().chain(0..available.len()).find(|&r| fits(r));
+
+        if let Some(r) = choice {
+            let reg = &available[r];
+
+            defined[range.def] = Some(reg.clone());
+
+            // Every read of the temp between its definition and last use belongs to this range
+            for i in range.def + 1..last_use + 1 {
+                if temps(&ir[i]).iter().any(|&(t, role)| t == name && role == Role::Use) {
+                    used[i] = Some(reg.clone());
+                }
+            }
+
+            free_from[r] = last_use;
+            moved += 1;
+        }
+    }
+
+    let old = mem::take(ir);
+
+    for (i, item) in old.into_iter().enumerate() {
+        match rewrite(item, used[i].as_ref(), defined[i].as_ref()) {
+            Ir::RegReg(ref a, ref b) if a == b => {},
+            item => ir.push(item)
+        }
+    }
+
+    moved
+}
+
+// Allocates registers in the main code and every symbol block
+pub fn allocate_registers(ir: &mut IrResult) {
+    allocate_registers_if(ir, |_| true);
+}
+
+// Like allocate_registers, but only in the code whose level has the pass, for a program linked at
+// `level`
+// The main code is split at its `opt` tags, which mark code built at other levels, and a symbol
+// block has the level of its first `opt` tag
+pub fn allocate_registers_at(ir: &mut IrResult, level: OptLevel) {
+    allocate_registers_if(ir, |code| code_level(code, level).passes().registers);
+}
+
+// Allocates registers in the pieces of code `enabled` accepts
+// Pieces of the main code start at `opt` tags, which are barriers, so no temp is live across them
+fn allocate_registers_if<F: Fn(&[Ir]) -> bool>(ir: &mut IrResult, enabled: F) {
+    let mut functions = vec![&ir.ir];
+    functions.extend(ir.blocks.values());
+
+    let pinned = pinned_temps(&functions);
+
+    // Int3 and Int4 are only free if the program never refers to them
+    let mentioned = functions.iter().flat_map(|f| f.iter()).fold(0, |acc, i| acc | reads(i) | writes(i));
+    let available = CANDIDATES.iter()
+                              .filter(|r| (**r != Register::Int3 && **r != Register::Int4) || mentioned & bit(r) == 0)
+                              .cloned()
+                              .collect::<Vec<_>>();
+
+    let mut parts = vec![Vec::new()];
+
+    for item in mem::take(&mut ir.ir) {
+        if matches!(item, Ir::Tag(ref key, _) if key == "opt") {
+            parts.push(Vec::new());
+        }
+
+        parts.last_mut().unwrap().push(item);
+    }
+
+    for mut part in parts {
+        if enabled(&part) {
+            allocate_function(&mut part, &pinned, &available);
+        }
+
+        ir.ir.append(&mut part);
+    }
+
+    for block in ir.blocks.values_mut() {
+        if enabled(block) {
+            allocate_function(block, &pinned, &available);
+        }
+    }
+}
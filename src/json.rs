@@ -0,0 +1,274 @@
+// A minimal JSON representation, used for machine readable output.
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    // Fields are kept in insertion order so output is stable
+    Object(Vec<(String, Json)>)
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn string(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+}
+
+fn write_escaped(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?
+        }
+    }
+
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(ref s) => write_escaped(f, s),
+            Json::Array(ref items) => {
+                write!(f, "[")?;
+
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+
+                    write!(f, "{}", item)?;
+                }
+
+                write!(f, "]")
+            },
+            Json::Object(ref fields) => {
+                write!(f, "{{")?;
+
+                for (i, &(ref key, ref value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+
+                    write_escaped(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl Json {
+    pub fn parse(s: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            chars: s.chars().collect(),
+            pos: 0
+        };
+
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+
+        if parser.pos < parser.chars.len() {
+            return Err(format!("Trailing characters at {}", parser.pos));
+        }
+
+        Ok(value)
+    }
+
+    // Looks up a field of an object, returning Null if it is missing or this is not an object
+    pub fn get(&self, key: &str) -> &Json {
+        const NULL: &'static Json = &Json::Null;
+
+        if let Json::Object(ref fields) = *self {
+            fields.iter().find(|f| f.0 == key).map(|f| &f.1).unwrap_or(NULL)
+        } else {
+            NULL
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        if let Json::String(ref s) = *self { Some(s) } else { None }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        if let Json::Number(n) = *self { Some(n) } else { None }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        if let Json::Array(ref items) = *self { Some(items) } else { None }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = self.chars.get(self.pos).cloned().ok_or("Unexpected end of input".to_string())?;
+        self.pos += 1;
+
+        Ok(c)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        for c in expected.chars() {
+            if self.next()? != c {
+                return Err(format!("Expected `{}` at {}", expected, self.pos - 1));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+
+        match self.chars.get(self.pos).cloned() {
+            Some('n') => self.expect("null").map(|_| Json::Null),
+            Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+            Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some('"') => self.parse_string().map(Json::String),
+            Some('[') => {
+                let mut items = Vec::new();
+                self.pos += 1;
+                self.skip_whitespace();
+
+                if self.chars.get(self.pos) == Some(&']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+
+                loop {
+                    items.push(self.parse_value()?);
+                    self.skip_whitespace();
+
+                    match self.next()? {
+                        ',' => continue,
+                        ']' => return Ok(Json::Array(items)),
+                        c => return Err(format!("Unexpected `{}` at {}", c, self.pos - 1))
+                    }
+                }
+            },
+            Some('{') => {
+                let mut fields = Vec::new();
+                self.pos += 1;
+                self.skip_whitespace();
+
+                if self.chars.get(self.pos) == Some(&'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+
+                loop {
+                    self.skip_whitespace();
+                    let key = self.parse_string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    fields.push((key, self.parse_value()?));
+                    self.skip_whitespace();
+
+                    match self.next()? {
+                        ',' => continue,
+                        '}' => return Ok(Json::Object(fields)),
+                        c => return Err(format!("Unexpected `{}` at {}", c, self.pos - 1))
+                    }
+                }
+            },
+            Some(c) if c == '-' || c.is_digit(10) => self.parse_number(),
+            Some(c) => Err(format!("Unexpected `{}` at {}", c, self.pos)),
+            None => Err("Unexpected end of input".to_string())
+        }
+    }
+
+    // Fractions and exponents are accepted but truncated, since only integers are needed
+    fn parse_number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+
+        while self.pos < self.chars.len() && (self.chars[self.pos].is_digit(10) ||
+                                              "+-.eE".contains(self.chars[self.pos])) {
+            self.pos += 1;
+        }
+
+        let text = self.chars[start..self.pos].iter().cloned().collect::<String>();
+
+        if let Ok(n) = text.parse::<i64>() {
+            Ok(Json::Number(n))
+        } else if let Ok(n) = text.parse::<f64>() {
+            Ok(Json::Number(n as i64))
+        } else {
+            Err(format!("Invalid number `{}` at {}", text, start))
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        let mut result = String::new();
+        self.expect("\"")?;
+
+        loop {
+            match self.next()? {
+                '"' => return Ok(result),
+                '\\' => match self.next()? {
+                    'n' => result.push('\n'),
+                    'r' => result.push('\r'),
+                    't' => result.push('\t'),
+                    'b' => result.push('\u{8}'),
+                    'f' => result.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.parse_hex()?;
+
+                        // Surrogate pairs encode characters outside the basic multilingual plane
+                        if code >= 0xd800 && code < 0xdc00 {
+                            self.expect("\\u")?;
+                            let low = self.parse_hex()?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                        }
+
+                        result.push(::std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                    },
+                    c => result.push(c)
+                },
+                c => result.push(c)
+            }
+        }
+    }
+
+    fn parse_hex(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+
+        for _ in 0..4 {
+            let c = self.next()?;
+            code = code * 16 + c.to_digit(16).ok_or(format!("Invalid escape at {}", self.pos - 1))?;
+        }
+
+        Ok(code)
+    }
+}
@@ -0,0 +1,270 @@
+// Memory layouts.
+// A layout says where each section of a program goes in memory. It is written in a small language
+// with one directive per line, and `//` comments:
+//
+//   section setup               the code that sets up the segment registers
+//   section data at 1024        variables
+//   section text align 16       symbol blocks
+//   section main                the main code, followed by the cleanup code
+//   reserve 65520 16            a region that nothing may be placed in, given by start and size
+//
+// Sections are placed in the order they are listed, and every section must be listed once. A
+// section without `at` starts where the previous one ended, rounded up to its alignment. The setup
+// section must be first and at address 0, because that is where the machine starts running.
+// Gaps between sections are filled with zeros, and every section must end within the emulator's
+// memory.
+
+use compile::{cleanup, segment_setup, setup_size};
+use emulator::DEFAULT_MEMORY_SIZE;
+use error::*;
+use token::Token;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionKind {
+    Setup,
+    Data,
+    Text,
+    Main
+}
+
+impl SectionKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SectionKind::Setup => "setup",
+            SectionKind::Data => "data",
+            SectionKind::Text => "text",
+            SectionKind::Main => "main"
+        }
+    }
+}
+
+const SECTIONS: &'static [SectionKind] = &[SectionKind::Setup, SectionKind::Data, SectionKind::Text, SectionKind::Main];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionRule {
+    pub kind: SectionKind,
+    pub at: Option<usize>,
+    pub align: usize
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub sections: Vec<SectionRule>,
+    // Start and size of each reserved region
+    pub reserved: Vec<(usize, usize)>
+}
+
+// Where a section ended up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub kind: SectionKind,
+    pub start: usize,
+    pub size: usize
+}
+
+impl Placement {
+    pub fn end(&self) -> usize {
+        self.start + self.size
+    }
+
+    fn overlaps(&self, start: usize, size: usize) -> bool {
+        self.size > 0 && size > 0 && self.start < start + size && start < self.end()
+    }
+}
+
+// A program placed according to a layout
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub code: Vec<i32>,
+    pub placements: Vec<Placement>
+}
+
+impl Image {
+    pub fn section(&self, kind: SectionKind) -> Placement {
+        *self.placements.iter().find(|p| p.kind == kind).unwrap()
+    }
+
+    // The address variable addresses are relative to
+    pub fn data_base(&self) -> usize {
+        self.section(SectionKind::Data).start
+    }
+}
+
+// The layout `compile` uses: every section in order, with nothing in between
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout {
+            sections: SECTIONS.iter().map(|&kind| SectionRule {
+                kind: kind,
+                at: None,
+                align: 1
+            }).collect(),
+            reserved: Vec::new()
+        }
+    }
+}
+
+fn layout_error(message: String) -> BlocksError {
+    BlocksError::new(ErrorKind::LayoutError, Token::Other(message))
+}
+
+fn parse_number(word: Option<&str>, line: usize) -> Result<usize, String> {
+    let word = word.ok_or(format!("Line {}: expected a number", line))?;
+
+    let value = match word.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => word.parse()
+    };
+
+    value.map_err(|_| format!("Line {}: invalid number `{}`", line, word))
+}
+
+impl Layout {
+    pub fn parse(text: &str) -> Result<Layout, String> {
+        let mut layout = Layout {
+            sections: Vec::new(),
+            reserved: Vec::new()
+        };
+
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.find("//").map_or(line, |c| &line[..c]);
+            let mut words = line.split_whitespace();
+
+            match words.next() {
+                None => continue,
+                Some("section") => {
+                    let name = words.next().ok_or(format!("Line {}: expected a section name", line_number))?;
+                    let kind = *SECTIONS.iter()
+                                        .find(|k| k.name() == name)
+                                        .ok_or(format!("Line {}: unknown section `{}`", line_number, name))?;
+
+                    if layout.sections.iter().any(|s| s.kind == kind) {
+                        return Err(format!("Line {}: section `{}` is listed twice", line_number, name));
+                    }
+
+                    let mut rule = SectionRule {
+                        kind: kind,
+                        at: None,
+                        align: 1
+                    };
+
+                    while let Some(word) = words.next() {
+                        match word {
+                            "at" => rule.at = Some(parse_number(words.next(), line_number)?),
+                            "align" => rule.align = parse_number(words.next(), line_number)?,
+                            _ => return Err(format!("Line {}: unexpected `{}`", line_number, word))
+                        }
+                    }
+
+                    if rule.align == 0 {
+                        return Err(format!("Line {}: alignment must be at least 1", line_number));
+                    }
+
+                    layout.sections.push(rule);
+                },
+                Some("reserve") => {
+                    let start = parse_number(words.next(), line_number)?;
+                    let size = parse_number(words.next(), line_number)?;
+
+                    if let Some(word) = words.next() {
+                        return Err(format!("Line {}: unexpected `{}`", line_number, word));
+                    }
+
+                    if start.checked_add(size).is_none() {
+                        return Err(format!("Line {}: the reserved region does not fit in memory", line_number));
+                    }
+
+                    layout.reserved.push((start, size));
+                },
+                Some(word) => return Err(format!("Line {}: unknown directive `{}`", line_number, word))
+            }
+        }
+
+        for kind in SECTIONS {
+            if !layout.sections.iter().any(|s| s.kind == *kind) {
+                return Err(format!("Section `{}` is missing", kind.name()));
+            }
+        }
+
+        match layout.sections[0] {
+            SectionRule { kind: SectionKind::Setup, at: None, .. } |
+            SectionRule { kind: SectionKind::Setup, at: Some(0), .. } => Ok(layout),
+            _ => Err("The setup section must be listed first, at address 0".to_string())
+        }
+    }
+
+    // Finds where every section goes, given their sizes in the order of SectionKind, and checks
+    // that no two sections overlap each other or a reserved region
+    pub fn place(&self, sizes: [usize; 4]) -> Result<Vec<Placement>, BlocksError> {
+        let mut placements: Vec<Placement> = Vec::new();
+        let mut next = 0usize;
+
+        for rule in &self.sections {
+            let size = sizes[SECTIONS.iter().position(|k| *k == rule.kind).unwrap()];
+            let start = match rule.at {
+                Some(at) if at % rule.align != 0 => {
+                    return Err(layout_error(format!("section `{}` at {} is not aligned to {}",
+                                                    rule.kind.name(), at, rule.align)));
+                },
+                Some(at) => at,
+                None => next.div_ceil(rule.align).saturating_mul(rule.align)
+            };
+
+            let placement = Placement {
+                kind: rule.kind,
+                start: start,
+                size: size
+            };
+
+            // The image is allocated up to the end of the last section, so this also stops a typo in
+            // an address from allocating far more than the machine could use
+            if start.checked_add(size).is_none_or(|end| end > DEFAULT_MEMORY_SIZE) {
+                return Err(layout_error(format!("section `{}` does not fit in memory ({} words)",
+                                                rule.kind.name(), DEFAULT_MEMORY_SIZE)));
+            }
+
+            if let Some(other) = placements.iter().find(|p| p.overlaps(start, size)) {
+                return Err(layout_error(format!("sections `{}` ({}..{}) and `{}` ({}..{}) overlap",
+                                                other.kind.name(), other.start, other.end(),
+                                                rule.kind.name(), start, placement.end())));
+            }
+
+            if let Some(&(r, s)) = self.reserved.iter().find(|&&(r, s)| placement.overlaps(r, s)) {
+                return Err(layout_error(format!("section `{}` ({}..{}) overlaps the reserved region {}..{}",
+                                                rule.kind.name(), start, placement.end(), r, r + s)));
+            }
+
+            next = placement.end();
+            placements.push(placement);
+        }
+
+        Ok(placements)
+    }
+
+    // Places compiled code in memory
+    // `text` is the symbol section, and `main` is the main code without the cleanup code
+    pub fn build(&self, text: &[i32], main: &[i32], data_size: usize) -> Result<Image, BlocksError> {
+        let main_size = main.len() + cleanup().len();
+        let placements = self.place([setup_size(), data_size, text.len(), main_size])?;
+
+        let find = |kind| *placements.iter().find(|p| p.kind == kind).unwrap();
+        let (data, flow, main_start) = (find(SectionKind::Data), find(SectionKind::Text), find(SectionKind::Main));
+
+        let size = placements.iter().map(|p| p.end()).max().unwrap_or(0);
+        let mut code = vec![0; size];
+
+        let setup = segment_setup(data.start as i32, flow.start as i32 - data.start as i32,
+                                  main_start.start as i32 - flow.start as i32);
+
+        code[..setup.len()].copy_from_slice(&setup);
+        code[flow.start..flow.end()].copy_from_slice(text);
+        code[main_start.start..main_start.start + main.len()].copy_from_slice(main);
+        code[main_start.start + main.len()..main_start.end()].copy_from_slice(cleanup());
+
+        Ok(Image {
+            code: code,
+            placements: placements
+        })
+    }
+}
@@ -1,13 +1,28 @@
 #![feature(core_intrinsics)]
 #![feature(question_mark)]
 
-mod utils;
+pub mod utils;
 mod tests;
-mod error;
-mod token;
-mod tree;
+pub mod error;
+pub mod token;
+pub mod json;
+pub mod tree;
 mod compile_utils;
-mod ir;
+pub mod ir;
+pub mod ssa;
 pub mod compile;
+pub mod opt;
+pub mod cache;
+pub mod object;
+pub mod archive;
+pub mod layout;
+pub mod lsp;
+pub mod formatter;
+pub mod doc;
+pub mod emulator;
+pub mod debug_info;
+pub mod debugger;
+pub mod profile;
+pub mod golden;
 
 pub use self::compile::compile;
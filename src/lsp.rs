@@ -0,0 +1,364 @@
+// Language server for blocks source files.
+// Speaks the language server protocol over any reader and writer (stdio in `blocks-lsp`).
+// Every change reruns the compiler up to compile_ir, which is cheap enough for hand written files.
+// Documents are always synced in full.
+
+use token::*;
+use tree::build_token_tree;
+use ir::build_ir;
+use compile::{compile_ir, TAGS};
+use compile_utils::locate_error;
+use error::{BlocksError, Span};
+use utils::TokenWrapper;
+use json::Json;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// Completion item kinds from the specification
+const KIND_FUNCTION: i64 = 3;
+const KIND_VARIABLE: i64 = 6;
+const KIND_PROPERTY: i64 = 10;
+const KIND_KEYWORD: i64 = 14;
+const KIND_CONSTANT: i64 = 21;
+
+// What is known about a document after compiling it
+struct Analysis {
+    tokens: Vec<(Token, Span)>,
+    error: Option<BlocksError>,
+    vars: HashMap<String, i32>,
+    symbols: Vec<String>
+}
+
+fn analyze(source: &str) -> Analysis {
+    let mut analysis = Analysis {
+        tokens: build_tokens_spanned(source.to_string()),
+        error: None,
+        vars: HashMap::new(),
+        symbols: Vec::new()
+    };
+
+    let result = build_token_tree(source.to_string()).and_then(|tree| {
+        let ir = build_ir(TokenWrapper::Tree(tree), 0)?;
+        analysis.symbols = ir.blocks.keys().cloned().collect();
+
+        compile_ir(ir, &mut analysis.vars, &mut 0, &mut 0)
+    });
+
+    if let Err(e) = result {
+        analysis.error = Some(locate_error(e, source));
+    }
+
+    analysis
+}
+
+fn to_range(span: Span) -> Json {
+    let position = |line: usize, column: usize| Json::object(vec![
+        ("line", Json::Number(line as i64 - 1)),
+        ("character", Json::Number(column as i64 - 1))
+    ]);
+
+    Json::object(vec![
+        ("start", position(span.line_start, span.column_start)),
+        ("end", position(span.line_end, span.column_end))
+    ])
+}
+
+// Finds the token under an LSP position (0-based), including the position just after it
+fn token_at(tokens: &[(Token, Span)], position: &Json) -> Option<(Token, Span)> {
+    let line = position.get("line").as_i64()? as usize + 1;
+    let column = position.get("character").as_i64()? as usize + 1;
+
+    tokens.iter().find(|&&(ref t, s)| {
+        *t != Token::Null && s.line_start == line && s.column_start <= column && column <= s.column_end
+    }).cloned()
+}
+
+// Names declared by a keyword, such as symbol names or variables assigned with `set`
+fn declared_after(tokens: &[(Token, Span)], keyword: Token) -> Vec<(String, Span)> {
+    tokens.windows(2).filter_map(|w| match (&w[0].0, &w[1].0) {
+        (k, &Token::Identifier(ref ident)) if *k == keyword => Some((ident.clone(), w[1].1)),
+        _ => None
+    }).collect()
+}
+
+fn token_name(token: &Token) -> Option<String> {
+    match *token {
+        Token::Identifier(ref ident) => Some(ident.clone()),
+        Token::Register(ref reg) => Some(register_name(reg).to_string()),
+        _ => None
+    }
+}
+
+pub struct Server {
+    documents: HashMap<String, String>,
+    exited: bool
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server {
+            documents: HashMap::new(),
+            exited: false
+        }
+    }
+
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    // Handles a single message, returning the messages that should be sent back
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").as_str().unwrap_or("");
+        let params = message.get("params");
+        let id = message.get("id");
+
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("").to_string();
+
+        let result = match method {
+            "initialize" => Some(Json::object(vec![
+                ("capabilities", Json::object(vec![
+                    ("textDocumentSync", Json::Number(1)),
+                    ("definitionProvider", Json::Bool(true)),
+                    ("hoverProvider", Json::Bool(true)),
+                    ("completionProvider", Json::object(vec![
+                        ("triggerCharacters", Json::Array(vec![Json::string("$"), Json::string("?")]))
+                    ]))
+                ]))
+            ])),
+            "shutdown" => Some(Json::Null),
+            "exit" => {
+                self.exited = true;
+                return Vec::new();
+            },
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str().unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+
+                return vec![self.diagnostics(&uri)];
+            },
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").as_array().unwrap_or(&[]);
+
+                if let Some(text) = changes.last().and_then(|c| c.get("text").as_str()) {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+
+                return vec![self.diagnostics(&uri)];
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+
+                return vec![self.diagnostics(&uri)];
+            },
+            "textDocument/definition" => Some(self.definition(&uri, params.get("position"))),
+            "textDocument/hover" => Some(self.hover(&uri, params.get("position"))),
+            "textDocument/completion" => Some(self.completion(&uri)),
+            _ => None
+        };
+
+        // Notifications never get a response
+        if *id == Json::Null {
+            return Vec::new();
+        }
+
+        let response = match result {
+            Some(result) => ("result", result),
+            None => ("error", Json::object(vec![
+                ("code", Json::Number(METHOD_NOT_FOUND)),
+                ("message", Json::String(format!("Unknown method: {}", method)))
+            ]))
+        };
+
+        vec![Json::object(vec![
+            ("jsonrpc", Json::string("2.0")),
+            ("id", id.clone()),
+            response
+        ])]
+    }
+
+    fn analysis(&self, uri: &str) -> Analysis {
+        analyze(self.documents.get(uri).map(|s| s as &str).unwrap_or(""))
+    }
+
+    fn diagnostics(&self, uri: &str) -> Json {
+        let mut diagnostics = Vec::new();
+
+        if self.documents.contains_key(uri) {
+            if let Some(e) = self.analysis(uri).error {
+                let mut message = e.message();
+
+                if let Some(suggestion) = e.suggestion() {
+                    message = format!("{}\nhelp: {}", message, suggestion);
+                }
+
+                diagnostics.push(Json::object(vec![
+                    ("range", to_range(e.span().unwrap_or(Span::new((1, 1), (1, 1))))),
+                    ("severity", Json::Number(1)),
+                    ("code", Json::Number(e.code() as i64)),
+                    ("source", Json::string("blocks")),
+                    ("message", Json::String(message))
+                ]));
+            }
+        }
+
+        Json::object(vec![
+            ("jsonrpc", Json::string("2.0")),
+            ("method", Json::string("textDocument/publishDiagnostics")),
+            ("params", Json::object(vec![
+                ("uri", Json::string(uri)),
+                ("diagnostics", Json::Array(diagnostics))
+            ]))
+        ])
+    }
+
+    fn definition(&self, uri: &str, position: &Json) -> Json {
+        let analysis = self.analysis(uri);
+
+        let name = match token_at(&analysis.tokens, position) {
+            Some((Token::Identifier(ident), _)) => ident,
+            _ => return Json::Null
+        };
+
+        // Symbols take priority because `goto` and `call` refer to them by name
+        let mut declared = declared_after(&analysis.tokens, Token::Symbol);
+        declared.extend(declared_after(&analysis.tokens, Token::Assign));
+
+        match declared.into_iter().find(|d| d.0 == name) {
+            Some((_, span)) => Json::object(vec![
+                ("uri", Json::string(uri)),
+                ("range", to_range(span))
+            ]),
+            None => Json::Null
+        }
+    }
+
+    fn hover(&self, uri: &str, position: &Json) -> Json {
+        let analysis = self.analysis(uri);
+
+        let (token, span) = match token_at(&analysis.tokens, position) {
+            Some(t) => t,
+            None => return Json::Null
+        };
+
+        let name = match token_name(&token) {
+            Some(name) => name,
+            None => return Json::Null
+        };
+
+        let text = if let Token::Register(reg) = token {
+            format!("register `{}` (id {})", name, reg as i32)
+        } else if let Some(addr) = analysis.vars.get(&name) {
+            if analysis.symbols.contains(&name) {
+                format!("symbol `{}` at address {} of the symbol section", name, addr)
+            } else {
+                format!("variable `{}` at data address {}", name, addr)
+            }
+        } else {
+            return Json::Null;
+        };
+
+        Json::object(vec![
+            ("contents", Json::object(vec![
+                ("kind", Json::string("markdown")),
+                ("value", Json::String(text))
+            ])),
+            ("range", to_range(span))
+        ])
+    }
+
+    fn completion(&self, uri: &str) -> Json {
+        let analysis = self.analysis(uri);
+        let mut items = Vec::new();
+
+        {
+            let mut add = |label: &str, kind: i64| {
+                if !items.iter().any(|i: &Json| i.get("label").as_str() == Some(label)) {
+                    items.push(Json::object(vec![
+                        ("label", Json::string(label)),
+                        ("kind", Json::Number(kind))
+                    ]));
+                }
+            };
+
+            for keyword in KEYWORDS {
+                add(keyword, KIND_KEYWORD);
+            }
+
+            for reg in REGISTERS {
+                add(reg.0, KIND_CONSTANT);
+            }
+
+            for tag in TAGS {
+                add(tag, KIND_PROPERTY);
+            }
+
+            for symbol in declared_after(&analysis.tokens, Token::Symbol) {
+                add(&symbol.0, KIND_FUNCTION);
+            }
+
+            for var in declared_after(&analysis.tokens, Token::Assign) {
+                add(&var.0, KIND_VARIABLE);
+            }
+        }
+
+        Json::Array(items)
+    }
+}
+
+// Reads one message, returning None at the end of the input
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Json>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim();
+
+        if header.is_empty() {
+            break;
+        }
+
+        if header.to_lowercase().starts_with("content-length:") {
+            length = header["content-length:".len()..].trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.ok_or(io::Error::new(io::ErrorKind::InvalidData, "Missing Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    let body = String::from_utf8(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    Json::parse(&body).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+
+    output.flush()
+}
+
+// Runs the server until the client sends `exit` or closes the input
+pub fn run<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<()> {
+    let mut server = Server::new();
+
+    while let Some(message) = read_message(input)? {
+        for response in server.handle(&message) {
+            write_message(output, &response)?;
+        }
+
+        if server.exited() {
+            break;
+        }
+    }
+
+    Ok(())
+}
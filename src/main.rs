@@ -1,18 +1,628 @@
-// TODO: add file i/o and command line tool
-//       add tags and write some libraries
-//       add optimizations
+// TODO: add tags and write some libraries
 
 extern crate blocks;
 
-fn main() {
-    let prog = "
-        set #0 = 322;
+use blocks::error::BlocksError;
+use blocks::json::Json;
+use blocks::object::Object;
+use blocks::archive::{Archive, Member};
+use blocks::layout::Layout;
+use blocks::ir::RemovedBlock;
+use blocks::opt::OptLevel;
+use blocks::emulator::Machine;
+use blocks::emulator::devices::{Console, attach_standard_devices};
+use blocks::emulator::trace::{self, TraceRecord, TraceReader, TraceWriter};
+
+use std::env;
+use std::path::Path;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::process;
+
+// Stops tracing and profiling from running forever on programs that never halt
+const MAX_STEPS: u64 = 100000000;
+
+// Number of rows shown in each table of a profile report
+const PROFILE_ROWS: usize = 20;
+
+const USAGE: &'static str = "Usage:
+    blocks [options] [build options] <file> Compile a file
+    blocks [options] [build options] [--cache=DIR] <files>
+                                            Compile several files as one program, reusing the IR of
+                                            files that did not change since the last build in DIR
+    blocks object [options] [--output=F] <file>
+                                            Compile a file to an object (default <file>.obj)
+    blocks link [options] [--layout=F] [--output=F] <objects and archives>
+                                            Link objects into a program, printing it unless an output
+                                            file is given, with the archive members they need
+    blocks ar --output=F <objects>          Bundle objects into an archive
+    blocks fmt [options] [--check] <files>  Format files in place, or check that they are formatted
+    blocks doc [options] <files>            Print Markdown documentation for the symbols in files
+    blocks run [options] [-OL] <file>       Run a file on the emulator, with the console on stdin and stdout
+    blocks debug [options] [-O0] <file>     Run a file in the interactive debugger
+    blocks golden [--bless] <dirs>          Check the programs in directories against the expectations in
+                                            their headers, or update the headers with --bless
+    blocks cfg [options] [--dot] <file>     Print the control-flow graph of a file, or render it as
+                                            Graphviz DOT with unreachable blocks highlighted
+    blocks gdb [options] [-OL] [--port=N] <file>
+                                            Serve a file to a GDB client on a local port (default 1234)
+    blocks trace [options] [-OL] [--output=F] <file>
+                                            Run a file, writing an execution trace (default <file>.trace)
+    blocks profile [options] [-O0] [--trace=F] [--folded] <file>
+                                            Report where instructions are spent, running the file unless
+                                            a trace is given, or print folded stacks for flamegraphs
+
+Options:
+    --message-format=human|json             How to print errors
+
+Build options:
+    --layout=F                              Place the sections of the program as described in F
+    -O0|-O1|-O2|-O3|-Os                     Optimization level (default -O1), which `?opt` tags
+                                            override for a file or symbol block, also taken by run,
+                                            gdb and trace as -OL. debug and profile only take -O0,
+                                            since their debug information can't follow optimized code
+    --verbose                               Report the unreachable symbol blocks that were removed";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MessageFormat {
+    Human,
+    Json
+}
+
+struct Options {
+    format: MessageFormat,
+    flags: Vec<String>,
+    paths: Vec<String>
+}
+
+impl Options {
+    fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+
+    // Returns the value of a `--name=value` flag
+    fn value(&self, name: &str) -> Option<&str> {
+        self.flags.iter().filter_map(|f| {
+            if f.starts_with(name) && f[name.len()..].starts_with("=") {
+                Some(&f[name.len() + 1..])
+            } else {
+                None
+            }
+        }).next_back()
+    }
+}
+
+// Parses the arguments after the command name, exiting if a flag is not in `allowed`
+// Flags in `allowed` that end with `=` take a value
+fn parse_options(args: &[String], allowed: &[&str]) -> Options {
+    let mut options = Options {
+        format: MessageFormat::Human,
+        flags: Vec::new(),
+        paths: Vec::new()
+    };
+
+    for arg in args {
+        match arg as &str {
+            "--message-format=human" => options.format = MessageFormat::Human,
+            "--message-format=json" => options.format = MessageFormat::Json,
+            _ if allowed.contains(&(arg as &str)) => options.flags.push(arg.clone()),
+            _ if allowed.iter().any(|a| a.ends_with("=") && arg.starts_with(a)) => options.flags.push(arg.clone()),
+            _ if arg.starts_with("-") => exit_with_usage(),
+            _ => options.paths.push(arg.clone())
+        }
+    }
+
+    if options.paths.is_empty() {
+        exit_with_usage();
+    }
+
+    options
+}
+
+fn read_file(path: &str) -> io::Result<String> {
+    let mut source = String::new();
+    File::open(path)?.read_to_string(&mut source)?;
+
+    Ok(source)
+}
+
+fn read_file_or_exit(path: &str) -> String {
+    match read_file(path) {
+        Ok(s) => s,
+        Err(e) => {
+            writeln!(io::stderr(), "{}: {}", path, e).unwrap();
+            process::exit(1);
+        }
+    }
+}
+
+fn report(err: &BlocksError, path: &str, format: MessageFormat) {
+    match format {
+        MessageFormat::Human => writeln!(io::stderr(), "{}: {}", path, err),
+        MessageFormat::Json => writeln!(io::stderr(), "{}", err.to_json(path))
+    }.unwrap();
+}
+
+fn exit_with_usage() -> ! {
+    writeln!(io::stderr(), "{}", USAGE).unwrap();
+    process::exit(2);
+}
+
+fn read_layout_or_exit(options: &Options) -> Layout {
+    let path = match options.value("--layout") {
+        Some(p) => p,
+        None => return Layout::default()
+    };
+
+    Layout::parse(&read_file_or_exit(path)).unwrap_or_else(|e| {
+        writeln!(io::stderr(), "{}: {}", path, e).unwrap();
+        process::exit(1);
+    })
+}
+
+const LEVEL_FLAGS: &'static [&'static str] = &["-O0", "-O1", "-O2", "-O3", "-Os"];
+
+// The level from the last `-O` flag, or the default one
+fn opt_level(options: &Options) -> OptLevel {
+    options.flags.iter().filter_map(|f| OptLevel::from_flag(f)).last().unwrap_or_default()
+}
+
+fn build(options: &Options) {
+    let sources = options.paths.iter().map(|p| read_file_or_exit(p)).collect::<Vec<_>>();
+    let sources = sources.iter().map(|s| s as &str).collect::<Vec<_>>();
+    let mut cache = options.value("--cache").map(blocks::cache::Cache::new);
+
+    let layout = read_layout_or_exit(options);
+
+    match blocks::cache::compile_project_with_report(&sources, cache.as_mut(), &layout, opt_level(options)) {
+        Ok(((v, _), removed)) => {
+            if options.has_flag("--verbose") {
+                report_removed(&removed);
+            }
+
+            let code = v.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            println!("{}", code.join(" "));
+        },
+        Err((i, e)) => {
+            report(&e, &options.paths[i], options.format);
+            process::exit(1);
+        }
+    }
+}
+
+// Code words are 32 bits, so each is 4 bytes
+fn report_removed(removed: &[RemovedBlock]) {
+    let mut stderr = io::stderr();
+
+    for block in removed {
+        writeln!(stderr, "Removed unreachable symbol block `{}` ({} bytes)", block.name, block.size * 4).unwrap();
+    }
+
+    let total = removed.iter().map(|b| b.size).sum::<usize>();
+    let plural = if removed.len() == 1 { "" } else { "s" };
+
+    writeln!(stderr, "Removed {} unreachable symbol block{} ({} bytes)", removed.len(), plural, total * 4).unwrap();
+}
+
+fn object(options: &Options) {
+    if options.paths.len() != 1 {
+        exit_with_usage();
+    }
+
+    let path = &options.paths[0];
+    let source = read_file_or_exit(path);
+    let output = options.value("--output").map(|s| s.to_string()).unwrap_or(format!("{}.obj", path));
+
+    let object = match blocks::object::compile_object(&source) {
+        Ok(o) => o,
+        Err(e) => {
+            report(&e, path, options.format);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = File::create(&output).and_then(|mut f| writeln!(f, "{}", object.to_json())) {
+        writeln!(io::stderr(), "{}: {}", output, e).unwrap();
+        process::exit(1);
+    }
+}
+
+enum Input {
+    Object(Object),
+    Archive(Archive)
+}
+
+fn read_input_or_exit(path: &str) -> Input {
+    let source = read_file_or_exit(path);
+
+    let input = Json::parse(&source).and_then(|json| {
+        if json.get("archive_version") != &Json::Null {
+            Archive::from_json(&json).map(Input::Archive)
+        } else {
+            Object::from_json(&json).map(Input::Object)
+        }
+    });
+
+    input.unwrap_or_else(|e| {
+        writeln!(io::stderr(), "{}: {}", path, e).unwrap();
+        process::exit(1);
+    })
+}
+
+fn ar(options: &Options) {
+    let output = options.value("--output").unwrap_or_else(|| exit_with_usage());
+    let mut members = Vec::new();
 
-        raw `10 7 0 10 8 0 29 -1`;
-    ";
+    for path in &options.paths {
+        match read_input_or_exit(path) {
+            Input::Object(object) => members.push(Member {
+                name: Path::new(path).file_name().map_or(path.clone(), |s| s.to_string_lossy().into_owned()),
+                object: object
+            }),
+            Input::Archive(_) => {
+                writeln!(io::stderr(), "{}: Archives can't contain other archives", path).unwrap();
+                process::exit(1);
+            }
+        }
+    }
+
+    let archive = Archive::new(members).unwrap_or_else(|e| {
+        report(&e, output, options.format);
+        process::exit(1);
+    });
+
+    if let Err(e) = File::create(output).and_then(|mut f| writeln!(f, "{}", archive.to_json())) {
+        writeln!(io::stderr(), "{}: {}", output, e).unwrap();
+        process::exit(1);
+    }
+}
+
+fn link(options: &Options) {
+    let mut objects = Vec::new();
+    let mut archives = Vec::new();
+
+    for path in &options.paths {
+        match read_input_or_exit(path) {
+            Input::Object(o) => objects.push(o),
+            Input::Archive(a) => archives.push(a)
+        }
+    }
+
+    let objects = blocks::archive::select_members(&objects, &archives);
+
+    let code = match blocks::object::link_objects_with_layout(&objects, &read_layout_or_exit(options)) {
+        Ok((image, _)) => image.code.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(" "),
+        Err(e) => {
+            report(&e, "<link>", options.format);
+            process::exit(1);
+        }
+    };
+
+    match options.value("--output") {
+        Some(output) => if let Err(e) = File::create(output).and_then(|mut f| writeln!(f, "{}", code)) {
+            writeln!(io::stderr(), "{}: {}", output, e).unwrap();
+            process::exit(1);
+        },
+        None => println!("{}", code)
+    }
+}
+
+fn fmt(options: &Options) {
+    let check = options.has_flag("--check");
+    let mut failed = false;
+
+    for path in &options.paths {
+        let source = read_file_or_exit(path);
+
+        let formatted = match blocks::formatter::format(&source) {
+            Ok(s) => s,
+            Err(e) => {
+                report(&e, path, options.format);
+                failed = true;
+                continue;
+            }
+        };
+
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("Would reformat: {}", path);
+            failed = true;
+        } else if let Err(e) = File::create(path).and_then(|mut f| f.write_all(formatted.as_bytes())) {
+            writeln!(io::stderr(), "{}: {}", path, e).unwrap();
+            failed = true;
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn doc(options: &Options) {
+    let mut pages = Vec::new();
+
+    for path in &options.paths {
+        let source = read_file_or_exit(path);
+
+        match blocks::doc::build_docs(&source) {
+            Ok(items) => {
+                let title = Path::new(path).file_stem().map(|s| s.to_string_lossy().into_owned());
+                pages.push(blocks::doc::render_markdown(&title.unwrap_or(path.clone()), &items));
+            },
+            Err(e) => {
+                report(&e, path, options.format);
+                process::exit(1);
+            }
+        }
+    }
+
+    print!("{}", pages.join("\n"));
+}
+
+fn cfg(options: &Options) {
+    if options.paths.len() != 1 {
+        exit_with_usage();
+    }
+
+    let path = &options.paths[0];
+    let source = read_file_or_exit(path);
+
+    match blocks::compile::build_module(&source) {
+        Ok(ir) => {
+            let cfg = blocks::ir::Cfg::build(&ir);
+
+            if options.has_flag("--dot") {
+                print!("{}", cfg.to_dot());
+            } else {
+                print!("{}", cfg);
+            }
+        },
+        Err(e) => {
+            report(&e, path, options.format);
+            process::exit(1);
+        }
+    }
+}
+
+fn debug(options: &Options) {
+    require_unoptimized(options, "debug");
+
+    if options.paths.len() != 1 {
+        exit_with_usage();
+    }
+
+    let path = &options.paths[0];
+    let source = read_file_or_exit(path);
+
+    let mut debugger = match blocks::debugger::Debugger::from_source(&source) {
+        Ok(d) => d,
+        Err(e) => {
+            report(&e, path, options.format);
+            process::exit(1);
+        }
+    };
+
+    attach_standard_devices(&mut debugger.machine, Console::stdio());
+
+    println!("{}", debugger.location());
+
+    let stdin = io::stdin();
+
+    loop {
+        print!("(blocks) ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+
+        match line.trim() {
+            "quit" | "q" => break,
+            command => println!("{}", debugger.command(command))
+        }
+    }
+}
+
+// Exits if a command that needs debug information is asked for optimized code
+fn require_unoptimized(options: &Options, command: &str) {
+    if options.flags.iter().filter_map(|f| OptLevel::from_flag(f)).any(|level| level != OptLevel::O0) {
+        writeln!(io::stderr(), "`{}` only accepts -O0, since its debug information can't follow optimized code",
+                 command).unwrap();
+        process::exit(1);
+    }
+}
+
+fn gdb(options: &Options) {
+    let port = match options.value("--port").map(|p| p.parse::<u16>()) {
+        Some(Ok(p)) => p,
+        Some(Err(_)) => exit_with_usage(),
+        None => 1234
+    };
+
+    let code = compile_for_emulator(options);
+    let mut machine = Machine::new(&code);
+    attach_standard_devices(&mut machine, Console::stdio());
+
+    let mut stub = blocks::emulator::gdb::GdbStub::new(machine);
+
+    writeln!(io::stderr(), "Listening for a GDB client on 127.0.0.1:{}", port).unwrap();
+
+    if let Err(e) = blocks::emulator::gdb::serve(&mut stub, port) {
+        writeln!(io::stderr(), "{}", e).unwrap();
+        process::exit(1);
+    }
+}
+
+// Compiles a file at the level given by the options, like the build does
+fn compile_for_emulator(options: &Options) -> Vec<i32> {
+    if options.paths.len() != 1 {
+        exit_with_usage();
+    }
+
+    let path = &options.paths[0];
+    let source = read_file_or_exit(path);
+
+    match blocks::compile::compile_at(&source, opt_level(options)) {
+        Ok((code, _)) => code,
+        Err(e) => {
+            report(&e, path, options.format);
+            process::exit(1);
+        }
+    }
+}
+
+// Compiles a file without optimizations, with debug information
+fn compile_with_debug_info(options: &Options, command: &str) -> (Vec<i32>, blocks::debug_info::DebugInfo) {
+    require_unoptimized(options, command);
+
+    if options.paths.len() != 1 {
+        exit_with_usage();
+    }
+
+    let path = &options.paths[0];
+    let source = read_file_or_exit(path);
+
+    match blocks::debug_info::compile_with_debug_info(&source) {
+        Ok(result) => result,
+        Err(e) => {
+            report(&e, path, options.format);
+            process::exit(1);
+        }
+    }
+}
+
+// Runs a program, passing each executed instruction to `record`
+fn run_traced<F>(program: &[i32], mut record: F) where F: FnMut(TraceRecord) -> io::Result<()> {
+    let mut machine = Machine::new(program);
+    attach_standard_devices(&mut machine, Console::stdio());
+
+    for _ in 0..MAX_STEPS {
+        match trace::step_traced(&mut machine) {
+            Ok(Some(r)) => record(r).unwrap_or_else(|e| {
+                writeln!(io::stderr(), "{}", e).unwrap();
+                process::exit(1);
+            }),
+            Ok(None) => return,
+            Err(fault) => {
+                writeln!(io::stderr(), "Fault at address {}: {}", machine.pc(), fault).unwrap();
+                return;
+            }
+        }
+    }
+
+    writeln!(io::stderr(), "Stopped after {} instructions", MAX_STEPS).unwrap();
+}
+
+fn run(options: &Options) {
+    let program = compile_for_emulator(options);
+    let mut machine = Machine::new(&program);
+
+    attach_standard_devices(&mut machine, Console::stdio());
+
+    match machine.run(MAX_STEPS) {
+        Ok(true) => {},
+        Ok(false) => {
+            writeln!(io::stderr(), "Stopped after {} instructions", MAX_STEPS).unwrap();
+            process::exit(1);
+        },
+        Err(fault) => {
+            writeln!(io::stderr(), "Fault at address {}: {}", machine.pc(), fault).unwrap();
+            process::exit(1);
+        }
+    }
+}
+
+fn trace_command(options: &Options) {
+    let program = compile_for_emulator(options);
+    let output = options.value("--output").map(|s| s.to_string())
+                                          .unwrap_or(format!("{}.trace", options.paths[0]));
+
+    let file = File::create(&output).and_then(|f| TraceWriter::new(BufWriter::new(f)));
+
+    let mut writer = file.unwrap_or_else(|e| {
+        writeln!(io::stderr(), "{}: {}", output, e).unwrap();
+        process::exit(1);
+    });
+
+    run_traced(&program, |r| writer.write(&r));
+
+    if let Err(e) = writer.finish() {
+        writeln!(io::stderr(), "{}: {}", output, e).unwrap();
+        process::exit(1);
+    }
+}
+
+fn profile(options: &Options) {
+    let (program, info) = compile_with_debug_info(options, "profile");
+    let mut records = Vec::new();
+
+    match options.value("--trace") {
+        Some(path) => {
+            let reader = File::open(path).and_then(|f| TraceReader::new(BufReader::new(f)));
+            let result = reader.and_then(|r| r.collect::<io::Result<Vec<_>>>());
+
+            records = result.unwrap_or_else(|e| {
+                writeln!(io::stderr(), "{}: {}", path, e).unwrap();
+                process::exit(1);
+            });
+        },
+        None => run_traced(&program, |r| {
+            records.push(r);
+            Ok(())
+        })
+    }
+
+    let profile = blocks::profile::build_profile(records, &info);
+
+    if options.has_flag("--folded") {
+        print!("{}", blocks::profile::render_folded(&profile));
+    } else {
+        print!("{}", blocks::profile::render_report(&profile, &program, &info, PROFILE_ROWS));
+    }
+}
+
+fn golden(options: &Options) {
+    let bless = options.has_flag("--bless");
+    let mut failed = false;
+
+    for dir in &options.paths {
+        let failures = blocks::golden::run_dir(Path::new(dir), bless).unwrap_or_else(|e| {
+            writeln!(io::stderr(), "{}: {}", dir, e).unwrap();
+            process::exit(1);
+        });
+
+        for (path, diff) in failures {
+            println!("FAILED {}\n{}\n", path.display(), diff);
+            failed = true;
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
 
-    match blocks::compile(prog) {
-        Ok(v) => println!("Compiled:\n{:?}", v),
-        Err(e) => println!("{}", e)
+    match args.first().map(|s| s as &str) {
+        Some("object") => object(&parse_options(&args[1..], &["--output="])),
+        Some("ar") => ar(&parse_options(&args[1..], &["--output="])),
+        Some("link") => link(&parse_options(&args[1..], &["--output=", "--layout="])),
+        Some("fmt") => fmt(&parse_options(&args[1..], &["--check"])),
+        Some("doc") => doc(&parse_options(&args[1..], &[])),
+        Some("run") => run(&parse_options(&args[1..], LEVEL_FLAGS)),
+        Some("cfg") => cfg(&parse_options(&args[1..], &["--dot"])),
+        Some("debug") => debug(&parse_options(&args[1..], LEVEL_FLAGS)),
+        Some("golden") => golden(&parse_options(&args[1..], &["--bless"])),
+        Some("gdb") => gdb(&parse_options(&args[1..], &[&["--port="], LEVEL_FLAGS].concat())),
+        Some("trace") => trace_command(&parse_options(&args[1..], &[&["--output="], LEVEL_FLAGS].concat())),
+        Some("profile") => profile(&parse_options(&args[1..], &[&["--trace=", "--folded"], LEVEL_FLAGS].concat())),
+        _ => build(&parse_options(&args, &[&["--cache=", "--layout=", "--verbose"], LEVEL_FLAGS].concat()))
     }
 }
@@ -0,0 +1,341 @@
+// Separate compilation.
+// A file can be compiled to an object, which is machine code whose references to variables and
+// symbols are left unresolved, and objects are later linked into a program. This allows a library
+// to be compiled once and linked into many programs.
+//
+// An object has two code sections, `text` for its symbol blocks and `main` for the code outside
+// them, and a data section for its variables. Every word of code that refers to a name has a
+// relocation, and holds an addend that the linker adds to the address of the name.
+// Variables are shared between objects: every object that assigns to a variable exports it, and
+// the linker gives all of them the same address. Symbol blocks must be defined only once.
+
+use compile::build_module;
+use compile_utils::{Operand, encode_ir, locate_error};
+use error::*;
+use ir::{Ir, allocate_registers_at, parse_inline_tag};
+use json::Json;
+use layout::{Image, Layout};
+use opt::{OptLevel, parse_level_tag};
+use token::Token;
+use utils::Address;
+
+use std::collections::HashMap;
+
+// Bump this when the format changes, so old objects are rejected instead of misread
+const OBJECT_VERSION: i64 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Section {
+    Text,
+    Main,
+    Data
+}
+
+impl Section {
+    fn name(&self) -> &'static str {
+        match *self {
+            Section::Text => "text",
+            Section::Main => "main",
+            Section::Data => "data"
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Section> {
+        match name {
+            "text" => Some(Section::Text),
+            "main" => Some(Section::Main),
+            "data" => Some(Section::Data),
+            _ => None
+        }
+    }
+}
+
+// A name defined by an object, at an offset in one of its sections
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Export {
+    pub name: String,
+    pub section: Section,
+    pub offset: usize
+}
+
+// A word of code in the text or main section that must be patched with the address of a name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relocation {
+    pub section: Section,
+    pub offset: usize,
+    pub name: String
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Object {
+    pub text: Vec<i32>,
+    pub main: Vec<i32>,
+    pub data_size: usize,
+    pub exports: Vec<Export>,
+    // Names referred to but not defined by the object, sorted
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>
+}
+
+impl Object {
+    pub fn export(&self, name: &str) -> Option<&Export> {
+        self.exports.iter().find(|e| e.name == name)
+    }
+
+    fn code_mut(&mut self, section: Section) -> &mut Vec<i32> {
+        match section {
+            Section::Text => &mut self.text,
+            _ => &mut self.main
+        }
+    }
+
+    fn emit(&mut self, section: Section, ir: Vec<Ir>, blocks: &[String]) -> Result<(), BlocksError> {
+        for item in ir {
+            if let Ir::Tag(name, value) = item {
+                // The levels have already been applied, and objects are not peephole optimized
+                match &name as &str {
+                    "opt" => {
+                        parse_level_tag(&value)?;
+                        continue;
+                    },
+                    "inline" => {
+                        parse_inline_tag(&value)?;
+                        continue;
+                    },
+                    "volatile" => continue,
+                    _ => {}
+                }
+
+                // var_addr places variables at fixed addresses, which can't be relocated
+                return Err(match &name as &str {
+                    "var_addr" => BlocksError::new(ErrorKind::TagError,
+                                                   Token::Other("var_addr can't be used in objects".to_string())),
+                    _ => BlocksError::new(ErrorKind::UnknownTag, Token::Other(name))
+                });
+            }
+
+            for operand in encode_ir(item) {
+                let (name, assigned) = match operand {
+                    Operand::Word(word) | Operand::Dest(Address::Static(word)) |
+                    Operand::Source(Address::Static(word)) => {
+                        self.code_mut(section).push(word);
+                        continue;
+                    },
+                    Operand::Dest(Address::Variable(name)) => (name, true),
+                    Operand::Source(Address::Variable(name)) => (name, false)
+                };
+
+                if assigned && self.export(&name).is_none() && !blocks.contains(&name) {
+                    self.exports.push(Export {
+                        name: name.clone(),
+                        section: Section::Data,
+                        offset: self.data_size
+                    });
+
+                    self.data_size += 1;
+                }
+
+                let offset = self.code_mut(section).len();
+                self.code_mut(section).push(0);
+
+                self.relocations.push(Relocation {
+                    section: section,
+                    offset: offset,
+                    name: name
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Json {
+        let code = |code: &[i32]| Json::Array(code.iter().map(|&x| Json::Number(x as i64)).collect());
+
+        let exports = self.exports.iter().map(|e| Json::object(vec![
+            ("name", Json::string(&e.name)),
+            ("section", Json::string(e.section.name())),
+            ("offset", Json::Number(e.offset as i64))
+        ])).collect();
+
+        let relocations = self.relocations.iter().map(|r| Json::object(vec![
+            ("section", Json::string(r.section.name())),
+            ("offset", Json::Number(r.offset as i64)),
+            ("name", Json::string(&r.name))
+        ])).collect();
+
+        Json::object(vec![
+            ("version", Json::Number(OBJECT_VERSION)),
+            ("text", code(&self.text)),
+            ("main", code(&self.main)),
+            ("data_size", Json::Number(self.data_size as i64)),
+            ("exports", Json::Array(exports)),
+            ("imports", Json::Array(self.imports.iter().map(|s| Json::string(s)).collect())),
+            ("relocations", Json::Array(relocations))
+        ])
+    }
+
+    pub fn from_json(json: &Json) -> Result<Object, String> {
+        if json.get("version").as_i64() != Some(OBJECT_VERSION) {
+            return Err(format!("Not an object file, or one made by a different version (expected version {})",
+                               OBJECT_VERSION));
+        }
+
+        let invalid = |field: &str| format!("Invalid object file: bad `{}`", field);
+
+        let array = |field: &str| json.get(field).as_array().ok_or(invalid(field));
+        let code = |field: &str| array(field)?.iter()
+                                              .map(|x| x.as_i64().map(|x| x as i32).ok_or(invalid(field)))
+                                              .collect::<Result<Vec<_>, _>>();
+        let section = |json: &Json, field: &str| json.get("section").as_str()
+                                                                    .and_then(Section::from_name)
+                                                                    .ok_or(invalid(field));
+        let offset = |json: &Json, field: &str| json.get("offset").as_i64()
+                                                                  .filter(|&x| x >= 0)
+                                                                  .map(|x| x as usize)
+                                                                  .ok_or(invalid(field));
+        let name = |json: &Json, field: &str| json.get("name").as_str().map(|s| s.to_string()).ok_or(invalid(field));
+
+        let object = Object {
+            text: code("text")?,
+            main: code("main")?,
+            data_size: json.get("data_size").as_i64().filter(|&x| x >= 0).ok_or(invalid("data_size"))? as usize,
+            exports: array("exports")?.iter().map(|e| Ok(Export {
+                name: name(e, "exports")?,
+                section: section(e, "exports")?,
+                offset: offset(e, "exports")?
+            })).collect::<Result<_, String>>()?,
+            imports: array("imports")?.iter()
+                                      .map(|s| s.as_str().map(|s| s.to_string()).ok_or(invalid("imports")))
+                                      .collect::<Result<_, _>>()?,
+            relocations: array("relocations")?.iter().map(|r| Ok(Relocation {
+                section: section(r, "relocations")?,
+                offset: offset(r, "relocations")?,
+                name: name(r, "relocations")?
+            })).collect::<Result<_, String>>()?
+        };
+
+        // Check offsets here so a bad object can't make the linker panic
+        for r in &object.relocations {
+            let size = match r.section {
+                Section::Text => object.text.len(),
+                Section::Main => object.main.len(),
+                Section::Data => 0
+            };
+
+            if r.offset >= size {
+                return Err(invalid("relocations"));
+            }
+        }
+
+        for e in &object.exports {
+            let size = match e.section {
+                Section::Text => object.text.len(),
+                Section::Main => 0,
+                Section::Data => object.data_size
+            };
+
+            // A symbol block can be empty, so it may start at the end of the text section
+            if e.offset > size || (e.section == Section::Data && e.offset == size) {
+                return Err(invalid("exports"));
+            }
+        }
+
+        Ok(object)
+    }
+}
+
+// Compiles a file to an object
+pub fn compile_object(prog: &str) -> Result<Object, BlocksError> {
+    let mut ir = build_module(prog)?;
+    allocate_registers_at(&mut ir, OptLevel::default());
+
+    let mut object = Object::default();
+
+    // Blocks are sorted by name, so compiling the same file always gives the same object
+    let blocks = ir.blocks.keys().cloned().collect::<Vec<_>>();
+
+    for name in &blocks {
+        object.exports.push(Export {
+            name: name.clone(),
+            section: Section::Text,
+            offset: object.text.len()
+        });
+
+        object.emit(Section::Text, ir.blocks[name].clone(), &blocks).map_err(|e| locate_error(e, prog))?;
+    }
+
+    object.emit(Section::Main, ir.ir, &blocks).map_err(|e| locate_error(e, prog))?;
+
+    let mut imports = object.relocations.iter()
+                                        .filter(|r| object.export(&r.name).is_none())
+                                        .map(|r| r.name.clone())
+                                        .collect::<Vec<_>>();
+    imports.sort();
+    imports.dedup();
+    object.imports = imports;
+
+    Ok(object)
+}
+
+// Links objects into a program, returning it with the addresses of its variables
+// Text sections are placed in order to form the symbol section, and main sections are placed in
+// order after it, so the main code of every object runs in turn
+pub fn link_objects(objects: &[Object]) -> Result<(Vec<i32>, HashMap<String, i32>), BlocksError> {
+    link_objects_with_layout(objects, &Layout::default()).map(|(image, vars)| (image.code, vars))
+}
+
+// Like link_objects, but places the sections of the program according to a layout
+pub fn link_objects_with_layout(objects: &[Object], layout: &Layout) -> Result<(Image, HashMap<String, i32>), BlocksError> {
+    let mut addresses = HashMap::new();
+    let mut vars = HashMap::new();
+    let mut text_size = 0;
+
+    for object in objects {
+        for export in &object.exports {
+            let address = match export.section {
+                Section::Text => (text_size + export.offset) as i32,
+                // Variables are numbered by their first definition
+                Section::Data => {
+                    let count = vars.len() as i32;
+                    *vars.entry(export.name.clone()).or_insert(count)
+                },
+                Section::Main => continue
+            };
+
+            match addresses.insert(export.name.clone(), (export.section, address)) {
+                Some(previous) if previous != (Section::Data, address) || export.section != Section::Data => {
+                    return Err(BlocksError::new(ErrorKind::DuplicateSymbol, Token::Other(export.name.clone())));
+                },
+                _ => {}
+            }
+        }
+
+        text_size += object.text.len();
+    }
+
+    let mut text = Vec::new();
+    let mut main = Vec::new();
+
+    for object in objects {
+        let (text_base, main_base) = (text.len(), main.len());
+
+        text.extend_from_slice(&object.text);
+        main.extend_from_slice(&object.main);
+
+        for r in &object.relocations {
+            let address = addresses.get(&r.name)
+                                   .map(|a| a.1)
+                                   .ok_or(BlocksError::new(ErrorKind::UndeclaredVar, Token::Other(r.name.clone())))?;
+
+            let word = match r.section {
+                Section::Text => &mut text[text_base + r.offset],
+                _ => &mut main[main_base + r.offset]
+            };
+
+            *word = word.wrapping_add(address);
+        }
+    }
+
+    Ok((layout.build(&text, &main, vars.len())?, vars))
+}
@@ -0,0 +1,166 @@
+// Optimization levels.
+// The IR generator's output is correct but slow, and most programs don't care how their variables
+// and temps are kept, so they are optimized by default. Programs that rely on the exact code, such
+// as ones that read their own instructions, can ask for less. Each level enables these passes:
+//
+//   -O0  none, the IR is compiled exactly as it was generated
+//   -O1  register allocation for temps and the peephole rules on the emitted code (the default)
+//   -O2  like -O1, but the IR is generated through the SSA form, which reuses temps and reads
+//        variables directly where it can, and the symbol blocks nothing can reach are removed
+//   -O3  like -O2, and calls to small symbol blocks are replaced with their code
+//   -Os  only the passes that never make code larger: register allocation, the peephole rules
+//        and removal of unreachable symbol blocks
+//
+// `?opt level;` sets the level of a file when it is in the main code, or of a symbol block when it
+// is in one, where the level is one of 0, 1, 2, 3 and s. Symbol blocks without the tag use the level
+// of their file.
+//
+// `?volatile name;` marks a variable whose reads and writes must all happen as written, such as one
+// shared with a device or with inline machine code. Fixed addresses are always treated this way.
+//
+// `?inline always;` and `?inline never;` in a symbol block override whether -O3 inlines it.
+
+use error::{BlocksError, ErrorKind};
+use ir::Ir;
+use token::Token;
+use tree::Tree;
+use utils::TokenWrapper;
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OptLevel {
+    O0,
+    #[default]
+    O1,
+    O2,
+    O3,
+    Os
+}
+
+// The passes a level enables
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Passes {
+    pub ssa: bool,
+    pub registers: bool,
+    pub peephole: bool,
+    pub inline: bool,
+    pub dead_blocks: bool
+}
+
+impl OptLevel {
+    // Parses the value of an `?opt` tag
+    pub fn parse(value: &str) -> Option<OptLevel> {
+        match value {
+            "0" => Some(OptLevel::O0),
+            "1" => Some(OptLevel::O1),
+            "2" => Some(OptLevel::O2),
+            "3" => Some(OptLevel::O3),
+            "s" => Some(OptLevel::Os),
+            _ => None
+        }
+    }
+
+    // Parses a `-O` command line flag
+    pub fn from_flag(flag: &str) -> Option<OptLevel> {
+        if let Some(value) = flag.strip_prefix("-O") {
+            OptLevel::parse(value)
+        } else {
+            None
+        }
+    }
+
+    // The value of an `?opt` tag for the level
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OptLevel::O0 => "0",
+            OptLevel::O1 => "1",
+            OptLevel::O2 => "2",
+            OptLevel::O3 => "3",
+            OptLevel::Os => "s"
+        }
+    }
+
+    pub fn passes(&self) -> Passes {
+        let none = Passes {
+            ssa: false,
+            registers: false,
+            peephole: false,
+            inline: false,
+            dead_blocks: false
+        };
+
+        match *self {
+            OptLevel::O0 => none,
+            OptLevel::O1 => Passes { registers: true, peephole: true, ..none },
+            OptLevel::O2 => Passes { ssa: true, registers: true, peephole: true, dead_blocks: true, ..none },
+            OptLevel::O3 => Passes { ssa: true, registers: true, peephole: true, inline: true, dead_blocks: true },
+            OptLevel::Os => Passes { registers: true, peephole: true, dead_blocks: true, ..none }
+        }
+    }
+}
+
+pub fn parse_level_tag(value: &str) -> Result<OptLevel, BlocksError> {
+    OptLevel::parse(value).ok_or_else(|| {
+        BlocksError::new(ErrorKind::TagError,
+                         Token::Other(format!("opt must be one of 0, 1, 2, 3 and s (found `{}`)", value)))
+    })
+}
+
+// The level set by the `?opt` tags directly in a block of statements, if any
+fn block_level(stmts: &[TokenWrapper]) -> Result<Option<OptLevel>, BlocksError> {
+    let mut level = None;
+
+    for stmt in stmts {
+        if let TokenWrapper::Tree(Tree::Tag(ref key, ref value)) = *stmt {
+            if key == "opt" {
+                level = Some(parse_level_tag(value)?);
+            }
+        }
+    }
+
+    Ok(level)
+}
+
+fn symbol_levels(stmts: &[TokenWrapper], outer: OptLevel,
+                 levels: &mut HashMap<String, OptLevel>) -> Result<(), BlocksError> {
+    for stmt in stmts {
+        if let TokenWrapper::Tree(Tree::Symbol(ref name, ref body)) = *stmt {
+            let body = match **body {
+                TokenWrapper::Tree(Tree::Block(ref body)) => body as &[_],
+                _ => &[]
+            };
+
+            let level = block_level(body)?.unwrap_or(outer);
+
+            levels.insert(name.clone(), level);
+            symbol_levels(body, level, levels)?;
+        }
+    }
+
+    Ok(())
+}
+
+// The levels of the main code and each symbol block of a file compiled at `level`
+pub fn file_levels(tree: &Tree, level: OptLevel) -> Result<(OptLevel, HashMap<String, OptLevel>), BlocksError> {
+    let stmts = match *tree {
+        Tree::Block(ref stmts) => stmts as &[_],
+        _ => &[]
+    };
+
+    let main = block_level(stmts)?.unwrap_or(level);
+    let mut levels = HashMap::new();
+
+    symbol_levels(stmts, main, &mut levels)?;
+
+    Ok((main, levels))
+}
+
+// The level of some code of a file compiled at `level`, which build_module marks with an `opt` tag
+// at its start if it isn't `level`
+pub fn code_level(ir: &[Ir], level: OptLevel) -> OptLevel {
+    ir.iter().filter_map(|item| match *item {
+        Ir::Tag(ref key, ref value) if key == "opt" => OptLevel::parse(value),
+        _ => None
+    }).next().unwrap_or(level)
+}
@@ -0,0 +1,136 @@
+// Instruction-level profiling from execution traces.
+// Counts executed instructions per address, per symbol block and per source line using debug
+// information, and tracks calls to produce folded stacks for flamegraph tools.
+// Instructions outside of the compiled statements, such as the segment setup and cleanup code,
+// are attributed to `[runtime]`.
+
+use emulator::trace::TraceRecord;
+use emulator::disassemble;
+use debug_info::DebugInfo;
+
+use std::collections::HashMap;
+
+const RUNTIME: &'static str = "[runtime]";
+const MAIN: &'static str = "main";
+
+const CALL_OPCODE: i32 = 33;
+const RET_OPCODE: i32 = 35;
+
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub total: u64,
+    // Sorted by count, then by key
+    pub instructions: Vec<(i32, u64)>,
+    pub symbols: Vec<(String, u64)>,
+    pub lines: Vec<(usize, u64)>,
+    // Semicolon separated call stacks, from the outermost frame
+    pub stacks: Vec<(String, u64)>
+}
+
+fn sorted<K: Ord + Clone>(counts: HashMap<K, u64>) -> Vec<(K, u64)> {
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    counts
+}
+
+// Name of the symbol block an instruction belongs to
+fn frame_name(info: &DebugInfo, pc: i32) -> String {
+    match info.entry_containing(pc) {
+        Some(entry) => entry.symbol.clone().unwrap_or(MAIN.to_string()),
+        None => RUNTIME.to_string()
+    }
+}
+
+pub fn build_profile<I: IntoIterator<Item = TraceRecord>>(records: I, info: &DebugInfo) -> Profile {
+    let mut total = 0;
+    let mut instructions = HashMap::new();
+    let mut symbols = HashMap::new();
+    let mut lines = HashMap::new();
+    let mut stacks = HashMap::new();
+
+    let mut stack = vec![MAIN.to_string()];
+    let mut entered_call = false;
+
+    for record in records {
+        let frame = frame_name(info, record.pc);
+
+        // The callee is named by the first instruction run after a call
+        if entered_call {
+            stack.push(frame.clone());
+            entered_call = false;
+        }
+
+        total += 1;
+        *instructions.entry(record.pc).or_insert(0) += 1;
+        *symbols.entry(frame).or_insert(0) += 1;
+        *stacks.entry(stack.join(";")).or_insert(0) += 1;
+
+        if let Some(line) = info.entry_containing(record.pc).and_then(|e| e.line) {
+            *lines.entry(line).or_insert(0) += 1;
+        }
+
+        match record.opcode {
+            CALL_OPCODE => entered_call = true,
+            RET_OPCODE if stack.len() > 1 => {
+                stack.pop();
+            },
+            _ => {}
+        }
+    }
+
+    Profile {
+        total: total,
+        instructions: sorted(instructions),
+        symbols: sorted(symbols),
+        lines: sorted(lines),
+        stacks: sorted(stacks)
+    }
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+// Renders a human readable report, showing at most `limit` rows in each table
+pub fn render_report(profile: &Profile, program: &[i32], info: &DebugInfo, limit: usize) -> String {
+    let mut result = format!("Executed {} instructions\n", profile.total);
+
+    result.push_str("\nSymbol blocks:\n");
+
+    for &(ref name, count) in profile.symbols.iter().take(limit) {
+        result.push_str(&format!("{:>12} {:>6.2}%  {}\n", count, percent(count, profile.total), name));
+    }
+
+    result.push_str("\nSource lines:\n");
+
+    for &(line, count) in profile.lines.iter().take(limit) {
+        result.push_str(&format!("{:>12} {:>6.2}%  line {}\n", count, percent(count, profile.total), line));
+    }
+
+    result.push_str("\nInstructions:\n");
+
+    for &(addr, count) in profile.instructions.iter().take(limit) {
+        let location = match info.entry_containing(addr).and_then(|e| e.line) {
+            Some(line) => format!(" (line {})", line),
+            None => String::new()
+        };
+
+        result.push_str(&format!("{:>12} {:>6.2}%  {:>6}: {}{}\n", count, percent(count, profile.total),
+                                 addr, disassemble(program, addr), location));
+    }
+
+    result
+}
+
+// Renders call stacks in the folded format used by flamegraph tools, one stack per line
+pub fn render_folded(profile: &Profile) -> String {
+    let mut stacks = profile.stacks.clone();
+    stacks.sort();
+
+    stacks.iter().map(|&(ref stack, count)| format!("{} {}\n", stack, count)).collect()
+}
@@ -0,0 +1,259 @@
+// Builds the SSA form of a program from its tree.
+// Expressions are evaluated left to right. A number is a constant, except when it is dereferenced
+// or assigned to, where it is the address of a memory location, as in the IR generator.
+
+use error::*;
+use ssa::*;
+use token::Token;
+use tree::Tree;
+use utils::TokenWrapper;
+
+struct Builder {
+    function: Function,
+    // The block code is added to, or None after a jump until more code follows
+    current: Option<BlockId>
+}
+
+impl Builder {
+    fn new(name: &str) -> Builder {
+        Builder {
+            function: Function::new(name),
+            current: Some(BlockId(0))
+        }
+    }
+
+    fn block(&mut self) -> BlockId {
+        match self.current {
+            Some(block) => block,
+            None => {
+                let block = self.function.add_block();
+                self.current = Some(block);
+                block
+            }
+        }
+    }
+
+    fn push(&mut self, inst: Inst) -> Value {
+        let block = self.block();
+        self.function.push(block, inst)
+    }
+
+    // Ends the current block, continuing in a new one if `next` is set
+    fn terminate(&mut self, terminator: Terminator, next: Option<BlockId>) {
+        let block = self.block();
+        self.function.blocks[block.0].terminator = terminator;
+        self.current = next;
+    }
+
+    fn finish(mut self) -> Function {
+        // A function that ends with a jump has no code to run off the end of
+        if let Some(block) = self.current {
+            self.function.blocks[block.0].terminator = Terminator::End;
+        }
+
+        self.function
+    }
+
+    fn statement(&mut self, tree: TokenWrapper, symbols: &mut Vec<Function>) -> Result<(), BlocksError> {
+        let tree = match tree {
+            TokenWrapper::Tree(tree) => tree,
+            token => {
+                self.value(token)?;
+                return Ok(());
+            }
+        };
+
+        match tree {
+            Tree::Block(stmts) => {
+                for stmt in stmts {
+                    self.statement(stmt, symbols)?;
+                }
+            },
+            Tree::Symbol(name, body) => {
+                // Symbol blocks declared inside this one come after it
+                let index = symbols.len();
+                let mut builder = Builder::new(&name);
+
+                builder.statement(*body, symbols)?;
+                symbols.insert(index, builder.finish());
+            },
+            Tree::Assign(lhs, rhs) => self.assign(*lhs, *rhs)?,
+            Tree::Compare(operator) => match *operator {
+                TokenWrapper::Tree(tree) => match split_operator(tree) {
+                    Ok((Operator::Compare(op), a, b)) => {
+                        let (a, b) = (self.value(a)?, self.value(b)?);
+                        self.push(Inst::Compare(op, a, b));
+                    },
+                    Ok((Operator::Binary(op), a, b)) => {
+                        self.binary(op, a, b)?;
+                    },
+                    Err(tree) => {
+                        self.value(TokenWrapper::Tree(tree))?;
+                    }
+                },
+                token => {
+                    self.value(token)?;
+                }
+            },
+            Tree::Goto(item) => {
+                let target = match *item {
+                    TokenWrapper::Token(Token::Identifier(ident)) => Target::Name(ident),
+                    TokenWrapper::Token(Token::Number(num)) => Target::Static(num),
+                    item => Target::Indirect(self.value(item)?)
+                };
+
+                self.terminate(Terminator::Jump(target), None);
+            },
+            Tree::IfGoto(item) => {
+                let target = direct_target(*item, ErrorKind::IfGotoAddressType)?;
+
+                // After a jump, the block this ends is added first, so that it comes before `next`
+                self.block();
+                let next = self.function.add_block();
+
+                self.terminate(Terminator::CondJump(target, next), Some(next));
+            },
+            Tree::Call(item) => {
+                let target = direct_target(*item, ErrorKind::CallAddressType)?;
+
+                self.block();
+                let next = self.function.add_block();
+
+                self.terminate(Terminator::Call(target, next), Some(next));
+            },
+            Tree::Return => self.terminate(Terminator::Return, None),
+            Tree::Raw(raw) => {
+                self.push(Inst::Raw(raw));
+            },
+            Tree::Tag(key, value) => {
+                self.push(Inst::Tag(key, value));
+            },
+            tree => {
+                self.value(TokenWrapper::Tree(tree))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn assign(&mut self, lhs: TokenWrapper, rhs: TokenWrapper) -> Result<(), BlocksError> {
+        match lhs {
+            TokenWrapper::Token(Token::Identifier(ident)) => {
+                let value = self.value(rhs)?;
+                self.push(Inst::Store(Place::Var(ident), value));
+            },
+            TokenWrapper::Token(Token::Number(num)) if num < 0 => {
+                return Err(BlocksError::new(ErrorKind::InvalidAddress, Token::Other(format!("{}", num))));
+            },
+            TokenWrapper::Token(Token::Number(num)) => {
+                let value = self.value(rhs)?;
+                self.push(Inst::Store(Place::Static(num), value));
+            },
+            TokenWrapper::Token(Token::Register(reg)) => {
+                let value = self.value(rhs)?;
+                self.push(Inst::WriteReg(reg, value));
+            },
+            // Any other expression is the address to store to
+            lhs => {
+                let pointer = self.value(lhs)?;
+                let value = self.value(rhs)?;
+                self.push(Inst::Store(Place::Pointer(pointer), value));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn binary(&mut self, op: BinOp, a: TokenWrapper, b: TokenWrapper) -> Result<Value, BlocksError> {
+        let (a, b) = (self.value(a)?, self.value(b)?);
+        Ok(self.push(Inst::Binary(op, a, b)))
+    }
+
+    fn value(&mut self, tree: TokenWrapper) -> Result<Value, BlocksError> {
+        let tree = match tree {
+            TokenWrapper::Token(Token::Identifier(ident)) => return Ok(self.push(Inst::Load(Place::Var(ident)))),
+            TokenWrapper::Token(Token::Number(num)) => return Ok(self.push(Inst::Const(num))),
+            TokenWrapper::Token(Token::Register(reg)) => return Ok(self.push(Inst::ReadReg(reg))),
+            TokenWrapper::Token(token) => return Err(BlocksError::new(ErrorKind::UnexpectedToken, token)),
+            TokenWrapper::Tree(tree) => tree
+        };
+
+        let tree = match split_operator(tree) {
+            Ok((Operator::Binary(op), a, b)) => return self.binary(op, a, b),
+            // Comparisons only set the flag register, so they have no value
+            Ok((Operator::Compare(_), _, _)) => {
+                return Err(BlocksError::new(ErrorKind::Other,
+                                            Token::Other("comparisons outside `cmp` are not supported yet".to_string())));
+            },
+            Err(tree) => tree
+        };
+
+        match tree {
+            Tree::Not(item) => {
+                let value = self.value(*item)?;
+                Ok(self.push(Inst::Not(value)))
+            },
+            Tree::Address(item) => match *item {
+                TokenWrapper::Token(Token::Identifier(ident)) => Ok(self.push(Inst::Addr(ident))),
+                _ => Err(BlocksError::new(ErrorKind::AddressNameType, Token::Null))
+            },
+            Tree::Dereference(item) => {
+                let pointer = match *item {
+                    TokenWrapper::Token(Token::Number(num)) => self.push(Inst::Load(Place::Static(num))),
+                    item => self.value(item)?
+                };
+
+                Ok(self.push(Inst::Load(Place::Pointer(pointer))))
+            },
+            Tree::And(..) | Tree::Or(..) => {
+                Err(BlocksError::new(ErrorKind::Other, Token::Other("`and` and `or` are not supported yet".to_string())))
+            },
+            _ => Err(BlocksError::new(ErrorKind::Other, Token::Other("expected an expression".to_string())))
+        }
+    }
+}
+
+enum Operator {
+    Binary(BinOp),
+    Compare(CmpOp)
+}
+
+// Splits an operator with two operands into its parts, or gives back any other tree
+fn split_operator(tree: Tree) -> Result<(Operator, TokenWrapper, TokenWrapper), Tree> {
+    let (op, a, b) = match tree {
+        Tree::Add(a, b) => (Operator::Binary(BinOp::Add), a, b),
+        Tree::Subtract(a, b) => (Operator::Binary(BinOp::Sub), a, b),
+        Tree::Multiply(a, b) => (Operator::Binary(BinOp::Mul), a, b),
+        Tree::Divide(a, b) => (Operator::Binary(BinOp::Div), a, b),
+        Tree::Xor(a, b) => (Operator::Binary(BinOp::Xor), a, b),
+        Tree::Equals(a, b) => (Operator::Compare(CmpOp::Equals), a, b),
+        Tree::Less(a, b) => (Operator::Compare(CmpOp::Less), a, b),
+        Tree::Greater(a, b) => (Operator::Compare(CmpOp::Greater), a, b),
+        Tree::LessEqual(a, b) => (Operator::Compare(CmpOp::LessEqual), a, b),
+        Tree::GreaterEqual(a, b) => (Operator::Compare(CmpOp::GreaterEqual), a, b),
+        tree => return Err(tree)
+    };
+
+    Ok((op, *a, *b))
+}
+
+// The target of `ifgoto` and `call`, which must be a name or an address
+fn direct_target(item: TokenWrapper, kind: ErrorKind) -> Result<Target, BlocksError> {
+    match item {
+        TokenWrapper::Token(Token::Identifier(ident)) => Ok(Target::Name(ident)),
+        TokenWrapper::Token(Token::Number(num)) => Ok(Target::Static(num)),
+        _ => Err(BlocksError::new(kind, Token::Null))
+    }
+}
+
+pub fn build_ssa(tree: Tree) -> Result<Program, BlocksError> {
+    let mut symbols = Vec::new();
+    let mut main = Builder::new("main");
+
+    main.statement(TokenWrapper::Tree(tree), &mut symbols)?;
+
+    Ok(Program {
+        main: main.finish(),
+        symbols: symbols
+    })
+}
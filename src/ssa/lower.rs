@@ -0,0 +1,525 @@
+// Lowers the SSA form to the IR.
+// Values are kept in temps, which the register allocator later moves into registers where it can.
+// A value used only in the block that defines it gets a temp that is reused once the value is
+// dead. Other values, and phis, get a temp of their own, since blocks can run in any order.
+// Constants and addresses are not kept anywhere, and are written into the instructions that use
+// them instead.
+//
+// Blocks are laid out in order, so a jump to the next block costs nothing. The IR can only jump to
+// names, so a block that is jumped to from anywhere else becomes a symbol block of its own.
+
+use compile::cleanup;
+use error::{BlocksError, ErrorKind};
+use ir::{Ir, IrResult};
+use ssa::*;
+use token::Token;
+use utils::{Address, Register};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+struct Lowering<'a> {
+    function: &'a Function,
+    // Index of the function in the program, to give its labels unique names
+    index: usize,
+    labeled: Vec<bool>,
+    // The temp holding each value
+    temps: HashMap<Value, Address>,
+    // Whether each value must keep its temp across blocks
+    shared: Vec<bool>,
+    // The position of the last use of each value in its block, where the terminator is at the
+    // position after the last instruction
+    last_use: Vec<Option<usize>>,
+    // Loads of named locations that are read directly by their only use instead
+    deferred: Vec<Option<Address>>,
+    free: Vec<i32>,
+    local_count: i32
+}
+
+fn temp(id: i32) -> Address {
+    Address::new_temp(id)
+}
+
+fn arithmetic(op: BinOp) -> Ir {
+    match op {
+        BinOp::Add => Ir::Add,
+        BinOp::Sub => Ir::Sub,
+        BinOp::Mul => Ir::Mul,
+        BinOp::Div => Ir::Div,
+        BinOp::Xor => Ir::Xor
+    }
+}
+
+fn comparison(op: CmpOp) -> Ir {
+    match op {
+        CmpOp::Equals => Ir::Equals,
+        CmpOp::Less => Ir::Less,
+        CmpOp::Greater => Ir::Greater,
+        CmpOp::LessEqual => Ir::LessEqual,
+        CmpOp::GreaterEqual => Ir::GreaterEqual
+    }
+}
+
+impl<'a> Lowering<'a> {
+    fn new(function: &'a Function, index: usize, volatile: &HashSet<String>) -> Lowering<'a> {
+        let n = function.insts.len();
+        let mut lowering = Lowering {
+            function: function,
+            index: index,
+            labeled: vec![false; function.blocks.len()],
+            temps: HashMap::new(),
+            shared: vec![false; n],
+            last_use: vec![None; n],
+            deferred: vec![None; n],
+            free: Vec::new(),
+            local_count: 0
+        };
+
+        let mut defined_in = vec![BlockId(0); n];
+        let mut use_count = vec![0; n];
+
+        for (i, block) in function.blocks.iter().enumerate() {
+            for &value in &block.insts {
+                defined_in[value.0] = BlockId(i);
+            }
+        }
+
+        for (i, block) in function.blocks.iter().enumerate() {
+            let uses = block.insts.iter()
+                                  .enumerate()
+                                  .flat_map(|(pos, &v)| function.inst(v).operands().into_iter().map(move |o| (pos, v, o)))
+                                  .chain(block.terminator.operands().into_iter().map(|o| (block.insts.len(), Value(0), o)));
+
+            for (pos, user, operand) in uses {
+                use_count[operand.0] += 1;
+
+                let phi = pos < block.insts.len() && matches!(*function.inst(user), Inst::Phi(_));
+
+                if phi || defined_in[operand.0] != BlockId(i) {
+                    lowering.shared[operand.0] = true;
+                } else {
+                    lowering.last_use[operand.0] = Some(pos);
+                }
+
+                if phi {
+                    lowering.shared[user.0] = true;
+                }
+            }
+
+            // A block needs a name if it is entered other than by falling through from the one
+            // before it
+            let (target, next) = match block.terminator {
+                Terminator::Jump(ref target) => (Some(target), None),
+                Terminator::CondJump(ref target, next) | Terminator::Call(ref target, next) => (Some(target), Some(next)),
+                _ => (None, None)
+            };
+
+            if let Some(&Target::Block(b)) = target {
+                lowering.labeled[b.0] = true;
+            }
+
+            if let Some(next) = next {
+                if next.0 != i + 1 {
+                    lowering.labeled[next.0] = true;
+                }
+            }
+        }
+
+        // A load can be moved to its only use if nothing in between may write memory
+        // Loads of fixed addresses and volatile variables stay in order with the others
+        for block in &function.blocks {
+            for (pos, &value) in block.insts.iter().enumerate() {
+                let addr = match *function.inst(value) {
+                    Inst::Load(Place::Var(ref name)) if !volatile.contains(name) => Address::Variable(name.clone()),
+                    _ => continue
+                };
+
+                let last_use = match lowering.last_use[value.0] {
+                    Some(last_use) if use_count[value.0] == 1 && !lowering.shared[value.0] => last_use,
+                    _ => continue
+                };
+
+                let writes = block.insts[pos + 1..last_use].iter().any(|&v| {
+                    matches!(*function.inst(v), Inst::Store(..) | Inst::Raw(_) | Inst::Tag(..))
+                });
+
+                if !writes {
+                    lowering.deferred[value.0] = Some(addr);
+                }
+            }
+        }
+
+        lowering
+    }
+
+    fn label(&self, block: BlockId) -> String {
+        match (self.index, block.0) {
+            (i, 0) if i > 0 => self.function.name.clone(),
+            (i, b) => format!("__block_{}_{}__", i, b)
+        }
+    }
+
+    fn falls_through(&self, from: usize, to: BlockId) -> bool {
+        to.0 == from + 1 && !self.labeled[to.0]
+    }
+
+    fn local_temp(&mut self) -> Address {
+        let id = match self.free.pop() {
+            Some(id) => id,
+            None => {
+                self.local_count += 1;
+                self.local_count - 1
+            }
+        };
+
+        temp(id)
+    }
+
+    fn release(&mut self, addr: Address) {
+        if let Address::Variable(ref name) = addr {
+            let id = name.trim_start_matches("__temp_").trim_end_matches("__").parse::<i32>();
+
+            if let Ok(id) = id {
+                self.free.push(id);
+                // Reuse the lowest temps first, so programs use as few as possible
+                self.free.sort_by(|a, b| b.cmp(a));
+            }
+        }
+    }
+
+    // The temp a value is written to
+    fn define(&mut self, value: Value) -> Address {
+        let addr = if self.shared[value.0] {
+            self.shared_temp(value)
+        } else {
+            self.local_temp()
+        };
+
+        self.temps.insert(value, addr.clone());
+        addr
+    }
+
+    fn shared_temp(&self, value: Value) -> Address {
+        Address::Variable(format!("__temp_{}_v{}__", self.index, value.0))
+    }
+
+    // The value as an instruction operand, if it is known before the program runs
+    fn literal(&self, value: Value) -> Option<Address> {
+        match *self.function.inst(value) {
+            Inst::Const(n) => Some(Address::Static(n)),
+            Inst::Addr(ref name) => Some(Address::Variable(name.clone())),
+            _ => None
+        }
+    }
+
+    // The temp holding a value
+    // Blocks may come before the blocks that dominate them, so shared temps are found by name
+    fn source(&self, value: Value) -> Result<Address, BlocksError> {
+        if let Some(ref addr) = self.deferred[value.0] {
+            return Ok(addr.clone());
+        }
+
+        match self.temps.get(&value) {
+            _ if self.shared[value.0] => Ok(self.shared_temp(value)),
+            Some(addr) => Ok(addr.clone()),
+            None => Err(BlocksError::new(ErrorKind::Other,
+                                         Token::Other(format!("{} is used before it is defined", value))))
+        }
+    }
+
+    fn load(&self, reg: Register, value: Value, ir: &mut Vec<Ir>) -> Result<(), BlocksError> {
+        match self.literal(value) {
+            Some(literal) => ir.push(Ir::RegWrite(reg, literal)),
+            None => ir.push(Ir::RegCopy(reg, self.source(value)?))
+        }
+
+        Ok(())
+    }
+
+    // Copies a value to a location
+    fn copy(&self, dest: Address, value: Value, ir: &mut Vec<Ir>) -> Result<(), BlocksError> {
+        match self.literal(value) {
+            Some(literal) => ir.push(Ir::Write(dest, literal)),
+            None => ir.push(Ir::Copy(dest, self.source(value)?))
+        }
+
+        Ok(())
+    }
+
+    // A location holding a value, writing literals to a scratch temp
+    fn in_memory(&mut self, value: Value, ir: &mut Vec<Ir>) -> Result<Address, BlocksError> {
+        match self.literal(value) {
+            Some(literal) => {
+                let scratch = self.local_temp();
+                ir.push(Ir::Write(scratch.clone(), literal));
+                Ok(scratch)
+            },
+            None => self.source(value)
+        }
+    }
+
+    // Lowers the instruction at a position in its block
+    fn lower_inst(&mut self, value: Value, pos: usize, ir: &mut Vec<Ir>) -> Result<(), BlocksError> {
+        let function = self.function;
+        let inst = function.inst(value);
+
+        if self.deferred[value.0].is_some() {
+            return Ok(());
+        }
+
+        // Operands whose last use is this instruction can share a temp with its result, since
+        // every instruction reads its operands before writing
+        let mut operands = inst.operands();
+        operands.dedup();
+
+        let dying = operands.into_iter()
+                            .filter(|o| self.last_use[o.0] == Some(pos) && self.temps.contains_key(o))
+                            .collect::<Vec<_>>();
+
+        for operand in dying {
+            if !self.shared[operand.0] {
+                let addr = self.temps[&operand].clone();
+                self.release(addr);
+            }
+        }
+
+        // The temp the result is written to, for instructions that have one
+        let dest = match *inst {
+            Inst::Load(ref place) => {
+                let dest = self.define(value);
+
+                match *place {
+                    Place::Var(ref name) => ir.push(Ir::Copy(dest.clone(), Address::Variable(name.clone()))),
+                    Place::Static(addr) => ir.push(Ir::Copy(dest.clone(), Address::Static(addr))),
+                    Place::Pointer(p) => match self.literal(p) {
+                        Some(addr) => ir.push(Ir::Copy(dest.clone(), addr)),
+                        None => ir.push(Ir::IndirCopy3(dest.clone(), self.source(p)?))
+                    }
+                }
+
+                Some(dest)
+            },
+            Inst::Store(Place::Var(ref name), v) => {
+                self.copy(Address::Variable(name.clone()), v, ir)?;
+                None
+            },
+            Inst::Store(Place::Static(addr), v) => {
+                self.copy(Address::Static(addr), v, ir)?;
+                None
+            },
+            Inst::Store(Place::Pointer(p), v) => {
+                match (self.literal(p), self.literal(v)) {
+                    (Some(addr), _) => self.copy(addr, v, ir)?,
+                    (None, Some(literal)) => ir.push(Ir::IndirWrite(self.source(p)?, literal)),
+                    (None, None) => ir.push(Ir::IndirCopy(self.source(p)?, self.source(v)?))
+                }
+
+                None
+            },
+            Inst::Binary(op, a, b) => {
+                let dest = self.define(value);
+
+                self.load(Register::Int1, a, ir)?;
+                self.load(Register::Int2, b, ir)?;
+                ir.push(arithmetic(op));
+                ir.push(Ir::RegMem(Register::Accum, dest.clone()));
+                Some(dest)
+            },
+            Inst::Not(a) => {
+                let dest = self.define(value);
+
+                self.load(Register::Int1, a, ir)?;
+                ir.push(Ir::Not);
+                ir.push(Ir::RegMem(Register::Accum, dest.clone()));
+                Some(dest)
+            },
+            Inst::Compare(op, a, b) => {
+                self.load(Register::Int1, a, ir)?;
+                self.load(Register::Int2, b, ir)?;
+                ir.push(comparison(op));
+                None
+            },
+            Inst::ReadReg(ref reg) => {
+                let dest = self.define(value);
+
+                ir.push(Ir::RegMem(reg.clone(), dest.clone()));
+                Some(dest)
+            },
+            Inst::WriteReg(ref reg, v) => {
+                self.load(reg.clone(), v, ir)?;
+                None
+            },
+            Inst::Raw(ref raw) => {
+                ir.push(Ir::Raw(raw.clone()));
+                None
+            },
+            Inst::Tag(ref key, ref value) => {
+                ir.push(Ir::Tag(key.clone(), value.clone()));
+                None
+            },
+            // Phis are written by their predecessors
+            Inst::Phi(_) => Some(self.define(value)),
+            Inst::Const(_) | Inst::Addr(_) => None
+        };
+
+        // A result that is never used is only computed for its side effects
+        if let Some(dest) = dest {
+            if self.last_use[value.0].is_none() && !self.shared[value.0] {
+                self.release(dest);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Copies the values of the phis of a block coming from another block
+    fn phi_copies(&mut self, from: BlockId, to: BlockId, ir: &mut Vec<Ir>) -> Result<(), BlocksError> {
+        let phis = self.function.blocks[to.0].insts.iter().filter_map(|&phi| match *self.function.inst(phi) {
+            Inst::Phi(ref incoming) => incoming.iter().find(|&&(b, _)| b == from).map(|&(_, v)| (phi, v)),
+            _ => None
+        }).collect::<Vec<_>>();
+
+        let dests = phis.iter().map(|&(phi, _)| self.shared_temp(phi)).collect::<Vec<_>>();
+
+        if phis.is_empty() {
+            return Ok(());
+        }
+
+        if phis.len() == 1 {
+            return self.copy(dests[0].clone(), phis[0].1, ir);
+        }
+
+        // A phi may be copied to another one, so every value is read before any is written
+        let mut scratch = Vec::new();
+
+        for &(_, v) in &phis {
+            let addr = self.local_temp();
+            self.copy(addr.clone(), v, ir)?;
+            scratch.push(addr);
+        }
+
+        for (dest, addr) in dests.into_iter().zip(scratch) {
+            ir.push(Ir::Copy(dest, addr.clone()));
+            self.release(addr);
+        }
+
+        Ok(())
+    }
+
+    fn target(&mut self, target: &Target, ir: &mut Vec<Ir>) -> Result<Address, BlocksError> {
+        match *target {
+            Target::Block(b) => Ok(Address::Variable(self.label(b))),
+            Target::Name(ref name) => Ok(Address::Variable(name.clone())),
+            Target::Static(addr) => Ok(Address::Static(addr)),
+            Target::Indirect(v) => self.in_memory(v, ir)
+        }
+    }
+
+    // Lowers the function into the main code or symbol block it starts, and the symbol blocks
+    // for its labeled blocks
+    fn lower(mut self) -> Result<Vec<(Option<String>, Vec<Ir>)>, BlocksError> {
+        let mut segments = vec![(None, Vec::new())];
+        let last = self.function.blocks.len() - 1;
+
+        // The entry of the main code has no name, so it jumps to its label if it has one
+        if self.index == 0 && self.labeled[0] {
+            segments[0].1.push(Ir::Branch(Address::Variable(self.label(BlockId(0)))));
+        }
+
+        for (i, block) in self.function.blocks.iter().enumerate() {
+            if self.labeled[i] && (i > 0 || self.index == 0) {
+                segments.push((Some(self.label(BlockId(i))), Vec::new()));
+            }
+
+            // Temps of values local to other blocks are free
+            self.free = (0..self.local_count).rev().collect();
+
+            let mut ir = Vec::new();
+
+            for (pos, &value) in block.insts.iter().enumerate() {
+                self.lower_inst(value, pos, &mut ir)?;
+            }
+
+            for succ in block.terminator.successors() {
+                self.phi_copies(BlockId(i), succ, &mut ir)?;
+            }
+
+            let continue_at = |this: &Lowering, next: BlockId, ir: &mut Vec<Ir>| {
+                if !this.falls_through(i, next) {
+                    ir.push(Ir::Branch(Address::Variable(this.label(next))));
+                }
+            };
+
+            match block.terminator {
+                Terminator::Jump(Target::Block(b)) => continue_at(&self, b, &mut ir),
+                Terminator::Jump(Target::Indirect(v)) => {
+                    let addr = self.in_memory(v, &mut ir)?;
+                    ir.push(Ir::IndirBranch(addr));
+                },
+                Terminator::Jump(ref target) => {
+                    let addr = self.target(target, &mut ir)?;
+                    ir.push(Ir::Branch(addr));
+                },
+                Terminator::CondJump(ref target, next) => {
+                    let addr = self.target(target, &mut ir)?;
+                    ir.push(Ir::CondBranch(addr));
+                    continue_at(&self, next, &mut ir);
+                },
+                Terminator::Call(ref target, next) => {
+                    let addr = self.target(target, &mut ir)?;
+                    ir.push(Ir::Call(addr));
+                    continue_at(&self, next, &mut ir);
+                },
+                Terminator::Return => ir.push(Ir::Return),
+                // Main code is followed by the cleanup code, which a block moved elsewhere has to
+                // run itself
+                Terminator::End if i == last && self.index == 0 && segments.len() > 1 => {
+                    ir.push(Ir::Raw(cleanup().to_vec()));
+                },
+                Terminator::End => {}
+            }
+
+            segments.last_mut().unwrap().1.append(&mut ir);
+        }
+
+        if self.index > 0 {
+            segments[0].0 = Some(self.function.name.clone());
+        }
+
+        Ok(segments)
+    }
+}
+
+// Lowers a program to the IR, ready for compile_ir
+// Lowering relies on the program being well formed, such as only the last block running off the end
+// of a function, so a program that isn't is an error
+pub fn lower(program: &Program) -> Result<IrResult, BlocksError> {
+    program.verify().map_err(|e| BlocksError::new(ErrorKind::Other, Token::Other(e)))?;
+
+    let mut result = IrResult {
+        ir: Vec::new(),
+        blocks: BTreeMap::new(),
+        address: Address::Static(-1),
+        var_addr: Address::Static(-1),
+        register: None,
+        deref: false,
+        math: false
+    };
+
+    let volatile = program.functions().iter().flat_map(|f| f.insts.iter()).filter_map(|inst| match *inst {
+        Inst::Tag(ref key, ref value) if key == "volatile" => Some(value.clone()),
+        _ => None
+    }).collect::<HashSet<_>>();
+
+    for (index, function) in program.functions().into_iter().enumerate() {
+        for (name, ir) in Lowering::new(function, index, &volatile).lower()? {
+            match name {
+                Some(name) => {
+                    result.blocks.insert(name, ir);
+                },
+                None => result.ir = ir
+            }
+        }
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,7 @@
+mod ssa;
+mod build;
+mod lower;
+
+pub use self::ssa::*;
+pub use self::build::*;
+pub use self::lower::*;
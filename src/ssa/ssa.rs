@@ -0,0 +1,535 @@
+// A mid-level IR in SSA form, between the tree and the IR.
+// Each symbol block and the main code is a function made of basic blocks. Every instruction is
+// identified by the value it defines, and each value is defined exactly once, so passes can follow
+// a value to its definition without tracking which temp holds what.
+//
+// Variables stay in memory and are accessed with loads and stores, because they can be changed
+// through pointers, raw code and other symbol blocks. Only the intermediate results of expressions
+// are values.
+//
+// Control flow is explicit: a block ends with a terminator whose successors are the CFG edges.
+// `goto`, `ifgoto` and `call` end a block, and the code after `ifgoto` and `call` continues in a
+// new one.
+
+use utils::Register;
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    // The address of a variable or symbol block, which is only known once the program is linked
+    Address
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Xor
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CmpOp {
+    Equals,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual
+}
+
+// A memory location
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Place {
+    Var(String),
+    Static(i32),
+    // The location a value points to
+    Pointer(Value)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inst {
+    Const(i32),
+    // The address of a variable or symbol block
+    Addr(String),
+    Load(Place),
+    Store(Place, Value),
+    Binary(BinOp, Value, Value),
+    Not(Value),
+    // Sets the flag register, which `CondJump` reads
+    Compare(CmpOp, Value, Value),
+    ReadReg(Register),
+    WriteReg(Register, Value),
+    // The value coming from each predecessor of the block
+    Phi(Vec<(BlockId, Value)>),
+    Raw(Vec<i32>),
+    Tag(String, String)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    Block(BlockId),
+    // A symbol block, or any other name the linker resolves
+    Name(String),
+    Static(i32),
+    // The address a value holds
+    Indirect(Value)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Terminator {
+    Jump(Target),
+    // Jumps if the flag register is set, otherwise continues at the block
+    CondJump(Target, BlockId),
+    // Continues at the block when the callee returns
+    Call(Target, BlockId),
+    Return,
+    // Runs off the end of the code, into whatever follows it in memory
+    End
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub insts: Vec<Value>,
+    pub terminator: Terminator
+}
+
+// The entry block is the first one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    // The instruction that defines each value
+    pub insts: Vec<Inst>,
+    pub blocks: Vec<Block>
+}
+
+// Symbol blocks are kept in the order they were declared
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Program {
+    pub main: Function,
+    pub symbols: Vec<Function>
+}
+
+impl Inst {
+    // The values the instruction uses
+    pub fn operands(&self) -> Vec<Value> {
+        match *self {
+            Inst::Load(Place::Pointer(p)) | Inst::Not(p) | Inst::WriteReg(_, p) => vec![p],
+            Inst::Store(Place::Pointer(p), v) => vec![p, v],
+            Inst::Store(_, v) => vec![v],
+            Inst::Binary(_, a, b) | Inst::Compare(_, a, b) => vec![a, b],
+            Inst::Phi(ref incoming) => incoming.iter().map(|&(_, v)| v).collect(),
+            _ => Vec::new()
+        }
+    }
+
+    // Whether the instruction does anything besides defining its value
+    // Loads count, because they fault on addresses outside memory
+    pub fn has_side_effects(&self) -> bool {
+        match *self {
+            Inst::Const(_) | Inst::Addr(_) | Inst::Not(_) | Inst::ReadReg(_) | Inst::Phi(_) => false,
+            Inst::Binary(op, _, _) => op == BinOp::Div,
+            _ => true
+        }
+    }
+}
+
+impl Terminator {
+    // The blocks of the same function control may continue at
+    pub fn successors(&self) -> Vec<BlockId> {
+        match *self {
+            Terminator::Jump(Target::Block(b)) => vec![b],
+            Terminator::CondJump(Target::Block(a), b) | Terminator::Call(Target::Block(a), b) => vec![a, b],
+            Terminator::CondJump(_, b) | Terminator::Call(_, b) => vec![b],
+            _ => Vec::new()
+        }
+    }
+
+    pub fn operands(&self) -> Vec<Value> {
+        match *self {
+            Terminator::Jump(Target::Indirect(v)) | Terminator::CondJump(Target::Indirect(v), _) |
+            Terminator::Call(Target::Indirect(v), _) => vec![v],
+            _ => Vec::new()
+        }
+    }
+}
+
+impl Function {
+    pub fn new(name: &str) -> Function {
+        Function {
+            name: name.to_string(),
+            insts: Vec::new(),
+            blocks: vec![Block {
+                insts: Vec::new(),
+                terminator: Terminator::End
+            }]
+        }
+    }
+
+    pub fn add_block(&mut self) -> BlockId {
+        self.blocks.push(Block {
+            insts: Vec::new(),
+            terminator: Terminator::End
+        });
+
+        BlockId(self.blocks.len() - 1)
+    }
+
+    // Appends an instruction to a block, returning its value
+    pub fn push(&mut self, block: BlockId, inst: Inst) -> Value {
+        self.insts.push(inst);
+
+        let value = Value(self.insts.len() - 1);
+        self.blocks[block.0].insts.push(value);
+
+        value
+    }
+
+    pub fn inst(&self, value: Value) -> &Inst {
+        &self.insts[value.0]
+    }
+
+    // The type of a value, or None if its instruction defines nothing
+    pub fn type_of(&self, value: Value) -> Option<Type> {
+        match *self.inst(value) {
+            Inst::Addr(_) => Some(Type::Address),
+            Inst::Const(_) | Inst::Load(_) | Inst::Binary(..) | Inst::Not(_) | Inst::ReadReg(_) | Inst::Phi(_) => {
+                Some(Type::Int)
+            },
+            _ => None
+        }
+    }
+
+    // The predecessors of every block
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            for succ in block.terminator.successors() {
+                if !preds[succ.0].contains(&BlockId(i)) {
+                    preds[succ.0].push(BlockId(i));
+                }
+            }
+        }
+
+        preds
+    }
+
+    // The blocks reachable from the entry block, in reverse postorder, so each block comes before
+    // its successors except along loops
+    pub fn reachable(&self) -> Vec<BlockId> {
+        let mut seen = vec![false; self.blocks.len()];
+        let mut postorder = Vec::new();
+
+        // Blocks being visited, with the number of their successors visited so far
+        let mut stack = vec![(BlockId(0), 0)];
+        seen[0] = true;
+
+        while let Some(&(block, next)) = stack.last() {
+            match self.blocks[block.0].terminator.successors().get(next) {
+                Some(&succ) => {
+                    stack.last_mut().unwrap().1 += 1;
+
+                    if !seen[succ.0] {
+                        seen[succ.0] = true;
+                        stack.push((succ, 0));
+                    }
+                },
+                None => {
+                    postorder.push(block);
+                    stack.pop();
+                }
+            }
+        }
+
+        postorder.reverse();
+        postorder
+    }
+
+    // The immediate dominator of every reachable block, computed with the iterative algorithm of
+    // Cooper, Harvey and Kennedy. The entry block is its own dominator
+    pub fn dominators(&self) -> Vec<Option<BlockId>> {
+        let preds = self.predecessors();
+        let order = self.reachable();
+        let mut index = vec![usize::MAX; self.blocks.len()];
+
+        for (i, b) in order.iter().enumerate() {
+            index[b.0] = i;
+        }
+
+        let mut idom: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        idom[0] = Some(BlockId(0));
+
+        let intersect = |idom: &[Option<BlockId>], mut a: BlockId, mut b: BlockId| {
+            while a != b {
+                while index[a.0] > index[b.0] {
+                    a = idom[a.0].unwrap();
+                }
+
+                while index[b.0] > index[a.0] {
+                    b = idom[b.0].unwrap();
+                }
+            }
+
+            a
+        };
+
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for &b in &order[1..] {
+                let mut new = None;
+
+                for &p in preds[b.0].iter().filter(|p| idom[p.0].is_some()) {
+                    new = Some(match new {
+                        Some(n) => intersect(&idom, p, n),
+                        None => p
+                    });
+                }
+
+                if new.is_some() && idom[b.0] != new {
+                    idom[b.0] = new;
+                    changed = true;
+                }
+            }
+        }
+
+        idom
+    }
+
+    fn dominates(idom: &[Option<BlockId>], a: BlockId, mut b: BlockId) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+
+            match idom[b.0] {
+                Some(d) if d != b => b = d,
+                _ => return false
+            }
+        }
+    }
+
+    // Checks that the function is in SSA form and its instructions are well typed
+    pub fn verify(&self) -> Result<(), String> {
+        let error = |message: String| Err(format!("{}: {}", self.name, message));
+
+        let mut defined_in = vec![None; self.insts.len()];
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            for &value in &block.insts {
+                if value.0 >= self.insts.len() {
+                    return error(format!("{} is not defined", value));
+                }
+
+                if defined_in[value.0].is_some() {
+                    return error(format!("{} is defined twice", value));
+                }
+
+                defined_in[value.0] = Some((BlockId(i), block.insts.iter().position(|&v| v == value).unwrap()));
+            }
+
+            if block.terminator == Terminator::End && i + 1 != self.blocks.len() {
+                return error(format!("block {} runs off the end of the function, but is not the last block", i));
+            }
+
+            for succ in block.terminator.successors() {
+                if succ.0 >= self.blocks.len() {
+                    return error(format!("block {} jumps to block {}, which does not exist", i, succ.0));
+                }
+            }
+
+            match block.terminator {
+                Terminator::CondJump(Target::Indirect(_), _) | Terminator::Call(Target::Indirect(_), _) => {
+                    return error(format!("block {} has an indirect target that can only be jumped to", i));
+                },
+                _ => {}
+            }
+        }
+
+        // Edges are checked first, since these follow them
+        let preds = self.predecessors();
+        let idom = self.dominators();
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            // Unreachable blocks have no dominators, so uses in them are only checked for definitions
+            let reachable = idom[i].is_some();
+            let mut seen_other = false;
+
+            // Phis use their operands at the end of the predecessor they come from, so they are
+            // checked separately
+            let uses = block.insts.iter()
+                                  .enumerate()
+                                  .filter(|&(_, &v)| !matches!(*self.inst(v), Inst::Phi(_)))
+                                  .flat_map(|(pos, &v)| self.inst(v).operands().into_iter().map(move |o| (Some(pos), o)))
+                                  .chain(block.terminator.operands().into_iter().map(|o| (None, o)));
+
+            for (pos, operand) in uses {
+                let (def_block, def_pos) = match defined_in.get(operand.0).cloned().unwrap_or(None) {
+                    Some(def) => def,
+                    None => return error(format!("{} is used but never defined", operand))
+                };
+
+                if self.type_of(operand).is_none() {
+                    return error(format!("{} is used, but its instruction has no result", operand));
+                }
+
+                let ok = if def_block == BlockId(i) {
+                    pos.is_none_or(|p| def_pos < p)
+                } else {
+                    !reachable || Function::dominates(&idom, def_block, BlockId(i))
+                };
+
+                if !ok {
+                    return error(format!("{} is used where its definition does not dominate", operand));
+                }
+            }
+
+            for &value in &block.insts {
+                let incoming = match *self.inst(value) {
+                    Inst::Phi(ref incoming) => incoming,
+                    _ => {
+                        seen_other = true;
+                        continue;
+                    }
+                };
+
+                if seen_other {
+                    return error(format!("{} is a phi after other instructions", value));
+                }
+
+                let mut from = incoming.iter().map(|&(b, _)| b).collect::<Vec<_>>();
+                let mut expected = preds[i].clone();
+                from.sort();
+                expected.sort();
+
+                if from != expected {
+                    return error(format!("{} does not have one value for each predecessor", value));
+                }
+
+                for &(pred, v) in incoming {
+                    let def_block = match defined_in.get(v.0).cloned().unwrap_or(None) {
+                        Some((b, _)) if self.type_of(v).is_some() => b,
+                        _ => return error(format!("{} is used but never defined", v))
+                    };
+
+                    if idom[pred.0].is_some() && !Function::dominates(&idom, def_block, pred) {
+                        return error(format!("{} does not dominate the end of block {}", v, pred.0));
+                    }
+
+                    // Lowering copies the values into the phi at the end of the predecessor,
+                    // which would also happen on its other edges
+                    if self.blocks[pred.0].terminator.successors().len() > 1 {
+                        return error(format!("block {} has other successors besides the phi {}", pred.0, value));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Program {
+    pub fn functions(&self) -> Vec<&Function> {
+        let mut functions = vec![&self.main];
+        functions.extend(self.symbols.iter());
+        functions
+    }
+
+    pub fn verify(&self) -> Result<(), String> {
+        self.functions().iter().try_for_each(|f| f.verify())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl fmt::Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Place::Var(ref name) => write!(f, "{}", name),
+            Place::Static(addr) => write!(f, "[{}]", addr),
+            Place::Pointer(p) => write!(f, "[{}]", p)
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Block(b) => write!(f, "block {}", b.0),
+            Target::Name(ref name) => write!(f, "{}", name),
+            Target::Static(addr) => write!(f, "{}", addr),
+            Target::Indirect(v) => write!(f, "[{}]", v)
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}:", self.name)?;
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "  block {}:", i)?;
+
+            for &value in &block.insts {
+                let text = match *self.inst(value) {
+                    Inst::Const(n) => format!("const {}", n),
+                    Inst::Addr(ref name) => format!("addr {}", name),
+                    Inst::Load(ref place) => format!("load {}", place),
+                    Inst::Store(ref place, v) => format!("store {} {}", place, v),
+                    Inst::Binary(op, a, b) => format!("{} {} {}", format!("{:?}", op).to_lowercase(), a, b),
+                    Inst::Not(v) => format!("not {}", v),
+                    Inst::Compare(op, a, b) => format!("cmp {} {} {}", format!("{:?}", op).to_lowercase(), a, b),
+                    Inst::ReadReg(ref reg) => format!("readreg {}", ::token::register_name(reg)),
+                    Inst::WriteReg(ref reg, v) => format!("writereg {} {}", ::token::register_name(reg), v),
+                    Inst::Phi(ref incoming) => {
+                        let incoming = incoming.iter().map(|&(b, v)| format!("block {}: {}", b.0, v)).collect::<Vec<_>>();
+                        format!("phi {}", incoming.join(", "))
+                    },
+                    Inst::Raw(ref raw) => format!("raw {:?}", raw),
+                    Inst::Tag(ref key, ref value) => format!("tag {} {}", key, value)
+                };
+
+                match self.type_of(value) {
+                    Some(_) => writeln!(f, "    {} = {}", value, text)?,
+                    None => writeln!(f, "    {}", text)?
+                }
+            }
+
+            match block.terminator {
+                Terminator::Jump(ref target) => writeln!(f, "    jump {}", target)?,
+                Terminator::CondJump(ref target, next) => writeln!(f, "    condjump {} else block {}", target, next.0)?,
+                Terminator::Call(ref target, next) => writeln!(f, "    call {} then block {}", target, next.0)?,
+                Terminator::Return => writeln!(f, "    return")?,
+                Terminator::End => writeln!(f, "    end")?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for function in &self.symbols {
+            writeln!(f, "{}", function)?;
+        }
+
+        write!(f, "{}", self.main)
+    }
+}
@@ -0,0 +1,68 @@
+#[cfg(test)]
+mod tests {
+    use archive::*;
+    use object::{compile_object, link_objects};
+    use golden::run_compiled;
+    use json::Json;
+
+    const INC: &'static str = "symbol inc {\n    set n = + n 1;\n    return;\n}\n";
+    // Needs a symbol from another member
+    const TWICE: &'static str = "symbol twice {\n    call inc;\n    call inc;\n    return;\n}\n";
+    const UNUSED: &'static str = "symbol unused {\n    set u = 5;\n    return;\n}\n";
+    const MAIN: &'static str = "set n = 4;\ncall twice;\nset result = n;\n";
+
+    fn archive() -> Archive {
+        let members = [("inc", INC), ("twice", TWICE), ("unused", UNUSED)].iter().map(|&(name, source)| Member {
+            name: name.to_string(),
+            object: compile_object(source).unwrap()
+        }).collect();
+
+        Archive::new(members).unwrap()
+    }
+
+    #[test]
+    fn test_only_needed_members_are_linked() {
+        let main = compile_object(MAIN).unwrap();
+        let (program, vars) = link_with_archives(&[main.clone()], &[archive()]).unwrap();
+
+        assert!(!vars.contains_key("u"));
+
+        let (expected, _) = link_objects(&[main, compile_object(TWICE).unwrap(), compile_object(INC).unwrap()]).unwrap();
+        assert_eq!(program.len(), expected.len());
+
+        let actual = run_compiled(&program, vars, b"");
+        assert_eq!(actual.vars, vec![("n".to_string(), 6), ("result".to_string(), 6)]);
+
+        // Objects given directly take precedence over archive members
+        let local = compile_object("symbol inc {\n    set n = + n 10;\n    return;\n}\n").unwrap();
+        let (program, vars) = link_with_archives(&[compile_object(MAIN).unwrap(), local], &[archive()]).unwrap();
+        assert!(run_compiled(&program, vars, b"").vars.contains(&("result".to_string(), 24)));
+    }
+
+    #[test]
+    fn test_index() {
+        let archive = archive();
+
+        assert_eq!(archive.index(), vec![("inc", "inc"), ("twice", "twice"), ("unused", "unused")]);
+        assert_eq!(archive.find("twice").map(|m| &m.name as &str), Some("twice"));
+        assert!(archive.find("n").is_none());
+
+        let members = vec![archive.members[0].clone(), archive.members[0].clone()];
+        assert_eq!(Archive::new(members).unwrap_err().code(), 16);
+
+        let main = compile_object("call missing;").unwrap();
+        assert_eq!(link_with_archives(&[main], &[archive]).unwrap_err().code(), 5);
+    }
+
+    #[test]
+    fn test_serialization() {
+        let archive = archive();
+        let text = archive.to_json().to_string();
+
+        assert_eq!(Archive::from_json(&Json::parse(&text).unwrap()), Ok(archive));
+
+        let tampered = text.replacen("\"member\":0", "\"member\":2", 1);
+        assert!(Archive::from_json(&Json::parse(&tampered).unwrap()).is_err());
+        assert!(Archive::from_json(&Json::parse("{\"version\":1}").unwrap()).is_err());
+    }
+}
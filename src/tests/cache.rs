@@ -0,0 +1,95 @@
+#[cfg(test)]
+mod tests {
+    use cache::*;
+    use compile::{build_module, compile_with_vars};
+    use layout::Layout;
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process;
+
+    const LIBRARY: &'static str = "symbol inc {\n    set n = + n 1;\n    return;\n}\n";
+    const MAIN: &'static str = "?var_addr 0;\nset n = 4;\ncall inc;\nset q = / * n 3 ~ n 2;\ncmp >= q 5;\n\
+                                raw `1 2 3`;\nset p = @q;\nset #p = 7;\nset $int1 = q;\nset r = $int1;\n";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("blocks-cache-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_serialization_round_trip() {
+        for prog in &[LIBRARY, MAIN] {
+            let module = Module::from_ir(build_module(prog).unwrap());
+            let json = module_to_json(&module, 1);
+
+            assert_eq!(module_from_json(&json, 1), Some(module.clone()));
+            assert_eq!(module_from_json(&json, 2), None);
+        }
+
+        let module = Module::from_ir(build_module(LIBRARY).unwrap());
+        assert_eq!(module.symbols(), vec!["inc"]);
+        assert!(module.references().contains(&"n"));
+    }
+
+    #[test]
+    fn test_cache_reuses_unchanged_modules() {
+        let dir = temp_dir("reuse");
+        let expected = compile_with_vars(&format!("{}{}", LIBRARY, MAIN)).unwrap();
+        let mut cache = Cache::new(&dir);
+
+        assert_eq!(compile_project(&[LIBRARY, MAIN], Some(&mut cache), &Layout::default()).unwrap(), expected);
+        assert_eq!((cache.hits, cache.misses), (0, 2));
+
+        assert_eq!(compile_project(&[LIBRARY, MAIN], Some(&mut cache), &Layout::default()).unwrap(), expected);
+        assert_eq!((cache.hits, cache.misses), (2, 2));
+
+        // Only the file that changed is rebuilt
+        let changed = format!("{}set extra = 1;\n", MAIN);
+        let expected = compile_with_vars(&format!("{}{}", LIBRARY, changed)).unwrap();
+
+        assert_eq!(compile_project(&[LIBRARY, &changed], Some(&mut cache), &Layout::default()).unwrap(), expected);
+        assert_eq!((cache.hits, cache.misses), (3, 3));
+
+        // Entries are shared by every cache using the same directory
+        let mut other = Cache::new(&dir);
+        assert_eq!(compile_project(&[LIBRARY, &changed], Some(&mut other), &Layout::default()).unwrap(), expected);
+        assert_eq!((other.hits, other.misses), (2, 0));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_entry_is_rebuilt() {
+        let dir = temp_dir("corrupt");
+        let mut cache = Cache::new(&dir);
+        let expected = cache.module(MAIN).unwrap();
+
+        for contents in &["{not json", "{\"version\":1}", "[]"] {
+            let path = dir.join(format!("{:016x}.json", content_hash(MAIN)));
+            File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+
+            assert_eq!(cache.module(MAIN).unwrap(), expected);
+        }
+
+        assert_eq!((cache.hits, cache.misses), (0, 4));
+        assert_eq!(cache.module(MAIN).unwrap(), expected);
+        assert_eq!(cache.hits, 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_errors_name_the_file() {
+        let (file, err) = compile_project(&[LIBRARY, "set p = @missing;"], None, &Layout::default()).unwrap_err();
+        assert_eq!((file, err.code()), (1, 5));
+        assert!(err.span().is_some());
+
+        let (file, err) = compile_project(&[LIBRARY, "set a = ;", MAIN], None, &Layout::default()).unwrap_err();
+        assert_eq!(file, 1);
+        assert_eq!(err.code(), compile_with_vars("set a = ;").unwrap_err().code());
+    }
+}
@@ -0,0 +1,132 @@
+#[cfg(test)]
+mod tests {
+    use compile::build_module;
+    use ir::*;
+    use utils::Address;
+
+    fn cfg(prog: &str) -> Cfg {
+        Cfg::build(&build_module(prog).unwrap())
+    }
+
+    #[test]
+    fn test_basic_blocks() {
+        let cfg = cfg("
+            symbol inc {
+                set n = + n 1;
+                return;
+            }
+            set n = 1;
+            cmp < n 3;
+            ifgoto inc;
+            call inc;
+            set n = 2;
+        ");
+
+        let main = cfg.entry(None).unwrap();
+        let inc = cfg.entry(Some("inc")).unwrap();
+
+        assert_eq!(main, 0);
+        assert_eq!(cfg.blocks.len(), 4);
+        assert_eq!(cfg.blocks[0].edges, vec![(EdgeKind::CondBranch, inc), (EdgeKind::Next, 1)]);
+        assert_eq!(cfg.blocks[1].ir, vec![Ir::Call(Address::new_var("inc"))]);
+        assert_eq!(cfg.blocks[1].edges, vec![(EdgeKind::Call, inc), (EdgeKind::Next, 2)]);
+        assert_eq!(cfg.blocks[inc].ir.last(), Some(&Ir::Return));
+        assert!(cfg.blocks[inc].edges.is_empty());
+        assert!(cfg.blocks.iter().all(|b| b.reachable));
+    }
+
+    #[test]
+    fn test_unreachable_blocks() {
+        let cfg = cfg("
+            symbol unused {
+                set n = 0;
+                return;
+            }
+            symbol done {
+                set n = 3;
+                return;
+            }
+            symbol pointed {
+                set n = 4;
+                return;
+            }
+            set p = @pointed;
+            goto done;
+            set n = 9;
+        ");
+
+        let reachable = |symbol| cfg.blocks[cfg.entry(symbol).unwrap()].reachable;
+
+        assert!(reachable(Some("done")));
+        assert!(reachable(Some("pointed")));
+        assert!(!reachable(Some("unused")));
+
+        // The code after the goto
+        assert_eq!(cfg.blocks[0].edges, vec![(EdgeKind::Branch, cfg.entry(Some("done")).unwrap())]);
+        assert!(!cfg.blocks[1].reachable);
+    }
+
+    #[test]
+    fn test_fall_through() {
+        let cfg = cfg("
+            symbol a {
+                set x = 1;
+            }
+            symbol b {
+                set y = 2;
+                return;
+            }
+            call a;
+        ");
+
+        let a = cfg.entry(Some("a")).unwrap();
+        let b = cfg.entry(Some("b")).unwrap();
+
+        // `a` has no return, so it runs into `b`
+        assert_eq!(cfg.blocks[a].edges, vec![(EdgeKind::Next, b)]);
+        assert!(cfg.blocks[b].edges.is_empty());
+        assert!(cfg.blocks.iter().all(|b| b.reachable));
+        assert!(!format!("{}", cfg).contains("unreachable"));
+    }
+
+    #[test]
+    fn test_runs_into_main() {
+        let cfg = cfg("
+            symbol a {
+                set x = 1;
+                return;
+            }
+            symbol b {
+                set y = 2;
+            }
+            set z = 3;
+            call a;
+        ");
+
+        let b = cfg.entry(Some("b")).unwrap();
+
+        // `b` is placed last and has no return, so it runs into the main code after it
+        assert_eq!(b, cfg.blocks.len() - 1);
+        assert_eq!(cfg.blocks[b].edges, vec![(EdgeKind::Next, 0)]);
+        assert!(!cfg.blocks[b].reachable);
+    }
+
+    #[test]
+    fn test_dot() {
+        let dot = cfg("
+            symbol f {
+                return;
+            }
+            symbol g {
+            }
+            call f;
+        ").to_dot();
+
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("label=\"main\";"));
+        assert!(dot.contains("label=\"f\";"));
+        assert!(dot.contains("b0 -> b1 [label=\"call\", style=dashed];"));
+        assert!(dot.contains("b2 [label=\"b2:\\l(empty)\\l\", style=\"filled,dashed\", fillcolor=\"#f4cccc\"];"));
+        assert_eq!(dot.matches("fillcolor").count(), 1);
+    }
+}
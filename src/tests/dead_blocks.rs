@@ -0,0 +1,197 @@
+#[cfg(test)]
+mod tests {
+    use compile::{build_module_at, compile_at, link_with_report};
+    use compile_utils::get_code_size;
+    use golden::run_compiled;
+    use ir::*;
+    use layout::Layout;
+    use opt::OptLevel;
+    use utils::Address;
+
+    use std::collections::BTreeMap;
+
+    fn removed(prog: &str, level: OptLevel) -> Vec<String> {
+        let mut ir = build_module_at(prog, level).unwrap();
+        remove_dead_blocks(&mut ir, level).into_iter().map(|b| b.name).collect()
+    }
+
+    #[test]
+    fn test_reachability() {
+        let prog = "
+            symbol called {
+                call nested;
+                return;
+            }
+            symbol nested {
+                set a = 1;
+                return;
+            }
+            symbol branched {
+                set b = 2;
+                return;
+            }
+            symbol jumped {
+                set c = 3;
+                return;
+            }
+            symbol pointed {
+                set d = 4;
+                return;
+            }
+            symbol unused {
+                call unused_too;
+                return;
+            }
+            symbol unused_too {
+                set e = 5;
+                return;
+            }
+            set p = @pointed;
+            call called;
+            cmp == a 0;
+            ifgoto branched;
+            goto jumped;
+        ";
+
+        assert_eq!(removed(prog, OptLevel::O2), vec!["unused", "unused_too"]);
+        assert!(removed(prog, OptLevel::O1).is_empty());
+        assert!(removed(prog, OptLevel::O0).is_empty());
+
+        let (code, vars) = compile_at(prog, OptLevel::O2).unwrap();
+        let result = run_compiled(&code, vars.clone(), b"");
+
+        assert!(result.vars.contains(&("a".to_string(), 1)));
+        assert!(result.vars.contains(&("c".to_string(), 3)));
+        assert!(!vars.contains_key("e"));
+    }
+
+    #[test]
+    fn test_levels() {
+        let prog = "
+            symbol kept {
+                ?opt 0;
+                set a = 1;
+                return;
+            }
+            symbol dropped {
+                ?opt 2;
+                set b = 2;
+                return;
+            }
+            set c = 3;
+        ";
+
+        // The level of each block decides whether it can be removed
+        assert_eq!(removed(prog, OptLevel::O2), vec!["dropped"]);
+        assert_eq!(removed(prog, OptLevel::O0), vec!["dropped"]);
+    }
+
+    #[test]
+    fn test_fall_through() {
+        let mut blocks = BTreeMap::new();
+
+        blocks.insert("first".to_string(), vec![Ir::Write(Address::new_var("a"), Address::Static(1))]);
+        blocks.insert("second".to_string(), vec![Ir::Return]);
+        blocks.insert("third".to_string(), vec![Ir::Return]);
+
+        let mut ir = IrResult {
+            ir: vec![Ir::Call(Address::new_var("first"))],
+            blocks: blocks,
+            address: Address::Static(-1),
+            var_addr: Address::Static(-1),
+            register: None,
+            deref: false,
+            math: false
+        };
+
+        // `first` runs into `second`, which returns before `third`
+        assert_eq!(remove_dead_blocks(&mut ir, OptLevel::O2), vec![RemovedBlock { name: "third".to_string(), size: 1 }]);
+    }
+
+    #[test]
+    fn test_report() {
+        let prog = "
+            symbol big {
+                set a = + 1 2;
+                set b = * a 3;
+                return;
+            }
+            symbol small {
+                return;
+            }
+            set c = 1;
+        ";
+
+        let ir = build_module_at(prog, OptLevel::O2).unwrap();
+        let mut allocated = build_module_at(prog, OptLevel::O2).unwrap();
+        allocate_registers_at(&mut allocated, OptLevel::O2);
+
+        // Blocks are removed after registers are allocated
+        let sizes = ["big", "small"].iter().map(|n| get_code_size(&allocated.blocks[*n])).collect::<Vec<_>>();
+        let (image, _, removed) = link_with_report(ir, &Layout::default(), OptLevel::O2).unwrap();
+
+        assert_eq!(removed, vec![
+            RemovedBlock { name: "big".to_string(), size: sizes[0] },
+            RemovedBlock { name: "small".to_string(), size: sizes[1] }
+        ]);
+
+        assert_eq!(image.code, compile_at("set c = 1;", OptLevel::O2).unwrap().0);
+    }
+
+    #[test]
+    fn test_jumps_anywhere() {
+        // The raw code jumps into `helper`, which nothing else refers to
+        let prog = "
+            symbol helper {
+                set h = 5;
+                return;
+            }
+            raw `33 0`;
+            set done = 1;
+        ";
+
+        let computed = "
+            symbol target {
+                set t = 1;
+                return;
+            }
+            set p = @target;
+            goto + p 0;
+        ";
+
+        for &level in &[OptLevel::O1, OptLevel::O2, OptLevel::O3] {
+            assert!(removed(prog, level).is_empty());
+            assert!(removed(computed, level).is_empty());
+
+            let (code, vars) = compile_at(prog, level).unwrap();
+            let result = run_compiled(&code, vars, b"");
+
+            assert_eq!(result.fault, None);
+            assert!(result.vars.contains(&("done".to_string(), 1)));
+        }
+    }
+
+    #[test]
+    fn test_static_targets() {
+        let prog = "
+            symbol a {
+                set x = 5;
+                return;
+            }
+            set y = 1;
+            call 0;
+            return;
+        ";
+
+        for stmt in &["call 0;", "goto 5;", "ifgoto 3;"] {
+            assert!(removed(&prog.replace("call 0;", stmt), OptLevel::O2).is_empty());
+        }
+
+        // `a` is still placed, so the code is longer than without it
+        let (code, vars) = compile_at(prog, OptLevel::O2).unwrap();
+        let (without, _) = compile_at("set y = 1;\ncall 0;\nreturn;", OptLevel::O2).unwrap();
+
+        assert!(vars.contains_key("x"));
+        assert!(code.len() > without.len());
+    }
+}
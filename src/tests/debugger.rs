@@ -0,0 +1,63 @@
+#[cfg(test)]
+mod tests {
+    use debugger::Debugger;
+    use debug_info::compile_with_debug_info;
+
+    const PROG: &'static str = "symbol double {
+    set x = * x 2;
+    return;
+}
+
+set x = 21;
+call double;
+set y = + x 1;
+";
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = Debugger::from_source(PROG).unwrap();
+
+        assert!(debugger.command("break double").starts_with("Breakpoint at double"));
+        assert!(debugger.command("break 7").starts_with("Breakpoint at 7"));
+        assert_eq!(debugger.command("break 4"), "No code on line 4");
+
+        assert!(debugger.command("continue").contains("Call(Variable(\"double\")) (main, line 7)"));
+        assert_eq!(debugger.command("print x"), "x = 21");
+
+        assert!(debugger.command("continue").contains("(symbol double, line 2)"));
+        assert_eq!(debugger.command("continue"), "Program halted");
+        assert_eq!(debugger.command("print y"), "y = 43");
+    }
+
+    #[test]
+    fn test_stepping() {
+        let mut debugger = Debugger::from_source(PROG).unwrap();
+
+        debugger.command("break 6");
+        debugger.command("continue");
+
+        assert!(debugger.command("step").contains("(main, line 7)"));
+        assert!(debugger.command("step").contains("(symbol double, line 2)"));
+        assert!(debugger.command("step").contains("(symbol double, line 3)"));
+        assert!(debugger.command("stepi").contains("(main, line 8)"));
+
+        let pc = debugger.machine.pc();
+        debugger.command("stepi");
+        assert_eq!(debugger.machine.pc(), pc + 3);
+
+        assert_eq!(debugger.command("print $pcounter"), format!("$pcounter = {}", pc + 3));
+        assert_eq!(debugger.command("print z"), "Unknown variable: z");
+    }
+
+    #[test]
+    fn test_step_is_bounded() {
+        // A branch to itself never reaches a statement
+        let (_, mut info) = compile_with_debug_info("").unwrap();
+        info.entries.clear();
+
+        let mut debugger = Debugger::new(&[29, 0], info);
+
+        assert!(debugger.command("step").starts_with("Stopped after 10000000 steps"));
+        assert_eq!(debugger.machine.pc(), 0);
+    }
+}
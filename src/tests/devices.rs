@@ -0,0 +1,66 @@
+#[cfg(test)]
+mod tests {
+    use emulator::Machine;
+    use emulator::devices::*;
+    use debug_info::compile_with_debug_info;
+
+    // Echoes the console input back, upper casing ASCII letters
+    const ECHO: &'static str = "
+symbol loop {
+    set console = ~ 65520 $segd;
+    set in = + console 1;
+    set status = + console 2;
+
+    set c = #in;
+    cmp >= c 97;
+    set lower = $flag;
+    set c = ~ c * lower 32;
+    set + console 0 = c;
+
+    set more = #status;
+    cmp == more 1;
+    ifgoto loop;
+    return;
+}
+
+call loop;
+return;
+";
+
+    #[test]
+    fn test_console() {
+        let (program, _) = compile_with_debug_info(ECHO).unwrap();
+        let mut machine = Machine::new(&program);
+        let (console, output) = Console::buffered(b"Hi, mybytes!");
+
+        attach_standard_devices(&mut machine, console);
+
+        assert!(machine.run(100000).unwrap());
+        assert_eq!(output.to_string_lossy(), "HI, MYBYTES!");
+    }
+
+    #[test]
+    fn test_status_and_timer() {
+        // regwrite $segd 0; regcopy $int1 65522; regcopy $int2 65521; regcopy $int3 65528; ret
+        let program = [10, 8, 0, 11, 0, 65522, 11, 1, 65521, 11, 2, 65528, 35];
+        let mut machine = Machine::new(&program);
+        let (console, _) = Console::buffered(b"x");
+
+        attach_standard_devices(&mut machine, console);
+
+        assert!(machine.run(100).unwrap());
+        assert_eq!(machine.registers[0..3], [1, 'x' as i32, 3]);
+
+        // Devices shadow memory, which is left untouched
+        assert_eq!(machine.read(65521), Ok(0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_overlapping_devices() {
+        let mut machine = Machine::new(&[]);
+
+        machine.attach(10, Box::new(Timer::default()));
+        machine.attach(8, Box::new(Console::buffered(b"").0));
+    }
+}
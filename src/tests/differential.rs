@@ -0,0 +1,445 @@
+// Differential tests between optimization levels.
+// Random well-formed programs are compiled at every level and run on the emulator, and the final
+// values of their variables, console output and outcome must match. Registers are not compared,
+// since levels are free to use them differently for temporaries.
+// When a program fails, it is shrunk to a minimal reproducer before being reported.
+
+#[cfg(test)]
+mod tests {
+    use compile::{compile_at, compile_with_vars, setup_size};
+    use debug_info::compile_with_debug_info;
+    use emulator::Machine;
+    use emulator::devices::{Console, attach_standard_devices};
+    use opt::OptLevel;
+
+    use std::collections::HashMap;
+    use std::fmt;
+
+    const CASES: usize = 300;
+    const SEED: u64 = 0x9e3779b97f4a7c15;
+    const MAX_STEPS: u64 = 100000;
+
+    type Compiler = fn(&str) -> Result<(Vec<i32>, HashMap<String, i32>), usize>;
+
+    fn unoptimized(prog: &str) -> Result<(Vec<i32>, HashMap<String, i32>), usize> {
+        compile_with_debug_info(prog).map(|(code, info)| (code, info.vars)).map_err(|e| e.code())
+    }
+
+    fn default(prog: &str) -> Result<(Vec<i32>, HashMap<String, i32>), usize> {
+        compile_with_vars(prog).map_err(|e| e.code())
+    }
+
+    fn level(prog: &str, level: OptLevel) -> Result<(Vec<i32>, HashMap<String, i32>), usize> {
+        compile_at(prog, level).map_err(|e| e.code())
+    }
+
+    fn o0(prog: &str) -> Result<(Vec<i32>, HashMap<String, i32>), usize> {
+        level(prog, OptLevel::O0)
+    }
+
+    fn o2(prog: &str) -> Result<(Vec<i32>, HashMap<String, i32>), usize> {
+        level(prog, OptLevel::O2)
+    }
+
+    fn o3(prog: &str) -> Result<(Vec<i32>, HashMap<String, i32>), usize> {
+        level(prog, OptLevel::O3)
+    }
+
+    fn os(prog: &str) -> Result<(Vec<i32>, HashMap<String, i32>), usize> {
+        level(prog, OptLevel::Os)
+    }
+
+    const LEVELS: &'static [(&'static str, Compiler)] = &[
+        ("unoptimized", unoptimized),
+        ("-O0", o0),
+        ("-O1", default),
+        ("-O2", o2),
+        ("-O3", o3),
+        ("-Os", os)
+    ];
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    enum Expr {
+        Number(i32),
+        Var(String),
+        // Operator, which is one of `+`, `~`, `*` and `^`
+        Binary(&'static str, Box<Expr>, Box<Expr>)
+    }
+
+    #[derive(Clone, Debug)]
+    enum Stmt {
+        Set(String, Expr),
+        Call(String),
+        // Jumps to a symbol block, which ends the program when it returns
+        Branch(&'static str, Expr, Expr, String),
+        // Sets the optimization level or inlining of the file or symbol block
+        Tag(&'static str, &'static str)
+    }
+
+    // Symbol blocks only use their own variables, since they are compiled before the main program
+    // A block that doesn't return runs into the next one, so the last block always returns
+    #[derive(Clone, Debug)]
+    struct Block {
+        name: String,
+        body: Vec<Stmt>,
+        returns: bool
+    }
+
+    #[derive(Clone, Debug)]
+    struct Program {
+        blocks: Vec<Block>,
+        main: Vec<Stmt>
+    }
+
+    impl fmt::Display for Expr {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                Expr::Number(n) => write!(f, "{}", n),
+                Expr::Var(ref name) => write!(f, "{}", name),
+                Expr::Binary(op, ref a, ref b) => write!(f, "{} {} {}", op, a, b)
+            }
+        }
+    }
+
+    impl fmt::Display for Stmt {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                Stmt::Set(ref name, ref value) => writeln!(f, "set {} = {};", name, value),
+                Stmt::Call(ref name) => writeln!(f, "call {};", name),
+                Stmt::Branch(op, ref a, ref b, ref target) => writeln!(f, "cmp {} {} {};\nifgoto {};", op, a, b, target),
+                Stmt::Tag(name, value) => writeln!(f, "?{} {};", name, value)
+            }
+        }
+    }
+
+    impl fmt::Display for Program {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            for (i, block) in self.blocks.iter().enumerate() {
+                writeln!(f, "symbol {} {{", block.name)?;
+
+                for stmt in &block.body {
+                    write!(f, "{}", stmt)?;
+                }
+
+                if block.returns || i + 1 == self.blocks.len() {
+                    writeln!(f, "return;")?;
+                }
+
+                writeln!(f, "}}")?;
+            }
+
+            for stmt in &self.main {
+                write!(f, "{}", stmt)?;
+            }
+
+            writeln!(f, "return;")
+        }
+    }
+
+    fn gen_expr(rng: &mut Rng, vars: &[String], depth: usize) -> Expr {
+        match rng.below(if depth == 0 { 2 } else { 4 }) {
+            0 if !vars.is_empty() => Expr::Var(vars[rng.below(vars.len())].clone()),
+            0 | 1 => Expr::Number(rng.below(41) as i32 - 20),
+            _ => {
+                let op = ["+", "~", "*", "^"][rng.below(4)];
+                Expr::Binary(op, Box::new(gen_expr(rng, vars, depth - 1)), Box::new(gen_expr(rng, vars, depth - 1)))
+            }
+        }
+    }
+
+    // Generates assignments, adding the variables they declare to `vars`
+    fn gen_sets(rng: &mut Rng, prefix: &str, vars: &mut Vec<String>, count: usize) -> Vec<Stmt> {
+        (0..count).map(|_| {
+            let value = gen_expr(rng, vars, 3);
+            let name = if vars.is_empty() || rng.below(3) == 0 {
+                let name = format!("{}{}", prefix, vars.len());
+                vars.push(name.clone());
+                name
+            } else {
+                vars[rng.below(vars.len())].clone()
+            };
+
+            Stmt::Set(name, value)
+        }).collect()
+    }
+
+    // Sometimes overrides the level the program is compiled at, or whether a block is inlined
+    fn gen_tags(rng: &mut Rng, block: bool) -> Vec<Stmt> {
+        let mut tags = Vec::new();
+
+        if rng.below(8) == 0 {
+            tags.push(Stmt::Tag("opt", ["0", "1", "2", "3", "s"][rng.below(5)]));
+        }
+
+        if block && rng.below(8) == 0 {
+            tags.push(Stmt::Tag("inline", ["always", "never"][rng.below(2)]));
+        }
+
+        tags
+    }
+
+    fn gen_program(rng: &mut Rng) -> Program {
+        let blocks = (0..rng.below(3)).map(|i| {
+            let name = format!("b{}", i);
+            let count = 1 + rng.below(4);
+            let mut body = gen_tags(rng, true);
+
+            body.extend(gen_sets(rng, &format!("{}_", name), &mut Vec::new(), count));
+
+            Block {
+                body: body,
+                name: name,
+                returns: rng.below(3) != 0
+            }
+        }).collect::<Vec<_>>();
+
+        let mut vars = Vec::new();
+        let mut main = gen_tags(rng, false);
+
+        for _ in 0..1 + rng.below(6) {
+            let count = 1 + rng.below(3);
+            main.extend(gen_sets(rng, "v", &mut vars, count));
+
+            if !blocks.is_empty() {
+                let target = blocks[rng.below(blocks.len())].name.clone();
+
+                match rng.below(3) {
+                    0 => main.push(Stmt::Call(target)),
+                    1 => {
+                        let op = ["==", "<", ">", "<=", ">="][rng.below(5)];
+                        main.push(Stmt::Branch(op, gen_expr(rng, &vars, 1), gen_expr(rng, &vars, 1), target));
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        Program {
+            blocks: blocks,
+            main: main
+        }
+    }
+
+    // The observable result of running a program: its variables, output and how it ended
+    type Outcome = Result<(Vec<(String, i32)>, Vec<u8>, String), usize>;
+
+    fn run(compiler: Compiler, prog: &str) -> Outcome {
+        let (code, vars) = compiler(prog)?;
+        let mut machine = Machine::new(&code);
+        let (console, output) = Console::buffered(b"");
+
+        attach_standard_devices(&mut machine, console);
+
+        let end = match machine.run(MAX_STEPS) {
+            Ok(true) => "halted".to_string(),
+            Ok(false) => "did not halt".to_string(),
+            Err(fault) => fault.to_string()
+        };
+
+        let mut values = vars.iter()
+                             .filter(|v| !v.0.starts_with("__temp_"))
+                             .map(|(name, addr)| (name.clone(), machine.read((setup_size() as i32 + addr) as i64).unwrap_or(0)))
+                             .collect::<Vec<_>>();
+
+        values.sort();
+
+        Ok((values, output.contents(), end))
+    }
+
+    // Removes the variables of symbol blocks the main program never reaches, which optimized builds
+    // leave out along with the blocks
+    // Blocks are generated in the order of their names, which is the order they are placed in
+    fn reachable_vars(outcome: Outcome, program: &Program) -> Outcome {
+        let mut dead = Vec::new();
+        let mut runs_on = false;
+
+        for block in &program.blocks {
+            let used = runs_on || program.main.iter().any(|stmt| match *stmt {
+                Stmt::Call(ref target) | Stmt::Branch(_, _, _, ref target) => *target == block.name,
+                _ => false
+            });
+
+            if !used {
+                dead.push(format!("{}_", block.name));
+            }
+
+            runs_on = used && !block.returns;
+        }
+
+        outcome.map(|(vars, output, end)| {
+            let vars = vars.into_iter().filter(|v| !dead.iter().any(|prefix| v.0.starts_with(prefix))).collect();
+            (vars, output, end)
+        })
+    }
+
+    // Returns a description of how the levels disagree, if they do
+    fn mismatch(program: &Program) -> Option<String> {
+        let source = program.to_string();
+        let (base_name, base) = LEVELS[0];
+        let expected = reachable_vars(run(base, &source), program);
+
+        for &(name, compiler) in &LEVELS[1..] {
+            let actual = reachable_vars(run(compiler, &source), program);
+
+            if actual != expected {
+                return Some(format!("{}: {:?}\n{}: {:?}", base_name, expected, name, actual));
+            }
+        }
+
+        None
+    }
+
+    fn shrink_expr(expr: &Expr) -> Vec<Expr> {
+        match *expr {
+            Expr::Number(0) => Vec::new(),
+            Expr::Number(_) | Expr::Var(_) => vec![Expr::Number(0)],
+            Expr::Binary(op, ref a, ref b) => {
+                let mut result = vec![(**a).clone(), (**b).clone()];
+
+                result.extend(shrink_expr(a).into_iter().map(|a| Expr::Binary(op, Box::new(a), b.clone())));
+                result.extend(shrink_expr(b).into_iter().map(|b| Expr::Binary(op, a.clone(), Box::new(b))));
+                result
+            }
+        }
+    }
+
+    fn shrink_stmts(stmts: &[Stmt]) -> Vec<Vec<Stmt>> {
+        let mut result = Vec::new();
+
+        for i in 0..stmts.len() {
+            let mut removed = stmts.to_vec();
+            removed.remove(i);
+            result.push(removed);
+
+            let simpler = match stmts[i] {
+                Stmt::Set(ref name, ref value) => {
+                    shrink_expr(value).into_iter().map(|v| Stmt::Set(name.clone(), v)).collect()
+                },
+                Stmt::Branch(op, ref a, ref b, ref target) => {
+                    let mut result = shrink_expr(a).into_iter()
+                                                   .map(|a| Stmt::Branch(op, a, b.clone(), target.clone()))
+                                                   .collect::<Vec<_>>();
+
+                    result.extend(shrink_expr(b).into_iter().map(|b| Stmt::Branch(op, a.clone(), b, target.clone())));
+                    result
+                },
+                Stmt::Call(..) | Stmt::Tag(..) => Vec::new()
+            };
+
+            for stmt in simpler {
+                let mut changed = stmts.to_vec();
+                changed[i] = stmt;
+                result.push(changed);
+            }
+        }
+
+        result
+    }
+
+    // Smaller variations of a program, which may not be well-formed
+    fn shrink_program(program: &Program) -> Vec<Program> {
+        let mut result = Vec::new();
+
+        for i in 0..program.blocks.len() {
+            let mut blocks = program.blocks.clone();
+            blocks.remove(i);
+            result.push(Program { blocks: blocks, main: program.main.clone() });
+
+            if !program.blocks[i].returns {
+                let mut blocks = program.blocks.clone();
+                blocks[i].returns = true;
+                result.push(Program { blocks: blocks, main: program.main.clone() });
+            }
+
+            for body in shrink_stmts(&program.blocks[i].body) {
+                let mut blocks = program.blocks.clone();
+                blocks[i].body = body;
+                result.push(Program { blocks: blocks, main: program.main.clone() });
+            }
+        }
+
+        for main in shrink_stmts(&program.main) {
+            result.push(Program { blocks: program.blocks.clone(), main: main });
+        }
+
+        result
+    }
+
+    // Repeatedly takes the first smaller program that still fails
+    fn shrink(mut program: Program) -> Program {
+        while let Some(smaller) = shrink_program(&program).into_iter().find(|p| mismatch(p).is_some()) {
+            program = smaller;
+        }
+
+        program
+    }
+
+    #[test]
+    fn test_levels_agree() {
+        let mut rng = Rng(SEED);
+
+        for case in 0..CASES {
+            let program = gen_program(&mut rng);
+
+            if mismatch(&program).is_some() {
+                let program = shrink(program);
+
+                panic!("Optimization levels disagree on case {}, shrunk to:\n{}\n{}",
+                       case, program, mismatch(&program).unwrap());
+            }
+        }
+    }
+
+    // A miscompilation must be shrunk down to a single statement
+    #[test]
+    fn test_shrinking() {
+        // Pretends `*` was miscompiled as `+`
+        let broken = |program: &Program| {
+            let source = program.to_string();
+            run(LEVELS[0].1, &source) != run(LEVELS[0].1, &source.replace("* ", "+ "))
+        };
+
+        let mut rng = Rng(SEED);
+        let mut program = (0..CASES).map(|_| gen_program(&mut rng))
+                                    .find(|p| p.main.len() > 4 && broken(p))
+                                    .unwrap();
+
+        while let Some(smaller) = shrink_program(&program).into_iter().find(|p| broken(p)) {
+            program = smaller;
+        }
+
+        assert_eq!(program.blocks.len() + program.main.len(), 1, "not minimal:\n{}", program);
+    }
+
+    #[test]
+    fn test_generated_programs_run() {
+        let mut rng = Rng(SEED);
+        let mut halted = 0;
+
+        for _ in 0..CASES {
+            if let Ok((_, _, end)) = run(LEVELS[0].1, &gen_program(&mut rng).to_string()) {
+                if end == "halted" {
+                    halted += 1;
+                }
+            }
+        }
+
+        // Most programs should compile and halt, or the tests above prove little
+        assert!(halted * 10 >= CASES * 9, "only {} of {} programs halted", halted, CASES);
+    }
+}
@@ -0,0 +1,90 @@
+#[cfg(test)]
+mod tests {
+    use doc::*;
+    use utils::Register;
+
+    const LIB: &'static str = "/// Compares `$int1` and `$int2`.
+///
+/// Sets `$flag` if the first is larger.
+symbol greater {
+    set a = $int1;
+    set b = $int2;
+    cmp > a b;
+    return;
+}
+
+// Not documentation
+
+symbol check {
+    call greater;
+    call _log;
+    return;
+}
+
+symbol _log {
+    raw `1 2 3`;
+    return;
+}
+
+/// Scratch value
+set a = 0;
+set b = 0;
+";
+
+    #[test]
+    fn test_build_docs() {
+        let items = build_docs(LIB).unwrap();
+
+        assert_eq!(items.len(), 3);
+
+        assert_eq!(items[0].name, "greater");
+        assert_eq!(items[0].doc, "Compares `$int1` and `$int2`.\n\nSets `$flag` if the first is larger.");
+        assert_eq!(items[0].inputs, vec![Register::Int1, Register::Int2]);
+        assert_eq!(items[0].clobbers, vec![Register::Int1, Register::Int2, Register::Flag]);
+        assert!(!items[0].raw);
+
+        assert_eq!(items[1].name, "check");
+        assert_eq!(items[1].doc, "");
+        assert_eq!(items[1].calls, vec!["greater".to_string(), "_log".to_string()]);
+        assert!(items[1].raw);
+
+        assert_eq!(items[2].kind, DocKind::Variable);
+        assert_eq!(items[2].name, "a");
+        assert_eq!(items[2].doc, "Scratch value");
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let markdown = render_markdown("lib", &build_docs(LIB).unwrap());
+
+        let expected = "# lib
+
+## Symbols
+
+### `greater`
+
+Compares `$int1` and `$int2`.
+
+Sets `$flag` if the first is larger.
+
+- Inputs: `$int1`, `$int2`
+- Clobbers: `$int1`, `$int2`, `$flag`
+
+### `check`
+
+- Inputs: `$int1`, `$int2`
+- Clobbers: `$int1`, `$int2`, `$flag`
+- Calls: `greater`
+- Contains inline machine code, so other registers may also be used
+
+## Variables
+
+### `a`
+
+Scratch value
+
+";
+
+        assert_eq!(markdown, expected);
+    }
+}
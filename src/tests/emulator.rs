@@ -0,0 +1,52 @@
+#[cfg(test)]
+mod tests {
+    use emulator::*;
+    use debug_info::compile_with_debug_info;
+    use utils::Register;
+
+    fn run(prog: &str, vars: &[&str]) -> Vec<i32> {
+        let (program, info) = compile_with_debug_info(prog).unwrap();
+        let mut machine = Machine::new(&program);
+
+        assert!(machine.run(100000).unwrap());
+
+        vars.iter().map(|v| machine.read(info.var_address(v).unwrap() as i64).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let prog = include_str!("programs/arithmetic.blk");
+
+        assert_eq!(run(prog, &["sum", "diff", "prod", "mixed"]), vec![12, 2, 35, 7]);
+    }
+
+    #[test]
+    fn test_calls_and_branches() {
+        assert_eq!(run(include_str!("programs/emulator_symbols.blk"), &["x", "done"]), vec![42, 1]);
+        assert_eq!(run(include_str!("programs/emulator_branches.blk"), &["result", "end"]), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_faults() {
+        let mut machine = Machine::new(&[10, 0, 4, 10, 1, 0, 19, 0, 1]);
+
+        machine.step().unwrap();
+        machine.step().unwrap();
+
+        assert_eq!(machine.step(), Err(Fault::DivideByZero));
+        assert_eq!(machine.pc(), 6);
+        assert_eq!(machine.register(Register::Int1), 4);
+
+        let mut machine = Machine::new(&[99]);
+        assert_eq!(machine.step(), Err(Fault::InvalidOpcode(99)));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let memory = [11, 0, 4, 13, 2, 5, 29, 7];
+
+        assert_eq!(disassemble(&memory, 0), "regcopy $int1 4");
+        assert_eq!(disassemble(&memory, 3), "regmem 2 $accum");
+        assert_eq!(disassemble(&memory, 6), "br 7");
+    }
+}
@@ -0,0 +1,56 @@
+#[cfg(test)]
+mod tests {
+    use compile::compile;
+    use error::*;
+    use json::Json;
+    use token::Token;
+
+    #[test]
+    fn test_parse_error_span() {
+        let prog = "set foo = 1;\nsymbol 5 { return; }\n";
+
+        let err = compile(prog).unwrap_err();
+
+        assert_eq!(err.code(), ErrorKind::SymbolNameType as usize);
+        assert_eq!(err.span(), Some(Span::new((2, 1), (2, 7))));
+    }
+
+    #[test]
+    fn test_located_error_span() {
+        let prog = "set foo = 1;\nset bar = baz;\n";
+
+        let err = compile(prog).unwrap_err();
+
+        assert_eq!(err.message(), "Use of undeclared variable: baz");
+        assert_eq!(err.span(), Some(Span::new((2, 11), (2, 14))));
+    }
+
+    #[test]
+    fn test_json_diagnostic() {
+        let err = compile("set foo = bar;").unwrap_err();
+
+        let expected = Json::object(vec![
+            ("code", Json::Number(5)),
+            ("severity", Json::string("error")),
+            ("message", Json::string("Use of undeclared variable: bar")),
+            ("file", Json::string("foo.blk")),
+            ("span", Span::new((1, 11), (1, 14)).to_json()),
+            ("suggestion", Json::string("assign to the variable with `set` before using it"))
+        ]);
+
+        assert_eq!(err.to_json("foo.blk"), expected);
+        assert_eq!(format!("{}", Json::string("a \"b\"\n")), "\"a \\\"b\\\"\\n\"");
+    }
+
+    #[test]
+    fn test_codes_are_stable() {
+        assert_eq!(ErrorKind::TagError as usize, 13);
+        assert_eq!(ErrorKind::Other as usize, 14);
+        assert_eq!(ErrorKind::TooDeep as usize, 15);
+        assert_eq!(ErrorKind::DuplicateSymbol as usize, 16);
+        assert_eq!(ErrorKind::LayoutError as usize, 17);
+
+        let err = BlocksError::new(ErrorKind::Other, Token::Other("x".to_string()));
+        assert_eq!(err.message(), "Unknown error at token: x");
+    }
+}
@@ -0,0 +1,65 @@
+#[cfg(test)]
+mod tests {
+    use formatter::format;
+    use tree::build_token_tree;
+
+    const MESSY: &'static str = "// Adds two numbers
+set   a = + 1 2;// sum
+set b=a; // copy
+
+
+
+symbol   foo {
+  set x = * a 2;   // double
+    return;
+  // trailing in block
+}
+symbol e {   }
+cmp > a b;
+ifgoto foo;
+set #a = ~ 1 2; // end
+// eof comment";
+
+    const FORMATTED: &'static str = "// Adds two numbers
+set a = + 1 2; // sum
+set b = a;     // copy
+
+symbol foo {
+    set x = * a 2; // double
+    return;
+    // trailing in block
+}
+symbol e {}
+cmp > a b;
+ifgoto foo;
+set #a = ~ 1 2; // end
+// eof comment
+";
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(MESSY).unwrap(), FORMATTED);
+    }
+
+    #[test]
+    fn test_format_idempotent() {
+        assert_eq!(format(FORMATTED).unwrap(), FORMATTED);
+    }
+
+    #[test]
+    fn test_format_preserves_tree() {
+        let prog = "symbol loop { set $accum = @loop; goto #$accum; } raw `1 2  3`; call loop;";
+
+        let formatted = format(prog).unwrap();
+
+        assert_eq!(build_token_tree(prog.to_string()).unwrap(),
+                   build_token_tree(formatted).unwrap());
+    }
+
+    #[test]
+    fn test_format_comment_after_block() {
+        assert_eq!(format("symbol foo { return; } // x").unwrap(), "symbol foo {\n    return;\n} // x\n");
+        assert_eq!(format("symbol foo { // x\nreturn; }").unwrap(), "symbol foo { // x\n    return;\n}\n");
+        assert_eq!(format("set a = 1; set b = 2; // x").unwrap(), "set a = 1;\nset b = 2; // x\n");
+    }
+}
@@ -0,0 +1,46 @@
+#[cfg(test)]
+mod tests {
+    use compile::compile_with_vars;
+    use token::build_tokens;
+    use tree::{build_token_tree, MAX_DEPTH};
+    use emulator::Machine;
+    use error::BlocksError;
+
+    use std::thread;
+
+    // Inputs that used to panic, found by the fuzz targets in `fuzz/`
+    #[test]
+    fn test_crashes() {
+        assert!(!build_tokens("é".to_string()).is_empty());
+        assert!(build_token_tree("set x = 1; // 日本語\nset y = x;".to_string()).is_ok());
+
+        let err = compile_with_vars("set p = @missing;").unwrap_err();
+        assert_eq!(err.message(), "Use of undeclared variable: missing");
+    }
+
+    #[test]
+    fn test_address_of() {
+        let (program, vars) = compile_with_vars("set x = 5;\nset p = @x;\nset y = #p;\nreturn;").unwrap();
+        let mut machine = Machine::new(&program);
+
+        assert!(machine.run(1000).unwrap());
+
+        let read = |name: &str| machine.read((::compile::setup_size() as i32 + vars[name]) as i64).unwrap();
+
+        assert_eq!(read("p"), vars["x"]);
+        assert_eq!(read("y"), 5);
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("set x = {}1;", "+ 1 ".repeat(depth));
+
+        // Runs on a thread with a small stack, so hitting the limit is what stops deep nesting
+        let result = thread::Builder::new().stack_size(1 << 20).spawn(move || {
+            (compile_with_vars(&nested(MAX_DEPTH - 2)).map(|_| ()), compile_with_vars(&nested(1000)).map(|_| ()))
+        }).unwrap().join().unwrap();
+
+        assert!(result.0.is_ok());
+        assert_eq!(result.1.map_err(|e: BlocksError| e.message()), Err("Expression is nested too deeply at token: Add".to_string()));
+    }
+}
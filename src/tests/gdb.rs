@@ -0,0 +1,99 @@
+#[cfg(test)]
+mod tests {
+    use emulator::Machine;
+    use emulator::gdb::*;
+
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    // regwrite $int1 7; regwrite $int2 5; add; ret
+    const PROGRAM: &'static [i32] = &[10, 0, 7, 10, 1, 5, 16, 0, 1, 35];
+
+    fn send(stub: &mut GdbStub, packet: &str) -> String {
+        stub.handle_packet(packet, &mut || false)
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut stub = GdbStub::new(Machine::new(PROGRAM));
+
+        assert_eq!(send(&mut stub, "?"), "S05");
+        assert_eq!(send(&mut stub, "g").len(), 10 * 8);
+
+        assert_eq!(send(&mut stub, "P0=2a000000"), "OK");
+        assert_eq!(send(&mut stub, "p0"), "2a000000");
+
+        // The program counter is a byte address
+        assert_eq!(send(&mut stub, "P9=0c000000"), "OK");
+        assert_eq!(stub.machine.pc(), 3);
+        assert_eq!(send(&mut stub, "P9=00000000"), "OK");
+
+        assert_eq!(send(&mut stub, "m0,8"), "0a00000000000000");
+        assert_eq!(send(&mut stub, "M40,4:ffffffff"), "OK");
+        assert_eq!(stub.machine.memory[16], -1);
+        assert_eq!(send(&mut stub, "m1,4"), "E01");
+        assert_eq!(send(&mut stub, "mfffffffffffffffc,8"), "E01");
+        assert_eq!(send(&mut stub, "Mfffffffffffffffc,8:0000000000000000"), "E01");
+
+        assert_eq!(send(&mut stub, "vMustReplyEmpty"), "");
+
+        let xml = send(&mut stub, "qXfer:features:read:target.xml:0,fff");
+        assert!(xml.starts_with("l<?xml"));
+        assert!(xml.contains("name=\"int1\"") && xml.contains("name=\"pcounter\""));
+    }
+
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        let mut stub = GdbStub::new(Machine::new(PROGRAM));
+
+        assert_eq!(send(&mut stub, "s"), "S05");
+        assert_eq!(send(&mut stub, "p9"), "0c000000");
+
+        assert_eq!(send(&mut stub, "Z0,18,4"), "OK");
+        assert_eq!(send(&mut stub, "c"), "S05");
+        assert_eq!(stub.machine.pc(), 6);
+
+        assert_eq!(send(&mut stub, "z0,18,4"), "OK");
+        assert_eq!(send(&mut stub, "c"), "W00");
+        assert_eq!(send(&mut stub, "p5"), "0c000000");
+    }
+
+    fn packet(stream: &mut TcpStream, payload: &str) -> String {
+        write_packet(stream, payload).unwrap();
+
+        let mut ack = [0];
+        stream.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+');
+
+        read_packet(stream).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_serve_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut stub = GdbStub::new(Machine::new(PROGRAM));
+
+            serve_connection(&mut stub, &mut stream).unwrap();
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+
+        assert!(packet(&mut client, "qSupported:xmlRegisters=i386").contains("qXfer:features:read+"));
+        assert_eq!(packet(&mut client, "c"), "W00");
+        assert_eq!(packet(&mut client, "p5"), "0c000000");
+
+        // A corrupted packet is rejected
+        client.write_all(b"$g#00").unwrap();
+        let mut nak = [0];
+        client.read_exact(&mut nak).unwrap();
+        assert_eq!(nak[0], b'-');
+
+        assert_eq!(packet(&mut client, "D"), "OK");
+        server.join().unwrap();
+    }
+}
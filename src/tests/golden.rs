@@ -0,0 +1,50 @@
+#[cfg(test)]
+mod tests {
+    use golden::*;
+
+    use std::path::Path;
+
+    #[test]
+    fn test_golden_programs() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tests/golden");
+        let failures = run_dir(&dir, false).unwrap();
+
+        for &(ref path, ref diff) in &failures {
+            println!("{}:\n{}\n", path.display(), diff);
+        }
+
+        assert!(failures.is_empty(), "Run `blocks golden --bless src/tests/golden` to update the expectations");
+    }
+
+    #[test]
+    fn test_bless() {
+        let prog = "// Doubles a number\n// var: y = 3\n// output: \\x00\nset x = 2;\nset y = * x 2;\n";
+
+        let diff = check(prog).unwrap_err();
+        assert!(diff.contains("var y: expected 3, found 4"));
+        assert!(diff.contains("output: expected `\\x00`, found ``"));
+
+        let blessed = bless(prog).unwrap();
+
+        assert_eq!(blessed, "// Doubles a number\n// var: x = 2\n// var: y = 4\nset x = 2;\nset y = * x 2;\n");
+        assert_eq!(check(&blessed), Ok(()));
+        assert_eq!(bless(&blessed).unwrap(), blessed);
+    }
+
+    #[test]
+    fn test_header() {
+        let expected = Expectation {
+            input: b"a\tb\\\n\xff".to_vec(),
+            vars: vec![("a".to_string(), -1)],
+            output: b"  ok".to_vec(),
+            error: None,
+            fault: Some("Division by zero".to_string())
+        };
+
+        let header = render_header(&expected);
+
+        assert_eq!(header.lines().next(), Some("// input: a\\tb\\\\\\n\\xff"));
+        assert_eq!(parse_header(&header), Ok(expected));
+        assert!(parse_header("// var: x\n").is_err());
+    }
+}
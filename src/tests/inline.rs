@@ -0,0 +1,179 @@
+#[cfg(test)]
+mod tests {
+    use compile::{build_module_at, compile_at};
+    use error::ErrorKind;
+    use golden::run_compiled;
+    use ir::*;
+    use opt::OptLevel;
+    use utils::Address;
+
+    fn calls(ir: &[Ir], name: &str) -> usize {
+        ir.iter().filter(|item| **item == Ir::Call(Address::new_var(name))).count()
+    }
+
+    fn inlined(prog: &str) -> (IrResult, usize) {
+        let mut ir = build_module_at(prog, OptLevel::O3).unwrap();
+        let replaced = inline(&mut ir, OptLevel::O3);
+        (ir, replaced)
+    }
+
+    #[test]
+    fn test_inline() {
+        let prog = "
+            symbol double {
+                set n = * n 2;
+                return;
+            }
+            set n = 3;
+            call double;
+            call double;
+        ";
+
+        let (ir, replaced) = inlined(prog);
+
+        assert_eq!(replaced, 2);
+        assert_eq!(calls(&ir.ir, "double"), 0);
+        assert!(!ir.blocks.contains_key("double"));
+
+        let (code, vars) = compile_at(prog, OptLevel::O3).unwrap();
+        assert!(run_compiled(&code, vars, b"").vars.contains(&("n".to_string(), 12)));
+
+        // With one call, the copy replaces the block and the call and return instructions
+        let once = prog.replacen("call double;", "", 1);
+        assert!(compile_at(&once, OptLevel::O3).unwrap().0.len() < compile_at(&once, OptLevel::O2).unwrap().0.len());
+    }
+
+    #[test]
+    fn test_nested() {
+        let prog = "
+            symbol inner {
+                set n = + n 1;
+                return;
+            }
+            symbol outer {
+                call inner;
+                set n = * n 3;
+                return;
+            }
+            set n = 1;
+            call outer;
+        ";
+
+        let (ir, _) = inlined(prog);
+
+        assert_eq!(calls(&ir.ir, "outer") + calls(&ir.ir, "inner"), 0);
+        assert!(ir.blocks.is_empty());
+
+        let (code, vars) = compile_at(prog, OptLevel::O3).unwrap();
+        assert!(run_compiled(&code, vars, b"").vars.contains(&("n".to_string(), 6)));
+    }
+
+    #[test]
+    fn test_hints() {
+        let body = (0..16).map(|i| format!("set x{} = + x{} {};\n", i, i, i)).collect::<String>();
+        let prog = format!("
+            symbol small {{
+                ?inline never;
+                set a = 1;
+                return;
+            }}
+            symbol large {{
+                ?inline always;
+                {}
+                return;
+            }}
+            symbol large2 {{
+                {}
+                return;
+            }}
+            call small;
+            call large;
+            call large2;
+        ", body, body);
+
+        let (ir, replaced) = inlined(&prog);
+
+        assert_eq!(replaced, 1);
+        assert_eq!(calls(&ir.ir, "small"), 1);
+        assert_eq!(calls(&ir.ir, "large"), 0);
+        assert_eq!(calls(&ir.ir, "large2"), 1);
+        assert!(!ir.blocks.contains_key("large"));
+
+        let err = compile_at("symbol f {\n?inline sometimes;\nreturn;\n}\ncall f;", OptLevel::O1).unwrap_err();
+        assert_eq!(err.code(), ErrorKind::TagError as usize);
+    }
+
+    #[test]
+    fn test_not_inlined() {
+        let prog = "
+            symbol recursive {
+                call recursive;
+                return;
+            }
+            symbol branches {
+                cmp == n 0;
+                ifgoto recursive;
+                return;
+            }
+            symbol jumped {
+                set n = 1;
+                return;
+            }
+            symbol slow {
+                ?opt 2;
+                set n = 2;
+                return;
+            }
+            call recursive;
+            call branches;
+            call jumped;
+            call slow;
+            cmp == n 1;
+            ifgoto jumped;
+        ";
+
+        let (ir, replaced) = inlined(prog);
+
+        // Only `jumped` is inlined, and it is kept for the branch
+        assert_eq!(replaced, 1);
+        assert_eq!(calls(&ir.ir, "jumped"), 0);
+        assert!(ir.blocks.contains_key("jumped"));
+
+        for name in &["recursive", "branches", "slow"] {
+            assert_eq!(calls(&ir.ir, name), 1);
+        }
+
+        // Nothing is inlined below -O3
+        let mut ir = build_module_at(prog, OptLevel::O2).unwrap();
+        assert_eq!(inline(&mut ir, OptLevel::O2), 0);
+    }
+
+    #[test]
+    fn test_run_into() {
+        // `a` has no return, so it runs into `b`, which is kept after its call is inlined
+        let prog = "
+            symbol a {
+                set x = 1;
+            }
+            symbol b {
+                set y = + y 2;
+                return;
+            }
+            set y = 0;
+            call a;
+            call b;
+        ";
+
+        let (ir, replaced) = inlined(prog);
+
+        assert_eq!(replaced, 1);
+        assert!(ir.blocks.contains_key("b"));
+
+        for &level in &[OptLevel::O0, OptLevel::O3] {
+            let (code, vars) = compile_at(prog, level).unwrap();
+            let outcome = run_compiled(&code, vars, b"");
+            assert_eq!(outcome.fault, None);
+            assert!(outcome.vars.contains(&("y".to_string(), 4)));
+        }
+    }
+}
@@ -1,9 +1,12 @@
 #[cfg(test)]
 mod tests {
+    use compile::compile_ir;
     use ir::*;
     use utils::*;
     use tree::build_token_tree;
 
+    use std::collections::{BTreeMap, HashMap};
+
     #[test]
     fn test_write() {
         let prog = "
@@ -12,9 +15,7 @@ mod tests {
         
         let tree = build_token_tree(prog.to_string()).unwrap();
 
-        let mut ir = build_ir(TokenWrapper::Tree(tree), 0).unwrap().ir;
-
-        remove_dead_temp(&mut ir);
+        let ir = build_ir(TokenWrapper::Tree(tree), 0).unwrap().ir;
 
         let expected = [
             Ir::Write(Address::Variable("__temp_0__".to_string()), Address::Static(0)),
@@ -24,4 +25,33 @@ mod tests {
 
         assert_eq!(&expected, &ir as &[_]);
     }
+
+    #[test]
+    fn test_block_reads_temp() {
+        let mut blocks = BTreeMap::new();
+        blocks.insert("get".to_string(), vec![
+            Ir::Copy(Address::new_var("r"), Address::new_temp(1)),
+            Ir::Return,
+        ]);
+
+        let ir = IrResult {
+            ir: vec![
+                Ir::Write(Address::new_temp(1), Address::Static(5)),
+                Ir::Call(Address::new_var("get")),
+            ],
+            blocks: blocks,
+            address: Address::Static(-1),
+            var_addr: Address::Static(-1),
+            register: None,
+            deref: false,
+            math: false
+        };
+
+        let mut vars = HashMap::new();
+        let (mut var_addr, mut symbol_addr) = (100, 0);
+
+        // The block is compiled first, so it declares the temp that main writes
+        assert!(compile_ir(ir, &mut vars, &mut var_addr, &mut symbol_addr).is_ok());
+        assert!(vars.contains_key("__temp_1__"));
+    }
 }
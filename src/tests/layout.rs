@@ -0,0 +1,81 @@
+#[cfg(test)]
+mod tests {
+    use layout::*;
+    use compile::{add_segments, compile_with_layout, compile_with_vars};
+    use emulator::Machine;
+
+    const PROG: &'static str = "symbol double {\n    set n = * n 2;\n    return;\n}\nset n = 21;\ncall double;\n";
+
+    const BOARD: &'static str = "// Code first, then data above the devices
+section setup
+section text at 0x40 align 16
+section main align 8   // after text
+section data at 300
+reserve 200 20
+";
+
+    #[test]
+    fn test_default_layout() {
+        let (text, main) = ([29, 0, 35], [33, 0, 1, 0, 7]);
+        let image = Layout::default().build(&text, &main, 4).unwrap();
+
+        assert_eq!(image.code, add_segments(text.iter().chain(main.iter()).cloned().collect(), 4, text.len()));
+        assert_eq!(compile_with_layout(PROG, &Layout::default()).unwrap().0.code, compile_with_vars(PROG).unwrap().0);
+    }
+
+    #[test]
+    fn test_custom_layout() {
+        let layout = Layout::parse(BOARD).unwrap();
+        let (image, vars) = compile_with_layout(PROG, &layout).unwrap();
+
+        assert_eq!(image.section(SectionKind::Text).start, 64);
+        assert_eq!(image.section(SectionKind::Main).start % 8, 0);
+        assert_eq!(image.data_base(), 300);
+        assert_eq!(image.code.len(), image.section(SectionKind::Data).end());
+
+        let mut machine = Machine::new(&image.code);
+        assert_eq!(machine.run(1000), Ok(true));
+        assert_eq!(machine.read((image.data_base() as i32 + vars["n"]) as i64), Ok(42));
+    }
+
+    #[test]
+    fn test_invalid_layouts() {
+        let errors = [
+            ("section setup\nsection data\nsection text\n", "Section `main` is missing"),
+            ("section setup\nsection setup\n", "Line 2: section `setup` is listed twice"),
+            ("section data\nsection setup\nsection text\nsection main\n", "The setup section must be listed first"),
+            ("section setup at 4\nsection data\nsection text\nsection main\n", "The setup section must be listed first"),
+            ("section setup\nsection code\n", "Line 2: unknown section `code`"),
+            ("section setup align 0\n", "Line 1: alignment must be at least 1"),
+            ("reserve 10\n", "Line 1: expected a number"),
+            ("origin 10\n", "Line 1: unknown directive `origin`"),
+            ("section setup at x\n", "Line 1: invalid number `x`")
+        ];
+
+        for &(text, message) in &errors {
+            let err = Layout::parse(text).unwrap_err();
+            assert!(err.starts_with(message), "{:?} gave {:?}", text, err);
+        }
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let place = |text: &str| Layout::parse(text).unwrap().place([23, 10, 10, 10]).map_err(|e| e.message());
+
+        assert_eq!(place("section setup\nsection data at 20\nsection text\nsection main"),
+                   Err("Invalid memory layout: sections `setup` (0..23) and `data` (20..30) overlap".to_string()));
+        assert_eq!(place("section setup\nsection data\nsection text\nsection main\nreserve 50 1"),
+                   Err("Invalid memory layout: section `main` (43..53) overlaps the reserved region 50..51".to_string()));
+        assert_eq!(place("section setup\nsection data at 30 align 4\nsection text\nsection main"),
+                   Err("Invalid memory layout: section `data` at 30 is not aligned to 4".to_string()));
+        assert_eq!(place("section setup\nsection data at 2147483640\nsection text\nsection main"),
+                   Err("Invalid memory layout: section `data` does not fit in memory (65536 words)".to_string()));
+        assert!(place("section setup\nsection data\nsection text\nsection main at 65526").is_ok());
+
+        // Empty sections never overlap anything
+        let placements = Layout::parse("section setup\nsection data at 30\nsection text\nsection main at 100\n")
+                                .unwrap().place([23, 0, 10, 10]).unwrap();
+        assert_eq!(placements[2], Placement { kind: SectionKind::Text, start: 30, size: 10 });
+        assert!(Layout::default().place([23, 0, 0, 7]).is_ok());
+    }
+}
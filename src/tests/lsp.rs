@@ -0,0 +1,96 @@
+#[cfg(test)]
+mod tests {
+    use lsp::*;
+    use json::Json;
+
+    const URI: &'static str = "file:///test.blk";
+
+    fn request(server: &mut Server, method: &str, params: &str) -> Json {
+        let message = Json::parse(&format!(r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{}}}"#,
+                                           method, params)).unwrap();
+
+        server.handle(&message).remove(0)
+    }
+
+    fn open(server: &mut Server, text: &str) -> Json {
+        let params = Json::object(vec![
+            ("textDocument", Json::object(vec![
+                ("uri", Json::string(URI)),
+                ("text", Json::string(text))
+            ]))
+        ]);
+
+        let message = Json::object(vec![
+            ("jsonrpc", Json::string("2.0")),
+            ("method", Json::string("textDocument/didOpen")),
+            ("params", params)
+        ]);
+
+        server.handle(&message).remove(0)
+    }
+
+    fn position_params(line: i64, character: i64) -> String {
+        format!(r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}"#,
+                URI, line, character)
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut server = Server::new();
+
+        let published = open(&mut server, "set foo = 1;\nset bar = baz;\n");
+        let diagnostics = published.get("params").get("diagnostics").as_array().unwrap();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].get("code"), &Json::Number(5));
+        assert_eq!(diagnostics[0].get("range").get("start").get("line"), &Json::Number(1));
+        assert_eq!(diagnostics[0].get("range").get("start").get("character"), &Json::Number(10));
+
+        let published = open(&mut server, "set foo = 1;\n");
+        assert_eq!(published.get("params").get("diagnostics"), &Json::Array(Vec::new()));
+    }
+
+    #[test]
+    fn test_definition_and_hover() {
+        let mut server = Server::new();
+        open(&mut server, "symbol foo {\n    return;\n}\nset bar = 1;\ncall foo;\nset bar = 2;\n");
+
+        let response = request(&mut server, "textDocument/definition", &position_params(4, 6));
+        assert_eq!(response.get("result").get("range").get("start").get("line"), &Json::Number(0));
+        assert_eq!(response.get("result").get("range").get("start").get("character"), &Json::Number(7));
+
+        let response = request(&mut server, "textDocument/definition", &position_params(5, 5));
+        assert_eq!(response.get("result").get("range").get("start").get("line"), &Json::Number(3));
+
+        let response = request(&mut server, "textDocument/hover", &position_params(5, 5));
+        let text = response.get("result").get("contents").get("value").as_str().unwrap();
+        assert!(text.starts_with("variable `bar` at data address"));
+    }
+
+    #[test]
+    fn test_completion() {
+        let mut server = Server::new();
+        open(&mut server, "set counter = 1;\n");
+
+        let response = request(&mut server, "textDocument/completion", &position_params(0, 0));
+        let labels = response.get("result").as_array().unwrap().iter()
+                             .map(|i| i.get("label").as_str().unwrap().to_string())
+                             .collect::<Vec<_>>();
+
+        for label in &["symbol", "$accum", "$int1", "var_addr", "counter"] {
+            assert!(labels.contains(&label.to_string()), "missing {}", label);
+        }
+    }
+
+    #[test]
+    fn test_framing() {
+        let input = "Content-Length: 52\r\n\r\n{\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"shutdown\"}        ";
+        let message = read_message(&mut input.as_bytes()).unwrap().unwrap();
+
+        let mut output = Vec::new();
+        write_message(&mut output, &Server::new().handle(&message)[0]).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "Content-Length: 38\r\n\r\n{\"jsonrpc\":\"2.0\",\"id\":7,\"result\":null}");
+    }
+}
@@ -1 +1,26 @@
 mod ir;
+mod error;
+mod lsp;
+mod formatter;
+mod token;
+mod doc;
+mod emulator;
+mod debugger;
+mod gdb;
+mod profile;
+mod devices;
+mod golden;
+mod fuzz;
+mod differential;
+mod cache;
+mod object;
+mod archive;
+mod layout;
+mod regalloc;
+mod ssa;
+mod cfg;
+mod peephole;
+mod opt;
+mod inline;
+mod dead_blocks;
+mod reproducible;
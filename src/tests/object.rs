@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod tests {
+    use object::*;
+    use golden::{run_compiled, run_program};
+    use json::Json;
+
+    const LIBRARY: &'static str = "symbol inc {\n    set n = + n 1;\n    return;\n}\n\
+                                   symbol double {\n    set n = * n 2;\n    return;\n}\n";
+    const MAIN: &'static str = "set n = 4;\ncall inc;\ncall double;\nset result = n;\n";
+    const OTHER: &'static str = "set n = 10;\ncall double;\ncall double;\nset other = n;\n";
+
+    #[test]
+    fn test_link_matches_compile() {
+        let library = compile_object(LIBRARY).unwrap();
+
+        // The same library object is linked into several programs
+        for main in &[MAIN, OTHER] {
+            let (program, vars) = link_objects(&[library.clone(), compile_object(main).unwrap()]).unwrap();
+            let expected = run_program(&format!("{}{}", LIBRARY, main), b"");
+
+            assert_eq!(run_compiled(&program, vars, b""), expected);
+            assert_eq!(expected.fault, None);
+        }
+
+        let (program, vars) = link_objects(&[compile_object(MAIN).unwrap(), library]).unwrap();
+        let actual = run_compiled(&program, vars, b"");
+
+        assert!(actual.vars.contains(&("result".to_string(), 10)));
+    }
+
+    #[test]
+    fn test_symbol_tables() {
+        let library = compile_object(LIBRARY).unwrap();
+        let main = compile_object(MAIN).unwrap();
+
+        assert_eq!(library.export("inc").map(|e| e.section), Some(Section::Text));
+        assert_eq!(library.export("n").map(|e| e.section), Some(Section::Data));
+        assert!(library.imports.is_empty());
+        assert!(library.main.is_empty());
+
+        assert_eq!(main.imports, vec!["double".to_string(), "inc".to_string()]);
+        assert!(main.relocations.iter().all(|r| main.main[r.offset] == 0));
+    }
+
+    #[test]
+    fn test_serialization() {
+        let object = compile_object(LIBRARY).unwrap();
+        let json = Json::parse(&object.to_json().to_string()).unwrap();
+
+        assert_eq!(Object::from_json(&json), Ok(object));
+
+        let text = json.to_string();
+
+        for (from, to) in &[("\"version\":1", "\"version\":0"), ("\"offset\":2,", "\"offset\":200,"),
+                            ("\"section\":\"text\"", "\"section\":\"code\"")] {
+            assert!(text.contains(from));
+            assert!(Object::from_json(&Json::parse(&text.replacen(from, to, 1)).unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let library = compile_object(LIBRARY).unwrap();
+        let main = compile_object(MAIN).unwrap();
+
+        assert_eq!(link_objects(&[main.clone()]).unwrap_err().code(), 5);
+        assert_eq!(link_objects(&[library.clone(), library.clone(), main]).unwrap_err().code(), 16);
+        assert_eq!(link_objects(&[library, compile_object("set inc = 1;").unwrap()]).unwrap_err().code(), 16);
+        assert_eq!(compile_object("?var_addr 10;\nset x = 1;").unwrap_err().code(), 13);
+    }
+}
@@ -0,0 +1,173 @@
+#[cfg(test)]
+mod tests {
+    use cache::{Cache, compile_project_at};
+    use compile::{build_module_at, compile_at};
+    use error::ErrorKind;
+    use golden::run_compiled;
+    use ir::*;
+    use layout::Layout;
+    use opt::*;
+    use utils::{Address, Register};
+
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs;
+
+    const LEVELS: &'static [OptLevel] = &[OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::O3, OptLevel::Os];
+
+    fn has_temps(ir: &[Ir]) -> bool {
+        ir.iter().any(|item| match *item {
+            Ir::Write(ref a, _) | Ir::Copy(ref a, _) | Ir::RegMem(_, ref a) => a.is_temp(),
+            _ => false
+        })
+    }
+
+    #[test]
+    fn test_levels() {
+        for level in LEVELS {
+            assert_eq!(OptLevel::parse(level.name()), Some(*level));
+            assert_eq!(OptLevel::from_flag(&format!("-O{}", level.name())), Some(*level));
+        }
+
+        assert_eq!(OptLevel::parse("4"), None);
+        assert_eq!(OptLevel::from_flag("-o2"), None);
+        assert_eq!(OptLevel::default(), OptLevel::O1);
+
+        assert!(!OptLevel::O0.passes().registers && !OptLevel::O0.passes().peephole);
+        assert!(OptLevel::O1.passes().registers && !OptLevel::O1.passes().ssa);
+        assert!(OptLevel::O2.passes().ssa);
+
+        let os = OptLevel::Os.passes();
+        assert!(os.registers && os.peephole && os.dead_blocks && !os.inline && !os.ssa);
+    }
+
+    #[test]
+    fn test_levels_agree() {
+        let prog = "
+            symbol double {
+                set n = * n 2;
+                return;
+            }
+            set n = + 3 4;
+            call double;
+            set m = ~ * n 3 / n 2;
+        ";
+
+        let mut sizes = Vec::new();
+
+        for &level in LEVELS {
+            let (code, vars) = compile_at(prog, level).unwrap();
+            let result = run_compiled(&code, vars, b"");
+
+            assert!(result.vars.contains(&("n".to_string(), 14)));
+            assert!(result.vars.contains(&("m".to_string(), 35)));
+            sizes.push(code.len());
+        }
+
+        // Temps are kept in memory without optimizations, and -Os only adds passes that shrink code
+        assert!(sizes[0] > sizes[1]);
+        assert!(sizes[4] <= sizes[1]);
+    }
+
+    #[test]
+    fn test_opt_tag() {
+        let prog = "
+            ?opt 0;
+            symbol f {
+                ?opt 2;
+                set t = + 1 2;
+                return;
+            }
+            symbol g {
+                set u = + 3 4;
+                return;
+            }
+            set a = + 3 4;
+            call f;
+        ";
+
+        let mut ir = build_module_at(prog, OptLevel::O1).unwrap();
+        let tag = |level: &str| Ir::Tag("opt".to_string(), level.to_string());
+
+        // The file's level applies to the main code and g, and code at other levels is marked, so
+        // registers are allocated at each code's level when linking
+        allocate_registers_at(&mut ir, OptLevel::O1);
+        assert!(has_temps(&ir.ir));
+        assert!(has_temps(&ir.blocks["g"]));
+        assert!(!has_temps(&ir.blocks["f"]));
+        assert_eq!(ir.ir.first(), Some(&tag("0")));
+        assert_eq!(ir.ir.last(), Some(&tag("1")));
+        assert_eq!(ir.blocks["f"].first(), Some(&tag("2")));
+        assert_eq!(ir.blocks["g"].first(), Some(&tag("0")));
+
+        // The tags only mark levels other than the one asked for
+        let ir = build_module_at(prog, OptLevel::O0).unwrap();
+        assert!(ir.ir.iter().all(|item| *item != tag("0")));
+        assert_eq!(ir.blocks["f"].first(), Some(&tag("2")));
+
+        let (code, vars) = compile_at(prog, OptLevel::O2).unwrap();
+        assert!(run_compiled(&code, vars, b"").vars.contains(&("t".to_string(), 3)));
+    }
+
+    #[test]
+    fn test_invalid_opt_tag() {
+        let err = compile_at("?opt 7;\nset a = 1;", OptLevel::O1).unwrap_err();
+        assert_eq!(err.code(), ErrorKind::TagError as usize);
+
+        let err = compile_at("symbol f {\n?opt fast;\n}\nset a = 1;", OptLevel::O1).unwrap_err();
+        assert_eq!(err.code(), ErrorKind::TagError as usize);
+    }
+
+    #[test]
+    fn test_volatile() {
+        let program = |volatile: bool| {
+            let mut ir = vec![
+                Ir::RegCopy(Register::Int1, Address::new_var("x")),
+                Ir::RegMem(Register::Int1, Address::new_var("x")),
+            ];
+
+            if volatile {
+                ir.insert(0, Ir::Tag("volatile".to_string(), "x".to_string()));
+            }
+
+            IrResult {
+                ir: ir,
+                blocks: BTreeMap::new(),
+                address: Address::Static(-1),
+                var_addr: Address::Static(-1),
+                register: None,
+                deref: false,
+                math: false
+            }
+        };
+
+        assert_eq!(peephole(&mut program(false), OptLevel::O1), 1);
+        assert_eq!(peephole(&mut program(true), OptLevel::O1), 0);
+        assert_eq!(peephole(&mut program(false), OptLevel::O0), 0);
+
+        for &level in LEVELS {
+            let (code, vars) = compile_at("?volatile x;\nset x = 5;\nset y = + x x;", level).unwrap();
+            assert!(run_compiled(&code, vars, b"").vars.contains(&("y".to_string(), 10)));
+        }
+    }
+
+    #[test]
+    fn test_project_levels() {
+        let dir = env::temp_dir().join(format!("blocks-opt-test-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let sources = ["set a = + 1 2;", "set b = * a 2;"];
+        let mut cache = Cache::new(&dir);
+
+        let slow = compile_project_at(&sources, Some(&mut cache), &Layout::default(), OptLevel::O0).unwrap();
+        let fast = compile_project_at(&sources, Some(&mut cache), &Layout::default(), OptLevel::O2).unwrap();
+
+        // Modules built at different levels are cached separately
+        assert_eq!(cache.misses, 4);
+        assert!(fast.0.len() < slow.0.len());
+        assert_eq!(compile_project_at(&sources, Some(&mut cache), &Layout::default(), OptLevel::O0).unwrap(), slow);
+        assert_eq!(cache.hits, 2);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
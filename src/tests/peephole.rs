@@ -0,0 +1,164 @@
+#[cfg(test)]
+mod tests {
+    use compile::{build_module, compile_with_vars, link};
+    use compile_utils::get_code_size;
+    use golden::{run_compiled, run_program};
+    use ir::*;
+    use opt::OptLevel;
+    use utils::{Address, Register};
+
+    use std::collections::BTreeMap;
+
+    fn var(name: &str) -> Address {
+        Address::new_var(name)
+    }
+
+    fn rule(name: &str) -> &'static Rule {
+        RULES.iter().find(|r| r.name == name).unwrap()
+    }
+
+    // Applies a single rule to a window
+    fn apply(name: &str, ir: &[Ir], next: &[&str]) -> Option<Vec<Ir>> {
+        let context = Context {
+            next: next.iter().map(|s| s.to_string()).collect(),
+            ..Context::default()
+        };
+
+        (rule(name).apply)(ir, &context)
+    }
+
+    fn program(main: Vec<Ir>, blocks: Vec<(&str, Vec<Ir>)>) -> IrResult {
+        IrResult {
+            ir: main,
+            blocks: blocks.into_iter().map(|(k, v)| (k.to_string(), v)).collect::<BTreeMap<_, _>>(),
+            address: Address::Static(-1),
+            var_addr: Address::Static(-1),
+            register: None,
+            deref: false,
+            math: false
+        }
+    }
+
+    #[test]
+    fn test_store_after_load() {
+        let load = Ir::RegCopy(Register::Int1, var("x"));
+
+        assert_eq!(apply("store-after-load", &[load.clone(), Ir::RegMem(Register::Int1, var("x"))], &[]),
+                   Some(vec![load.clone()]));
+        assert_eq!(apply("store-after-load", &[load.clone(), Ir::RegMem(Register::Int1, var("y"))], &[]), None);
+        assert_eq!(apply("store-after-load", &[load, Ir::RegMem(Register::Int2, var("x"))], &[]), None);
+
+        // Fixed addresses may be devices, where reading and writing are not the same
+        let device = [Ir::RegCopy(Register::Int1, Address::Static(9)), Ir::RegMem(Register::Int1, Address::Static(9))];
+        assert_eq!(apply("store-after-load", &device, &[]), None);
+
+        // Loading the data segment register changes what x refers to
+        let segment = [Ir::RegCopy(Register::DataSegment, var("x")), Ir::RegMem(Register::DataSegment, var("x"))];
+        assert_eq!(apply("store-after-load", &segment, &[]), None);
+    }
+
+    #[test]
+    fn test_load_after_store() {
+        let store = Ir::RegMem(Register::Accum, var("x"));
+
+        assert_eq!(apply("load-after-store", &[store.clone(), Ir::RegCopy(Register::Accum, var("x"))], &[]),
+                   Some(vec![store.clone()]));
+        assert_eq!(apply("load-after-store", &[store, Ir::RegCopy(Register::Int1, var("x"))], &[]), None);
+    }
+
+    #[test]
+    fn test_overwritten_register() {
+        let second = Ir::RegWrite(Register::Int3, Address::Static(2));
+
+        assert_eq!(apply("overwritten-register", &[Ir::RegWrite(Register::Int3, Address::Static(1)), second.clone()], &[]),
+                   Some(vec![second.clone()]));
+        assert_eq!(apply("overwritten-register", &[Ir::RegReg(Register::Int3, Register::Accum), second.clone()], &[]),
+                   Some(vec![second]));
+
+        // The second write reads the first value
+        let reads = [Ir::RegWrite(Register::Int3, Address::Static(1)), Ir::RegReg(Register::Int3, Register::Int3)];
+        assert_eq!(apply("overwritten-register", &reads, &[]), None);
+
+        // Loads declare the variable they read
+        let load = [Ir::RegCopy(Register::Int3, var("x")), Ir::RegWrite(Register::Int3, Address::Static(1))];
+        assert_eq!(apply("overwritten-register", &load, &[]), None);
+
+        // Writing the program counter jumps
+        let jump = [Ir::RegWrite(Register::PCounter, Address::Static(1)), Ir::RegWrite(Register::PCounter, Address::Static(2))];
+        assert_eq!(apply("overwritten-register", &jump, &[]), None);
+    }
+
+    #[test]
+    fn test_self_move() {
+        assert_eq!(apply("self-move", &[Ir::RegReg(Register::Int2, Register::Int2)], &[]), Some(vec![]));
+        assert_eq!(apply("self-move", &[Ir::RegReg(Register::Int2, Register::Int1)], &[]), None);
+    }
+
+    #[test]
+    fn test_branch_to_next() {
+        assert_eq!(apply("branch-to-next", &[Ir::Branch(var("b"))], &["b"]), Some(vec![]));
+        assert_eq!(apply("branch-to-next", &[Ir::CondBranch(var("b"))], &["a", "b"]), Some(vec![]));
+        assert_eq!(apply("branch-to-next", &[Ir::Branch(var("b"))], &[]), None);
+        assert_eq!(apply("branch-to-next", &[Ir::Call(var("b"))], &["b"]), None);
+    }
+
+    #[test]
+    fn test_optimize_code() {
+        // Removing the self move makes the load follow the store
+        let mut ir = vec![
+            Ir::RegMem(Register::Int1, var("x")),
+            Ir::RegReg(Register::Int2, Register::Int2),
+            Ir::RegCopy(Register::Int1, var("x")),
+            Ir::Branch(var("next")),
+        ];
+
+        assert_eq!(optimize_code(&mut ir, &Context { next: vec!["next".to_string()], ..Context::default() }, RULES), 3);
+        assert_eq!(ir, vec![Ir::RegMem(Register::Int1, var("x"))]);
+    }
+
+    // Calls a, which branches to b, which branches to c, passing a value along in a register
+    fn chain() -> IrResult {
+        program(vec![Ir::Call(var("a"))], vec![
+            ("a", vec![Ir::RegWrite(Register::Int3, Address::Static(7)), Ir::Branch(var("b"))]),
+            ("b", vec![Ir::RegMem(Register::Int3, var("y")), Ir::Branch(var("c"))]),
+            ("c", vec![Ir::Return]),
+        ])
+    }
+
+    #[test]
+    fn test_branch_fixups() {
+        // Only the branches to the block placed right after are removed, and the other branches must
+        // still reach their targets after the blocks move
+        let mut ir = chain();
+        let order = ir.blocks.keys().cloned().collect::<Vec<_>>();
+        let expected = order.windows(2).filter(|w| (w[0] == "a" && w[1] == "b") || (w[0] == "b" && w[1] == "c")).count();
+
+        assert_eq!(peephole(&mut ir, OptLevel::default()), expected);
+
+        let (code, vars) = link(ir).unwrap();
+        assert!(run_compiled(&code, vars, b"").vars.contains(&("y".to_string(), 7)));
+    }
+
+    #[test]
+    fn test_programs_shrink() {
+        let prog = "
+            set x = + 1 2;
+            set y = x;
+            set $int1 = 4;
+            set $int1 = 5;
+        ";
+
+        let mut ir = build_module(prog).unwrap();
+        allocate_registers(&mut ir);
+        let before = get_code_size(&ir.ir);
+
+        assert_eq!(peephole(&mut ir, OptLevel::default()), 1);
+        assert_eq!(get_code_size(&ir.ir), before - 3);
+
+        let (code, vars) = compile_with_vars(prog).unwrap();
+        let result = run_compiled(&code, vars, b"");
+
+        assert_eq!(result.vars, run_program(prog, b"").vars);
+        assert!(result.vars.contains(&("y".to_string(), 3)));
+    }
+}
@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod tests {
+    use emulator::Machine;
+    use emulator::trace::*;
+    use debug_info::{DebugInfo, compile_with_debug_info};
+    use profile::*;
+
+    fn trace(prog: &str) -> (Vec<TraceRecord>, DebugInfo) {
+        let (program, info) = compile_with_debug_info(prog).unwrap();
+        let mut machine = Machine::new(&program);
+        let mut records = Vec::new();
+
+        while let Some(record) = step_traced(&mut machine).unwrap() {
+            records.push(record);
+        }
+
+        assert_eq!(records.len() as u64, machine.steps);
+
+        (records, info)
+    }
+
+    #[test]
+    fn test_trace_round_trip() {
+        let (records, _) = trace(include_str!("programs/arithmetic.blk"));
+
+        let mut writer = TraceWriter::new(Vec::new()).unwrap();
+
+        for r in &records {
+            writer.write(r).unwrap();
+        }
+
+        let bytes = writer.finish().unwrap();
+        let read = TraceReader::new(&bytes[..]).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(read, records);
+
+        // regwrite $int1 23 only changes $int1
+        assert_eq!(records[1].opcode, 10);
+        assert_eq!(records[1].operands, vec![0, 23]);
+        assert_eq!(records[1].deltas, vec![(0, 23)]);
+
+        assert!(TraceReader::new(&b"trace"[..]).is_err());
+        assert!(TraceReader::new(&bytes[..bytes.len() - 1]).unwrap().any(|r| r.is_err()));
+    }
+
+    #[test]
+    fn test_profile() {
+        let (records, info) = trace(include_str!("programs/emulator_symbols.blk"));
+        let total = records.len() as u64;
+        let profile = build_profile(records, &info);
+
+        assert_eq!(profile.total, total);
+        assert_eq!(profile.symbols.iter().map(|s| s.1).sum::<u64>(), total);
+        assert!(profile.symbols.contains(&("double".to_string(), 7)));
+        assert!(profile.lines.contains(&(3, 6)));
+
+        let folded = render_folded(&profile);
+
+        assert!(folded.lines().any(|l| l == "main;double 7"));
+        assert!(folded.lines().any(|l| l == "main;skip 1"));
+    }
+}
@@ -0,0 +1,137 @@
+#[cfg(test)]
+mod tests {
+    use compile::{build_module, compile_with_vars};
+    use golden::run_program;
+    use ir::*;
+    use utils::*;
+
+    use std::collections::{BTreeMap, HashSet};
+
+    fn temp(n: usize) -> Address {
+        Address::Variable(format!("__temp_{}__", n))
+    }
+
+    fn var(name: &str) -> Address {
+        Address::Variable(name.to_string())
+    }
+
+    fn temps_in(ir: &IrResult) -> usize {
+        let mut found = HashSet::new();
+
+        for item in ir.ir.iter().chain(ir.blocks.values().flat_map(|b| b.iter())) {
+            let addresses = match *item {
+                Ir::Write(ref a, _) | Ir::Copy(ref a, _) | Ir::RegMem(_, ref a) => vec![a],
+                _ => vec![]
+            };
+
+            for a in addresses {
+                if let Address::Variable(ref name) = *a {
+                    if name.starts_with("__temp_") {
+                        found.insert(name.clone());
+                    }
+                }
+            }
+        }
+
+        found.len()
+    }
+
+    #[test]
+    fn test_loaded_temp_shares_register() {
+        let mut ir = vec![
+            Ir::Write(temp(0), Address::Static(5)),
+            Ir::RegCopy(Register::Int1, temp(0)),
+            Ir::Write(temp(1), Address::Static(6)),
+            Ir::RegCopy(Register::Int2, temp(1)),
+            Ir::Add,
+            Ir::RegMem(Register::Accum, var("x")),
+        ];
+
+        let moved = allocate_function(&mut ir, &HashSet::new(), &[Register::Int3, Register::Int1, Register::Int2]);
+
+        let expected = [
+            Ir::RegWrite(Register::Int1, Address::Static(5)),
+            Ir::RegWrite(Register::Int2, Address::Static(6)),
+            Ir::Add,
+            Ir::RegMem(Register::Accum, var("x")),
+        ];
+
+        assert_eq!(moved, 2);
+        assert_eq!(&expected, &ir as &[_]);
+    }
+
+    #[test]
+    fn test_spill_when_registers_are_busy() {
+        // Int1 holds a value across the whole range of the temp, so it must stay in memory
+        let original = vec![
+            Ir::RegCopy(Register::Int1, var("a")),
+            Ir::Write(temp(0), Address::Static(1)),
+            Ir::RegCopy(Register::Int2, temp(0)),
+            Ir::Add,
+        ];
+
+        let mut ir = original.clone();
+        assert_eq!(allocate_function(&mut ir, &HashSet::new(), &[Register::Int1]), 0);
+        assert_eq!(ir, original);
+
+        // Temps whose value crosses a branch are pinned to memory
+        let original = vec![
+            Ir::Write(temp(0), Address::Static(1)),
+            Ir::Branch(var("f")),
+            Ir::RegCopy(Register::Int1, temp(0)),
+        ];
+
+        let mut result = IrResult {
+            ir: original.clone(),
+            blocks: BTreeMap::new(),
+            address: Address::Static(-1),
+            var_addr: Address::Static(-1),
+            register: None,
+            deref: false,
+            math: false
+        };
+
+        allocate_registers(&mut result);
+        assert_eq!(result.ir, original);
+    }
+
+    #[test]
+    fn test_data_section_shrinks() {
+        let prog = "
+            set a = 3;
+            set b = 4;
+            set c = + * a b ~ a b;
+            set d = / c 2;
+        ";
+
+        let mut ir = build_module(prog).unwrap();
+        allocate_registers(&mut ir);
+        let (_, vars) = compile_with_vars(prog).unwrap();
+
+        assert_eq!(temps_in(&ir), 0);
+        assert_eq!(vars.len(), 4);
+
+        let result = run_program(prog, b"");
+        assert!(result.vars.contains(&("c".to_string(), 11)));
+        assert!(result.vars.contains(&("d".to_string(), 5)));
+    }
+
+    #[test]
+    fn test_registers_used_by_program() {
+        let prog = "
+            set $int3 = 7;
+            set a = + $int3 1;
+        ";
+
+        let mut ir = build_module(prog).unwrap();
+        allocate_registers(&mut ir);
+
+        for item in &ir.ir {
+            if let Ir::RegWrite(Register::Int3, ref data) = *item {
+                assert_eq!(*data, Address::Static(7));
+            }
+        }
+
+        assert!(run_program(prog, b"").vars.contains(&("a".to_string(), 8)));
+    }
+}
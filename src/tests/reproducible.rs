@@ -0,0 +1,76 @@
+#[cfg(test)]
+mod tests {
+    use cache::compile_project_at;
+    use compile::compile_at;
+    use debug_info::compile_with_debug_info;
+    use layout::Layout;
+    use object::compile_object;
+    use opt::OptLevel;
+
+    const LEVELS: &'static [OptLevel] = &[OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::O3, OptLevel::Os];
+
+    const PROGRAM: &'static str = "
+        symbol zeta {
+            set z = + z 1;
+            return;
+        }
+        symbol alpha {
+            call zeta;
+            set a = * z 2;
+            return;
+        }
+        symbol middle {
+            ?inline never;
+            set m = ~ a z;
+            cmp > m 0;
+            ifgoto zeta;
+            return;
+        }
+        symbol nested {
+            set n = 1;
+            return;
+        }
+        symbol beta {
+            call nested;
+            return;
+        }
+        set p = @beta;
+        call alpha;
+        call middle;
+        call beta;
+    ";
+
+    #[test]
+    fn test_same_output() {
+        for &level in LEVELS {
+            let first = compile_at(PROGRAM, level).unwrap();
+            let second = compile_at(PROGRAM, level).unwrap();
+
+            assert_eq!(first.0, second.0, "code differs at -O{}", level.name());
+            assert_eq!(first.1, second.1, "variables differ at -O{}", level.name());
+        }
+
+        let sources = [PROGRAM, "symbol other {\nset o = 1;\nreturn;\n}\ncall other;"];
+        let first = compile_project_at(&sources, None, &Layout::default(), OptLevel::O2).unwrap();
+        assert_eq!(first, compile_project_at(&sources, None, &Layout::default(), OptLevel::O2).unwrap());
+
+        let first = compile_object(PROGRAM).unwrap().to_json().to_string();
+        assert_eq!(first, compile_object(PROGRAM).unwrap().to_json().to_string());
+    }
+
+    #[test]
+    fn test_same_symbols() {
+        let (first_code, first) = compile_with_debug_info(PROGRAM).unwrap();
+        let (second_code, second) = compile_with_debug_info(PROGRAM).unwrap();
+
+        assert_eq!(first_code, second_code);
+        assert_eq!(first.symbols, second.symbols);
+        assert_eq!(first.vars, second.vars);
+
+        // Symbol blocks are placed in the order of their names
+        let mut names = first.symbols.keys().collect::<Vec<_>>();
+        names.sort_by_key(|name| first.symbols[*name]);
+
+        assert_eq!(names, vec!["alpha", "beta", "middle", "nested", "zeta"]);
+    }
+}
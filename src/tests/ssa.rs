@@ -0,0 +1,188 @@
+#[cfg(test)]
+mod tests {
+    use compile::{build_module_at, link};
+    use error::ErrorKind;
+    use golden::{run_compiled, run_program};
+    use opt::OptLevel;
+    use ssa::*;
+    use tree::build_token_tree;
+
+    fn build(prog: &str) -> Program {
+        build_ssa(build_token_tree(prog.to_string()).unwrap()).unwrap()
+    }
+
+    fn run(program: &Program) -> Vec<(String, i32)> {
+        let (code, vars) = link(lower(program).unwrap()).unwrap();
+        run_compiled(&code, vars, b"").vars
+    }
+
+    #[test]
+    fn test_build_ssa() {
+        let program = build("
+            symbol double {
+                set x = * x 2;
+                return;
+            }
+            set x = 3;
+            cmp < x 5;
+            ifgoto skip;
+            call double;
+            set y = x;
+        ");
+
+        program.verify().unwrap();
+        assert_eq!(program.symbols.len(), 1);
+
+        // The conditional jump and the call each end a block
+        assert_eq!(program.main.blocks.len(), 3);
+        assert_eq!(program.main.blocks[0].terminator,
+                   Terminator::CondJump(Target::Name("skip".to_string()), BlockId(1)));
+        assert_eq!(program.main.blocks[1].terminator,
+                   Terminator::Call(Target::Name("double".to_string()), BlockId(2)));
+        assert_eq!(program.main.blocks[2].terminator, Terminator::End);
+
+        let expected = "\
+double:
+  block 0:
+    v0 = load x
+    v1 = const 2
+    v2 = mul v0 v1
+    store x v2
+    return
+";
+
+        assert_eq!(format!("{}", program.symbols[0]), expected);
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let error = |prog: &str| build_ssa(build_token_tree(prog.to_string()).unwrap()).unwrap_err().code();
+
+        assert_eq!(error("set -1 = 2;"), ErrorKind::InvalidAddress as usize);
+        assert_eq!(error("set a = @ + b 1;"), ErrorKind::AddressNameType as usize);
+        assert_eq!(error("ifgoto + a 1;"), ErrorKind::IfGotoAddressType as usize);
+        assert_eq!(error("call #a;"), ErrorKind::CallAddressType as usize);
+        assert_eq!(error("set a = > b c;"), ErrorKind::Other as usize);
+    }
+
+    #[test]
+    fn test_jump_then_branch() {
+        // The block ended by `ifgoto` or `call` after a jump comes before the block that follows
+        for stmt in &["ifgoto x;", "call x;"] {
+            let prog = format!("
+                symbol x {{
+                    return;
+                }}
+                goto x;
+                {}
+            ", stmt);
+
+            let program = build(&prog);
+            program.verify().unwrap();
+            assert_eq!(program.main.blocks.last().unwrap().terminator, Terminator::End);
+
+            build_module_at(&prog, OptLevel::O2).unwrap();
+        }
+    }
+
+    // Counts down from 5 with a phi for the counter, and stores the sum of the counts
+    fn countdown() -> Function {
+        let mut f = Function::new("main");
+        let entry = BlockId(0);
+        let header = f.add_block();
+        let body = f.add_block();
+        let exit = f.add_block();
+
+        let start = f.push(entry, Inst::Const(5));
+        let zero = f.push(entry, Inst::Const(0));
+        f.blocks[entry.0].terminator = Terminator::Jump(Target::Block(header));
+
+        let count = f.push(header, Inst::Phi(vec![]));
+        let sum = f.push(header, Inst::Phi(vec![]));
+        f.push(header, Inst::Compare(CmpOp::Equals, count, zero));
+        f.blocks[header.0].terminator = Terminator::CondJump(Target::Block(exit), body);
+
+        let one = f.push(body, Inst::Const(1));
+        let next_count = f.push(body, Inst::Binary(BinOp::Sub, count, one));
+        let next_sum = f.push(body, Inst::Binary(BinOp::Add, sum, count));
+        f.blocks[body.0].terminator = Terminator::Jump(Target::Block(header));
+
+        f.insts[count.0] = Inst::Phi(vec![(entry, start), (body, next_count)]);
+        f.insts[sum.0] = Inst::Phi(vec![(entry, zero), (body, next_sum)]);
+
+        f.push(exit, Inst::Store(Place::Var("sum".to_string()), sum));
+        f.blocks[exit.0].terminator = Terminator::End;
+
+        f
+    }
+
+    #[test]
+    fn test_verify() {
+        let f = countdown();
+        f.verify().unwrap();
+
+        assert_eq!(f.predecessors()[1], vec![BlockId(0), BlockId(2)]);
+        assert_eq!(f.dominators(), vec![Some(BlockId(0)), Some(BlockId(0)), Some(BlockId(1)), Some(BlockId(1))]);
+
+        // A phi must have a value for each predecessor
+        let mut bad = f.clone();
+        bad.insts[2] = Inst::Phi(vec![(BlockId(0), Value(0))]);
+        assert!(bad.verify().is_err());
+
+        // A value may only be used where its definition dominates
+        let mut bad = f.clone();
+        let late = bad.push(BlockId(2), Inst::Const(7));
+        bad.push(BlockId(3), Inst::Store(Place::Var("x".to_string()), late));
+        assert!(bad.verify().is_err());
+
+        // Jumps must go to blocks that exist
+        let mut bad = f;
+        bad.blocks[0].terminator = Terminator::Jump(Target::Block(BlockId(9)));
+        assert!(bad.verify().is_err());
+    }
+
+    #[test]
+    fn test_lower_loop() {
+        let program = Program {
+            main: countdown(),
+            symbols: vec![]
+        };
+
+        assert!(run(&program).contains(&("sum".to_string(), 15)));
+
+        // A function that runs off the end of a block other than the last one can't be lowered
+        let mut bad = countdown();
+        bad.blocks[1].terminator = Terminator::End;
+
+        let program = Program {
+            main: bad,
+            symbols: vec![]
+        };
+
+        assert_eq!(lower(&program).unwrap_err().code(), ErrorKind::Other as usize);
+    }
+
+    #[test]
+    fn test_lowered_program_matches() {
+        let prog = "
+            symbol inc {
+                set n = + n 1;
+                return;
+            }
+            set n = 4;
+            call inc;
+            set q = / * n 3 ~ n 2;
+            set p = @q;
+            set r = ^ #p 1;
+            set #p = + r 2;
+        ";
+
+        let (code, vars) = build_module_at(prog, OptLevel::O2).and_then(link).unwrap();
+        let mut lowered = run_compiled(&code, vars, b"").vars;
+        let mut expected = run_program(prog, b"").vars;
+
+        lowered.sort();
+        expected.sort();
+        assert_eq!(lowered, expected);
+    }
+}
@@ -0,0 +1,138 @@
+#[cfg(test)]
+mod tests {
+    use token::*;
+    use error::Span;
+
+    const CORPUS: &'static [&'static str] = &[
+        include_str!("programs/arithmetic.blk"),
+        include_str!("programs/branches.blk"),
+        include_str!("programs/crlf.blk"),
+        include_str!("programs/memory.blk"),
+        include_str!("programs/spacing.blk"),
+        include_str!("programs/symbols.blk"),
+        include_str!("programs/tags.blk"),
+        "",
+        "// only a comment",
+        "?var_addr 10 set x = 1;",
+        "set\tc = / b a;\r\nset é = 1; raw `1 2`; raw `unterminated"
+    ];
+
+    #[test]
+    fn test_trivia_blank_lines() {
+        let tokens = build_tokens_with_trivia("a;\n\n\nb;".to_string());
+
+        assert_eq!(tokens[1].trailing, vec![]);
+        assert_eq!(tokens[2].leading, vec![Trivia::Newline, Trivia::Newline, Trivia::Newline]);
+    }
+
+    #[test]
+    fn test_trivia_crlf() {
+        let tokens = build_tokens_with_trivia("set a = 1;\r\nreturn;\r\n".to_string());
+
+        // The carriage return stays on the line it ends
+        assert_eq!(tokens[4].trailing, vec![Trivia::Whitespace("\r".to_string())]);
+        assert_eq!(tokens[5].leading, vec![Trivia::Newline]);
+        assert_eq!(tokens[6].trailing, vec![Trivia::Whitespace("\r".to_string())]);
+        assert_eq!(tokens[7].leading, vec![Trivia::Newline]);
+    }
+
+    #[test]
+    fn test_trivia_unterminated_raw() {
+        let tokens = build_tokens_with_trivia("raw `1 2".to_string());
+
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].trailing, vec![Trivia::Whitespace(" ".to_string()), Trivia::Skipped("`1 2".to_string())]);
+        assert_eq!(tokens[1].token, Token::Null);
+    }
+
+    #[test]
+    fn test_trivia_matches_tokens() {
+        for prog in CORPUS {
+            let tokens = build_tokens_with_trivia(prog.to_string()).into_iter().map(|t| t.token);
+            let expected = build_tokens(prog.to_string()).into_iter().filter(|t| *t != Token::Null);
+
+            assert!(tokens.filter(|t| *t != Token::Null).eq(expected));
+
+            // The lexer covers every character with a lexeme, so nothing is lost
+            assert_eq!(&tokens_to_source(&build_tokens_with_trivia(prog.to_string())), prog);
+        }
+    }
+
+    #[test]
+    fn test_trivia_attachment() {
+        let tokens = build_tokens_with_trivia("set a = 1; // one\n\n// two\nreturn;".to_string());
+
+        let semicolon = &tokens[4];
+        assert_eq!(semicolon.text, ";");
+        assert_eq!(semicolon.trailing, vec![Trivia::Whitespace(" ".to_string()),
+                                            Trivia::Comment("// one".to_string())]);
+
+        let ret = &tokens[5];
+        assert_eq!(ret.text, "return");
+        assert_eq!(ret.leading, vec![Trivia::Newline, Trivia::Newline,
+                                     Trivia::Comment("// two".to_string()), Trivia::Newline]);
+    }
+
+    #[test]
+    fn test_every_word_is_a_token() {
+        let ident = |name: &str| Token::Identifier(name.to_string());
+
+        // The old lexer could drop a word that directly followed another word. It gave
+        // [Null, Tag, Identifier("var_addr"), Assign, Identifier("x"), AssignSymbol, Number(1), LineEnd, Null]
+        // for this, without the 10
+        assert_eq!(build_tokens("?var_addr 10 set x = 1;".to_string()),
+                   vec![Token::Null, Token::Tag, ident("var_addr"), Token::Number(10), Token::Assign, ident("x"),
+                        Token::AssignSymbol, Token::Number(1), Token::LineEnd, Token::Null]);
+
+        // It gave [Null, Assign, Identifier("c"), AssignSymbol, Divide, Identifier("b"), LineEnd, Null]
+        // for the first line, without the `a`, and [Null, Tag, Identifier("x"), Add, Identifier("a"), Null]
+        // for the second, with only one `a`
+        let tokens = build_tokens("set\tc = / b a;\r\n?x + a a".to_string());
+
+        assert_eq!(tokens, vec![Token::Null, Token::Assign, ident("c"), Token::AssignSymbol,
+                                Token::Divide, ident("b"), ident("a"), Token::LineEnd, Token::Tag,
+                                ident("x"), Token::Add, ident("a"), ident("a"), Token::Null]);
+    }
+
+    #[test]
+    fn test_lexemes() {
+        let source = "é>=1 `2 3` // c";
+        let lexemes = Lexer::new(source).collect::<Vec<_>>();
+
+        assert_eq!(lexemes, vec![(Lexeme::Word("é"), Span::new((1, 1), (1, 2))),
+                                 (Lexeme::Token(Token::GreaterEqual), Span::new((1, 2), (1, 4))),
+                                 (Lexeme::Word("1"), Span::new((1, 4), (1, 5))),
+                                 (Lexeme::Raw("2 3"), Span::new((1, 7), (1, 10))),
+                                 (Lexeme::Comment("// c"), Span::new((1, 12), (1, 16)))]);
+
+        // Words borrow from the source instead of allocating
+        match lexemes[0].0 {
+            Lexeme::Word(w) => assert_eq!(w.as_ptr(), source.as_ptr()),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_lexemes_with_trivia() {
+        let lexemes = Lexer::with_trivia("a `1` //c\r\n``").collect::<Vec<_>>();
+
+        assert_eq!(lexemes, vec![(Lexeme::Word("a"), Span::new((1, 1), (1, 2))),
+                                 (Lexeme::Whitespace(" "), Span::new((1, 2), (1, 3))),
+                                 (Lexeme::Skipped("`"), Span::new((1, 3), (1, 4))),
+                                 (Lexeme::Raw("1"), Span::new((1, 4), (1, 5))),
+                                 (Lexeme::Skipped("`"), Span::new((1, 5), (1, 6))),
+                                 (Lexeme::Whitespace(" "), Span::new((1, 6), (1, 7))),
+                                 (Lexeme::Comment("//c\r"), Span::new((1, 7), (1, 11))),
+                                 (Lexeme::Newline, Span::new((1, 11), (2, 1))),
+                                 (Lexeme::Skipped("``"), Span::new((2, 1), (2, 3)))]);
+    }
+
+    #[test]
+    fn test_large_input() {
+        let line = "set value = + value 1; // increment\n";
+        let source = line.repeat((1 << 20) / line.len());
+        let count = Lexer::new(&source).count();
+
+        assert_eq!(count, 8 * ((1 << 20) / line.len()));
+    }
+}
@@ -1,8 +1,10 @@
 // First stage in compilation.
 // Converts a given program taken as a string into a a tokenized form, for easier compilation.
 // This stage does not detect any errors, but may produce invalid sets of tokens from invalid input.
+// The lexer makes a single pass over the source, so its running time is linear in the input size.
 
 use utils::*;
+use error::Span;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Token {
@@ -25,225 +27,401 @@ pub enum Token {
     Other(String), Null
 }
 
-pub fn build_tokens(mut prog: String) -> Vec<Token> {
-    let mut tokens = vec![Token::Null];
-
-    let mut previous_chr = '\0';
-    let mut previous_chr2 = '\0';
-    let mut previous_word = String::new();
-    let mut word = String::new();
-    let mut raw = false;
-    let mut comment = false;
-    let mut previous_whitespace = false;
-
-    while !prog.is_empty() {
-        let mut token = Token::Null;
-        let chr = prog.chars().nth(0).unwrap();
-        let mut word_end = true;
-        let mut is_char = true;
-        let mut pop = false;
-        let mut special = false;
-
-        if raw {
-            word_end = false;
-            is_char = false;
-            special = true;
-
-            if chr != '`' {
-                word.push(chr);
-            }
+// Source text that carries no meaning for the compiler
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Trivia {
+    Whitespace(String),
+    Newline,
+    Comment(String),
+    // Text the lexer consumed without producing a token, such as the backticks around raw code
+    Skipped(String)
+}
+
+// A token along with its exact source text and the trivia around it
+// Trivia up to the end of the line belongs to the previous token, the rest to the next one
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TriviaToken {
+    pub token: Token,
+    pub span: Span,
+    pub text: String,
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>
+}
+
+pub const KEYWORDS: &'static [&'static str] = &[
+    "set", "cmp",
+    "symbol", "goto",
+    "ifgoto", "call",
+    "return", "raw"
+];
+
+pub const REGISTERS: &'static [(&'static str, Register)] = &[
+    ("$int1", Register::Int1),
+    ("$int2", Register::Int2),
+    ("$int3", Register::Int3),
+    ("$int4", Register::Int4),
+    ("$accum", Register::Accum),
+    ("$flag", Register::Flag),
+    ("$error", Register::Error),
+    ("$segf", Register::FlowSegment),
+    ("$segd", Register::DataSegment),
+    ("$pcounter", Register::PCounter)
+];
+
+pub fn register_name(reg: &Register) -> &'static str {
+    REGISTERS.iter().find(|r| r.1 == *reg).map(|r| r.0).unwrap()
+}
+
+pub fn build_tokens(prog: String) -> Vec<Token> {
+    build_tokens_spanned(prog).into_iter().map(|(t, _)| t).collect()
+}
+
+// Same as build_tokens, but also returns where in the source each token came from
+pub fn build_tokens_spanned(prog: String) -> Vec<(Token, Span)> {
+    build_tokens_with_comments(prog).0
+}
+
+// Same as build_tokens_spanned, but also returns the `//` comments that are normally discarded
+// Comment text includes the leading slashes
+pub fn build_tokens_with_comments(prog: String) -> (Vec<(Token, Span)>, Vec<(String, Span)>) {
+    let mut tokens = vec![(Token::Null, Span::new((1, 1), (1, 1)))];
+    let mut comments = Vec::new();
+    let mut lexer = Lexer::new(&prog);
+
+    for (lexeme, span) in &mut lexer {
+        match lexeme {
+            Lexeme::Comment(text) => comments.push((text.to_string(), span)),
+            lexeme => tokens.push((lexeme.to_token(), span))
         }
+    }
 
-        if comment {
-            if chr == '\n' {
-                comment = false;
-            } else {
-                special = true;
-            }
+    if !prog.is_empty() {
+        tokens.push((Token::Null, Span::new(lexer.location(), lexer.location())));
+    }
+
+    (tokens, comments)
+}
+
+// A piece of source text found by the lexer, borrowing from the source
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Lexeme<'a> {
+    // Operators, punctuation and keywords
+    Token(Token),
+    // Identifiers, numbers and registers
+    Word(&'a str),
+    // The code between the backticks of inline machine code
+    Raw(&'a str),
+    // Includes the leading slashes
+    Comment(&'a str),
+    // Only produced by Lexer::with_trivia, along with Newline and Skipped
+    // Never includes a newline
+    Whitespace(&'a str),
+    Newline,
+    // Text that produces no token, such as the backticks around raw code
+    Skipped(&'a str)
+}
+
+impl<'a> Lexeme<'a> {
+    pub fn to_token(&self) -> Token {
+        match *self {
+            Lexeme::Token(ref token) => token.clone(),
+            Lexeme::Word(word) => word_token(word),
+            Lexeme::Raw(code) | Lexeme::Comment(code) => Token::Identifier(code.to_string()),
+            Lexeme::Whitespace(..) | Lexeme::Newline | Lexeme::Skipped(..) => Token::Null
+        }
+    }
+
+    // Whether the lexeme carries no meaning for the compiler
+    pub fn is_trivia(&self) -> bool {
+        match *self {
+            Lexeme::Comment(..) | Lexeme::Whitespace(..) | Lexeme::Newline | Lexeme::Skipped(..) => true,
+            Lexeme::Token(..) | Lexeme::Word(..) | Lexeme::Raw(..) => false
+        }
+    }
+}
+
+fn word_token(word: &str) -> Token {
+    match word {
+        "set" => Token::Assign,
+        "cmp" => Token::Compare,
+        "symbol" => Token::Symbol,
+        "goto" => Token::Goto,
+        "ifgoto" => Token::IfGoto,
+        "call" => Token::Call,
+        "return" => Token::Return,
+        "raw" => Token::Raw,
+        _ => if let Ok(v) = word.parse::<i32>() {
+            Token::Number(v)
         } else {
-            if chr == '`' {
-                raw = !raw;
-                special = true;
+            match REGISTERS.iter().find(|r| r.0 == word) {
+                Some(r) => Token::Register(r.1.clone()),
+                None => Token::Identifier(word.to_string())
             }
         }
+    }
+}
 
-        if special {
-            if prog.len() > 1 {
-                prog = prog[1..].to_string();
-                continue;
-            } else {
-                tokens.push(Token::Null);
-                break;
-            }
+// Characters that form a token on their own, and so also end words
+fn operator(chr: char) -> Option<Token> {
+    Some(match chr {
+        '=' => Token::AssignSymbol,
+        ';' => Token::LineEnd,
+        '#' => Token::Dereference,
+        '@' => Token::Address,
+        '*' => Token::Multiply,
+        '/' => Token::Divide,
+        '+' => Token::Add,
+        '~' => Token::Subtract,
+        '!' => Token::Not,
+        '&' => Token::And,
+        '|' => Token::Or,
+        '^' => Token::Xor,
+        '>' => Token::Greater,
+        '<' => Token::Less,
+        '{' => Token::OpenBrace,
+        '}' => Token::CloseBrace,
+        '?' => Token::Tag,
+        _ => return None
+    })
+}
+
+// Splits source text into lexemes in a single pass
+// Words are separated by whitespace and operators, and `>=`, `<=` and `==` are single tokens when
+// written without a space between the characters
+pub struct Lexer<'a> {
+    source: &'a str,
+    // Byte offset of the next character
+    offset: usize,
+    line: usize,
+    column: usize,
+    // Whether whitespace and skipped text are returned instead of being passed over
+    trivia: bool,
+    raw: RawState
+}
+
+// Where the lexer is in a raw block when returning trivia, which splits it into the opening
+// backtick, the code and the closing backtick
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RawState {
+    Outside,
+    Code,
+    Closing
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Lexer<'a> {
+        Lexer {
+            source: source,
+            offset: 0,
+            line: 1,
+            column: 1,
+            trivia: false,
+            raw: RawState::Outside
         }
+    }
 
-        match chr {
-            '\n' | ' ' => is_char = false,
-            '=' => token = if let Some(t) = tokens.last() {
-                if !previous_whitespace {
-                    match *t {
-                        Token::Greater => {
-                            pop = true;
-                            Token::GreaterEqual
-                        },
-                        Token::Less => {
-                            pop = true;
-                            Token::LessEqual
-                        },
-                        Token::AssignSymbol => {
-                            pop = true;
-                            Token::Equals
-                        },
-                        _ => Token::AssignSymbol
-                    }
+    // Like new, but also returns whitespace, newlines and skipped text, so that the lexemes cover
+    // the whole source
+    pub fn with_trivia(source: &'a str) -> Lexer<'a> {
+        Lexer {
+            trivia: true,
+            ..Lexer::new(source)
+        }
+    }
+
+    // Line and column of the next character
+    pub fn location(&self) -> (usize, usize) {
+        (self.line, self.column)
+    }
+
+    // Byte offset of the next character
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.offset..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let chr = self.peek()?;
+        self.offset += chr.len_utf8();
+
+        if chr == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(chr)
+    }
+
+    // Consumes characters while `pred` holds, returning the text consumed
+    fn take_while<F: Fn(char) -> bool>(&mut self, pred: F) -> &'a str {
+        let start = self.offset;
+
+        while self.peek().is_some_and(&pred) {
+            self.bump();
+        }
+
+        &self.source[start..self.offset]
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = (Lexeme<'a>, Span);
+
+    fn next(&mut self) -> Option<(Lexeme<'a>, Span)> {
+        loop {
+            if !self.trivia {
+                self.take_while(char::is_whitespace);
+            }
+
+            let start = self.location();
+            let chr = self.peek()?;
+
+            let lexeme = if self.raw == RawState::Code {
+                self.raw = RawState::Closing;
+                Lexeme::Raw(self.take_while(|c| c != '`'))
+            } else if self.raw == RawState::Closing {
+                self.raw = RawState::Outside;
+                self.bump();
+                Lexeme::Skipped("`")
+            } else if chr == '\n' && self.trivia {
+                self.bump();
+                Lexeme::Newline
+            } else if chr.is_whitespace() && self.trivia {
+                Lexeme::Whitespace(self.take_while(|c| c.is_whitespace() && c != '\n'))
+            } else if chr == '`' && self.trivia {
+                let code = self.source[self.offset + 1..].find('`');
+
+                if code.is_some_and(|len| len > 0) {
+                    self.raw = RawState::Code;
+                    self.bump();
+                    Lexeme::Skipped("`")
                 } else {
-                    Token::AssignSymbol
-                }
-            } else { Token::AssignSymbol },
-            ';' => token = Token::LineEnd,
-            '#' => token = Token::Dereference,
-            '@' => token = Token::Address,
-            '*' => token = Token::Multiply,
-            '/' => token = {
-                if let Some(&Token::Divide) = tokens.last() {
-                    if !previous_whitespace {
-                        pop = true;
-                        comment = true;
-                        Token::Null
-                    } else {
-                        Token::Divide
+                    // Inline code without a closing backtick, or without any code, is skipped
+                    // whole, as it produces nothing
+                    let start = self.offset;
+                    let end = code.map(|len| self.offset + len + 2).unwrap_or(self.source.len());
+
+                    while self.offset < end {
+                        self.bump();
                     }
-                } else {
-                    Token::Divide
+
+                    Lexeme::Skipped(&self.source[start..end])
                 }
-            },
-            '+' => token = Token::Add,
-            '~' => token = Token::Subtract,
-            '!' => token = Token::Not,
-            '&' => token = Token::And,
-            '|' => token = Token::Or,
-            '^' => token = Token::Xor,
-            '>' => token = Token::Greater,
-            '<' => token = Token::Less,
-            '{' => token = Token::OpenBrace,
-            '}' => token = Token::CloseBrace,
-            '?' => token = Token::Tag,
-            _ => {
-                word_end = false;
-                is_char = false;
-                word.push(chr);
-            }
-        }
+            } else if chr == '`' {
+                self.bump();
 
-        if pop {
-            tokens.pop();
-        }
+                let code_start = self.location();
+                let code = self.take_while(|c| c != '`');
+                let code_end = self.location();
 
-        if word_end {
-            let words = ["set", "cmp",
-                         ">=", "<=",
-                         "symbol", "goto",
-                         "ifgoto", "call",
-                         "raw"];
-
-            let symbols = ['>', '<',
-                           '!', '&',
-                           '|', '^',
-                           '+', '~',
-                           '*', '/',
-                           '#', '@',
-                           '=', '?'];
-
-            if is_element(&previous_word, &words) {
-                if !word.is_empty() {
-                    tokens.push(Token::Identifier(word.clone()));
+                // Inline code without a closing backtick produces nothing
+                if self.bump().is_none() || code.is_empty() {
+                    continue;
                 }
-            } else if is_element(&previous_chr, &symbols) &&
-                      previous_chr2 != '?' &&
-                      (previous_chr, previous_chr2) != ('/', '/') {
-                // this magically lets you leave out semicolons in tags
-                previous_chr2 = previous_chr;
-
-                if word.len() > 0 {
-                    tokens.push(Token::Identifier(word.clone()));
+
+                return Some((Lexeme::Raw(code), Span::new(code_start, code_end)));
+            } else if self.source[self.offset..].starts_with("//") {
+                Lexeme::Comment(self.take_while(|c| c != '\n'))
+            } else if let Some(token) = operator(chr) {
+                self.bump();
+
+                match (token, self.peek()) {
+                    (Token::Greater, Some('=')) => { self.bump(); Lexeme::Token(Token::GreaterEqual) },
+                    (Token::Less, Some('=')) => { self.bump(); Lexeme::Token(Token::LessEqual) },
+                    (Token::AssignSymbol, Some('=')) => { self.bump(); Lexeme::Token(Token::Equals) },
+                    (token, _) => Lexeme::Token(token)
                 }
             } else {
-                if word == "==" {
-                    tokens.push(Token::Equals);
-                } else if word == ">=" {
-                    tokens.push(Token::GreaterEqual);
-                } else if word == "<=" {
-                    tokens.push(Token::LessEqual);
-                } else if word == "set" {
-                    tokens.push(Token::Assign);
-                } else if word == "cmp" {
-                    tokens.push(Token::Compare);
-                } else if word == "symbol" {
-                    tokens.push(Token::Symbol);
-                } else if word == "goto" {
-                    tokens.push(Token::Goto);
-                } else if word == "ifgoto" {
-                    tokens.push(Token::IfGoto);
-                } else if word == "call" {
-                    tokens.push(Token::Call);
-                } else if word == "return" {
-                    tokens.push(Token::Return);
-                } else if word == "raw" {
-                    tokens.push(Token::Raw);
-                } else {
-                    if !tokens.is_empty() {
-                        let last = tokens[tokens.len() - 1].clone();
-
-                        if let Token::Identifier(..) = last {} else {
-                            if !word.is_empty() {
-                                tokens.push(Token::Identifier(word.clone()));
-                            }
-                        }
-                    }
-                }
-            }
+                Lexeme::Word(self.take_while(|c| !c.is_whitespace() && c != '`' && operator(c).is_none()))
+            };
+
+            return Some((lexeme, Span::new(start, self.location())));
+        }
+    }
+}
+
+// Same as build_tokens_spanned, but keeps everything the lexer skips as trivia, so the source can
+// be reconstructed exactly with tokens_to_source
+// The last token is always a Null token marking the end of the input
+pub fn build_tokens_with_trivia(prog: String) -> Vec<TriviaToken> {
+    let mut lexer = Lexer::with_trivia(&prog);
+    let mut result: Vec<TriviaToken> = Vec::new();
+    let mut leading = Vec::new();
+    // Trivia before the first newline after a token belongs to that token
+    let mut trailing = false;
+
+    loop {
+        let start = lexer.offset();
 
-            previous_word = word;
-            word = String::new();
+        let (lexeme, span) = match lexer.next() {
+            Some(item) => item,
+            None => break
+        };
 
-            if token != Token::Null {
-                tokens.push(token);
+        let trivia = match lexeme {
+            Lexeme::Newline => Trivia::Newline,
+            Lexeme::Whitespace(text) => Trivia::Whitespace(text.to_string()),
+            Lexeme::Comment(text) => Trivia::Comment(text.to_string()),
+            Lexeme::Skipped(text) => Trivia::Skipped(text.to_string()),
+            lexeme => {
+                result.push(TriviaToken {
+                    token: lexeme.to_token(),
+                    span: span,
+                    text: prog[start..lexer.offset()].to_string(),
+                    leading: ::std::mem::take(&mut leading),
+                    trailing: Vec::new()
+                });
+
+                trailing = true;
+                continue;
             }
-        }
+        };
 
-        if is_char {
-            previous_chr = chr;
+        if trivia == Trivia::Newline {
+            trailing = false;
         }
 
-        previous_whitespace = chr.is_whitespace();
-
-        if prog.len() > 1 {
-            prog = prog[1..].to_string();
-        } else {
-            tokens.push(Token::Null);
-            break;
+        match result.last_mut() {
+            Some(last) if trailing => last.trailing.push(trivia),
+            _ => leading.push(trivia)
         }
     }
 
-    tokens.iter().map(|t| if let &Token::Identifier(ref ident) = t {
-        if let Ok(v) = ident.parse::<i32>() {
-            Token::Number(v)
-        } else {
-            match &ident as &str {
-                "$int1" => Token::Register(Register::Int1),
-                "$int2" => Token::Register(Register::Int2),
-                "$int3" => Token::Register(Register::Int3),
-                "$int4" => Token::Register(Register::Int4),
-                "$accum" => Token::Register(Register::Accum),
-                "$flag" => Token::Register(Register::Flag),
-                "$error" => Token::Register(Register::Error),
-                "$segf" => Token::Register(Register::FlowSegment),
-                "$segd" => Token::Register(Register::DataSegment),
-                "$pcounter" => Token::Register(Register::PCounter),
-                _ => t.clone()
+    let end = lexer.location();
+
+    result.push(TriviaToken {
+        token: Token::Null,
+        span: Span::new(end, end),
+        text: String::new(),
+        leading: leading,
+        trailing: Vec::new()
+    });
+
+    result
+}
+
+pub fn tokens_to_source(tokens: &[TriviaToken]) -> String {
+    fn push_trivia(trivia: &[Trivia], source: &mut String) {
+        for t in trivia {
+            match *t {
+                Trivia::Newline => source.push('\n'),
+                Trivia::Whitespace(ref s) | Trivia::Comment(ref s) | Trivia::Skipped(ref s) => source.push_str(s)
             }
         }
-    } else {
-        t.clone()
-    }).collect()
+    }
+
+    let mut source = String::new();
+
+    for token in tokens {
+        push_trivia(&token.leading, &mut source);
+        source.push_str(&token.text);
+        push_trivia(&token.trailing, &mut source);
+    }
+
+    source
 }
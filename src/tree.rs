@@ -7,12 +7,16 @@ use token::*;
 use error::BlocksError;
 use error::ErrorKind::*;
 
-const IGNORED_TOKENS: [Token; 3] = [
+pub const IGNORED_TOKENS: [Token; 3] = [
     Token::AssignSymbol,
     Token::LineEnd,
     Token::Null
 ];
 
+// Nodes deeper than this are rejected, because the later stages recurse over the tree and would
+// overflow the stack
+pub const MAX_DEPTH: usize = 40;
+
 pub type Boxed = Box<TokenWrapper>;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -45,35 +49,45 @@ pub enum Tree {
 }
 
 pub fn build_token_tree<'a>(prog: String) -> Result<Tree, BlocksError> {
+    let (tokens, spans): (Vec<_>, Vec<_>) = build_tokens_spanned(prog).into_iter().unzip();
+    let mut current = 0;
+
+    build_tree(&tokens, &mut current).map_err(|e| e.with_span(spans[current]))
+}
+
+// Builds the tree from a list of tokens
+// The index of the token being processed is stored in `current`, so errors can be located
+fn build_tree(tokens: &[Token], current: &mut usize) -> Result<Tree, BlocksError> {
     let mut tree = Vec::new();
     let mut stack = Stack::new();
-    let tokens = build_tokens(prog);
 
     let mut block_data = Stack::new();
     let mut block = false;
 
-    for token in tokens.iter().rev() {
+    for (i, token) in tokens.iter().enumerate().rev() {
+        *current = i;
+
         if is_element_token(token, &IGNORED_TOKENS) {
             continue;
         }
 
         if block {
-            block_data.push(TokenWrapper::Token(token.clone()));
+            block_data.push(TokenWrapper::Token(token.clone()), 1);
         } else {
-            stack.push(TokenWrapper::Token(token.clone()));
+            stack.push(TokenWrapper::Token(token.clone()), 1);
         }
 
         let inputs = get_input_count(token);
-        let mut node_data;
+        let (mut node_data, depths);
 
         if block {
-            node_data = if let Some(v) = block_data.pop(inputs) {
+            (node_data, depths) = if let Some(v) = block_data.pop(inputs) {
                 v
             } else {
                 return Err(BlocksError::new(NotEnoughArgs, token.clone()));
             }
         } else {
-            node_data = if let Some(v) = stack.pop(inputs) {
+            (node_data, depths) = if let Some(v) = stack.pop(inputs) {
                 v
             } else {
                 return Err(BlocksError::new(NotEnoughArgs, token.clone()));
@@ -88,179 +102,188 @@ pub fn build_token_tree<'a>(prog: String) -> Result<Tree, BlocksError> {
             }
         }
 
+        // Index 0 is the operator itself
+        let children = depths.iter().skip(1).cloned().max().unwrap_or(0);
+
+        if children >= MAX_DEPTH {
+            return Err(BlocksError::new(TooDeep, token.clone()));
+        }
+
+        let depth = children + 1;
+
         match node_data[0] {
             TokenWrapper::Token(Token::Assign) => {
                 let new = TokenWrapper::Tree(
                     Tree::Assign(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Dereference) => {
-                let new = TokenWrapper::Tree(Tree::Dereference(Box::new(node_data[1].clone())));
+                let new = TokenWrapper::Tree(Tree::Dereference(arg(&node_data, 1, token)?));
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Address) => {
-                let new = TokenWrapper::Tree(Tree::Address(Box::new(node_data[1].clone())));
+                let new = TokenWrapper::Tree(Tree::Address(arg(&node_data, 1, token)?));
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Goto) => {
-                let new = TokenWrapper::Tree(Tree::Goto(Box::new(node_data[1].clone())));
+                let new = TokenWrapper::Tree(Tree::Goto(arg(&node_data, 1, token)?));
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::IfGoto) => {
-                let new = TokenWrapper::Tree(Tree::IfGoto(Box::new(node_data[1].clone())));
+                let new = TokenWrapper::Tree(Tree::IfGoto(arg(&node_data, 1, token)?));
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Call) => {
-                let new = TokenWrapper::Tree(Tree::Call(Box::new(node_data[1].clone())));
+                let new = TokenWrapper::Tree(Tree::Call(arg(&node_data, 1, token)?));
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Return) => {
                 let new = TokenWrapper::Tree(Tree::Return);
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Multiply) => {
                 let new = TokenWrapper::Tree(
                     Tree::Multiply(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Divide) => {
                 let new = TokenWrapper::Tree(
                     Tree::Divide(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Add) => {
                 let new = TokenWrapper::Tree(
                     Tree::Add(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Subtract) => {
                 let new = TokenWrapper::Tree(
                     Tree::Subtract(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Compare) => {
-                let new = TokenWrapper::Tree(Tree::Compare(Box::new(node_data[1].clone())));
+                let new = TokenWrapper::Tree(Tree::Compare(arg(&node_data, 1, token)?));
 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Greater) => {
                 let new = TokenWrapper::Tree(
                     Tree::Greater(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Equals) => {
                 let new = TokenWrapper::Tree(
                     Tree::Equals(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Less) => {
                 let new = TokenWrapper::Tree(
                     Tree::Less(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::GreaterEqual) => {
                 let new = TokenWrapper::Tree(
                     Tree::GreaterEqual(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::LessEqual) => {
                 let new = TokenWrapper::Tree(
                     Tree::LessEqual(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Not) => {
-                let new = TokenWrapper::Tree(Tree::Not(Box::new(node_data[1].clone())));
+                let new = TokenWrapper::Tree(Tree::Not(arg(&node_data, 1, token)?));
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::And) => {
                 let new = TokenWrapper::Tree(
                     Tree::And(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Or) => {
                 let new = TokenWrapper::Tree(
                     Tree::Or(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Xor) => {
                 let new = TokenWrapper::Tree(
                     Tree::Xor(
-                        Box::new(node_data[1].clone()),
-                        Box::new(node_data[2].clone())
+                        arg(&node_data, 1, token)?,
+                        arg(&node_data, 2, token)?
                     )
                 );
 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::Raw) => {
-                let temp = if let TokenWrapper::Token(Token::Identifier(string)) = node_data[1].clone() {
+                let temp = if let TokenWrapper::Token(Token::Identifier(string)) = *arg(&node_data, 1, token)? {
                     string.split_whitespace().map(|i| match i.parse::<i32>() {
                         Ok(v) => Ok(v),
                         Err(..) => Err(BlocksError::new(InvalidRaw, Token::Identifier(string.clone())))
@@ -284,16 +307,16 @@ pub fn build_token_tree<'a>(prog: String) -> Result<Tree, BlocksError> {
                     )
                 );
 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, 1) } else { stack.push(new, 1) }
             }
             TokenWrapper::Token(Token::Tag) => {
-                let a = if let TokenWrapper::Token(ref a) = node_data[1].clone() {
+                let a = if let TokenWrapper::Token(ref a) = *arg(&node_data, 1, token)? {
                     a.clone()
                 } else {
                     return Err(BlocksError::new(TagNameType, Token::Null));
                 };
 
-                let b = if let TokenWrapper::Token(ref b) = node_data[2].clone() {
+                let b = if let TokenWrapper::Token(ref b) = *arg(&node_data, 2, token)? {
                     b.clone()
                 } else {
                     return Err(BlocksError::new(TagValueType, Token::Null));
@@ -313,28 +336,30 @@ pub fn build_token_tree<'a>(prog: String) -> Result<Tree, BlocksError> {
 
                 let new = TokenWrapper::Tree(Tree::Tag(name, value));
 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, 1) } else { stack.push(new, 1) }
             },
             TokenWrapper::Token(Token::Symbol) => {
-                let token = if let TokenWrapper::Token(ref t) = node_data[1] {
+                let name_token = if let TokenWrapper::Token(ref t) = *arg(&node_data, 1, token)? {
                     t.clone()
                 } else {
                     return Err(BlocksError::new(SymbolNameType, Token::Null));
                 };
 
-                let name = if let Token::Identifier(ident) = token {
+                let name = if let Token::Identifier(ident) = name_token {
                     ident
                 } else {
-                    return Err(BlocksError::new(SymbolNameType, token));
+                    return Err(BlocksError::new(SymbolNameType, name_token));
                 };
 
-                let new = TokenWrapper::Tree(Tree::Symbol(name, Box::new(node_data[2].clone())));
+                let new = TokenWrapper::Tree(Tree::Symbol(name, arg(&node_data, 2, token)?));
                 
-                if block { block_data.push(new) } else { stack.push(new) }
+                if block { block_data.push(new, depth) } else { stack.push(new, depth) }
             },
             TokenWrapper::Token(Token::OpenBrace) => {
                 block_data.pop(1);
-                stack.push(TokenWrapper::Tree(Tree::Block(block_data.data.iter().cloned().rev().collect())));
+
+                let depth = 1 + block_data.depths.iter().cloned().max().unwrap_or(0);
+                stack.push(TokenWrapper::Tree(Tree::Block(block_data.data.iter().cloned().rev().collect())), depth);
                 block_data = Stack::new();
 
                 if !block {
@@ -363,3 +388,34 @@ pub fn build_token_tree<'a>(prog: String) -> Result<Tree, BlocksError> {
 
     Ok(Tree::Block(tree))
 }
+
+// Gets an operand of a node, where index 0 is the operator itself
+fn arg(node_data: &[TokenWrapper], index: usize, token: &Token) -> Result<Boxed, BlocksError> {
+    node_data.get(index)
+             .map(|a| Box::new(a.clone()))
+             .ok_or(BlocksError::new(NotEnoughArgs, token.clone()))
+}
+
+// Counts the tokens a tree was built from, not including ignored tokens such as semicolons
+pub fn count_tokens(tree: &TokenWrapper) -> usize {
+    let tree = match *tree {
+        TokenWrapper::Token(..) => return 1,
+        TokenWrapper::Tree(ref tree) => tree
+    };
+
+    match *tree {
+        Tree::Block(ref stmts) => stmts.iter().map(count_tokens).sum::<usize>() + 2,
+        Tree::Return => 1,
+        Tree::Raw(..) => 2,
+        Tree::Tag(..) => 3,
+        Tree::Symbol(_, ref body) => count_tokens(body) + 2,
+        Tree::Dereference(ref item) | Tree::Address(ref item) | Tree::Goto(ref item) |
+        Tree::IfGoto(ref item) | Tree::Call(ref item) | Tree::Compare(ref item) |
+        Tree::Not(ref item) => count_tokens(item) + 1,
+        Tree::Assign(ref lhs, ref rhs) | Tree::Multiply(ref lhs, ref rhs) | Tree::Divide(ref lhs, ref rhs) |
+        Tree::Add(ref lhs, ref rhs) | Tree::Subtract(ref lhs, ref rhs) | Tree::Greater(ref lhs, ref rhs) |
+        Tree::Less(ref lhs, ref rhs) | Tree::GreaterEqual(ref lhs, ref rhs) |
+        Tree::LessEqual(ref lhs, ref rhs) | Tree::Equals(ref lhs, ref rhs) | Tree::And(ref lhs, ref rhs) |
+        Tree::Or(ref lhs, ref rhs) | Tree::Xor(ref lhs, ref rhs) => count_tokens(lhs) + count_tokens(rhs) + 1
+    }
+}
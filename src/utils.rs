@@ -76,36 +76,51 @@ impl Address {
     pub fn new_temp(id: i32) -> Address {
         Address::Variable(format!("__temp_{}__", id))
     }
+
+    pub fn is_temp(&self) -> bool {
+        match *self {
+            Address::Variable(ref name) => name.starts_with("__temp_"),
+            Address::Static(_) => false
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Stack {
-    pub data: Vec<TokenWrapper>
+    pub data: Vec<TokenWrapper>,
+    // The depth of each item, so nodes built from them don't have to be walked to find theirs
+    pub depths: Vec<usize>
 }
 
 impl Stack {
     pub fn new() -> Stack {
         Stack {
-            data: Vec::new()
+            data: Vec::new(),
+            depths: Vec::new()
         }
     }
 
-    pub fn push(&mut self, item: TokenWrapper) {
+    pub fn push(&mut self, item: TokenWrapper, depth: usize) {
         self.data.push(item);
+        self.depths.push(depth);
     }
 
-    pub fn pop(&mut self, count: usize) -> Option<Vec<TokenWrapper>> {
+    // Pops the items along with their depths
+    pub fn pop(&mut self, count: usize) -> Option<(Vec<TokenWrapper>, Vec<usize>)> {
         let mut result = Vec::new();
+        let mut depths = Vec::new();
 
         for _ in 0..count {
             result.push(if let Some(v) = self.data.pop() {
                 v
             } else {
                 return None;
-            })
+            });
+
+            depths.extend(self.depths.pop());
         }
 
-        Some(result)
+        Some((result, depths))
     }
 }
 
@@ -193,7 +208,8 @@ pub fn insert_operator(lhs: TokenWrapper, rhs: TokenWrapper,
             register_store(lhs, Register::Int1, result, 1)?;
         },
         (true, true) => {
-            register_store(lhs, Register::Int2, result, temp_id + 1)?;
+            // The left operand is loaded after the right one is computed, which would overwrite it
+            register_store(lhs, Register::Int1, result, temp_id + 1)?;
 
             let last = if let Some(v) = result.pop() {
                 v
@@ -201,7 +217,7 @@ pub fn insert_operator(lhs: TokenWrapper, rhs: TokenWrapper,
                 return Err(BlocksError::new(ErrorKind::Other, Token::Other("this might not need to be an error".to_string())));
             };
 
-            register_store(rhs, Register::Int1, result, temp_id + 2)?;
+            register_store(rhs, Register::Int2, result, temp_id + 2)?;
 
             result.push(last);
         }